@@ -1,26 +1,54 @@
 use colored::Colorize;
 use std::fmt;
 
-use crate::lib::{parser::statements::ReturnVal, position::Span};
-pub(crate) type LoxResult<T> = Result<T, LoxError>;
+use crate::{interpreter::LoxValue, parser::statements::ReturnVal, position::Span, sync::Rc};
+pub type LoxResult<T> = Result<T, LoxError>;
+
+/// Source text of a program, along with the name its errors are reported under.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Source<'a> {
+    pub name: &'a str,
+    pub text: &'a str,
+}
 
 /// Error that wraps the inner errors
 /// in order to be able to print the line
 /// at which the error was generated
 pub(crate) struct InterpreterError<'a> {
     err: LoxError,
+    level: ErrorLevel,
     src_file: Vec<&'a str>,
+    /// Name of the source file, shown before the position of the error
+    file_name: &'a str,
 }
 
 impl<'a> InterpreterError<'a> {
     pub fn from(err: LoxError, src_file: &'a str) -> Self {
         Self {
             err: if let LoxError::Return(e) = err {
-                InnerError::from(e).into()
+                InnerError::from(*e).into()
             } else {
                 err
             },
+            level: ErrorLevel::Error,
             src_file: src_file.lines().collect(),
+            file_name: "<input>",
+        }
+    }
+
+    /// Same as [`InterpreterError::from`], naming the source it comes from.
+    pub fn in_source(err: LoxError, src: Source<'a>) -> Self {
+        Self {
+            file_name: src.name,
+            ..Self::from(err, src.text)
+        }
+    }
+
+    /// Same as [`InterpreterError::in_source`], but reported as a warning.
+    pub fn warning(err: LoxError, src: Source<'a>) -> Self {
+        Self {
+            level: ErrorLevel::Warning,
+            ..Self::in_source(err, src)
         }
     }
 }
@@ -30,14 +58,8 @@ impl<'a> fmt::Display for InterpreterError<'a> {
         use LoxError::*;
         match &self.err {
             Inner(e) => {
-                let line = fmt_line_error(
-                    e.pos,
-                    self.src_file
-                        .get(e.pos.start().line_number() as usize - 1)
-                        // Unwrapping here is safe because we know this line exists
-                        .unwrap(),
-                );
-                write!(f, "{} {}\n{}", ErrorLevel::Error, e, line)
+                let line = fmt_line_error(e.pos, self.file_name, &self.src_file);
+                write!(f, "{} {}\n{}", self.level, e, line)
             }
             e => write!(f, "{}", e),
         }
@@ -47,18 +69,20 @@ impl<'a> fmt::Display for InterpreterError<'a> {
 #[derive(Debug, Clone)]
 pub enum ErrorLevel {
     Error,
+    Warning,
 }
 
 impl fmt::Display for ErrorLevel {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match &self {
             ErrorLevel::Error => write!(f, "{}:", "error".red().bold()),
+            ErrorLevel::Warning => write!(f, "{}:", "warning".yellow().bold()),
         }
     }
 }
 
 #[derive(Debug)]
-pub(crate) struct InnerError {
+pub struct InnerError {
     pos: Span,
     message: String,
 }
@@ -88,9 +112,15 @@ impl std::fmt::Display for InnerError {
 
 /// Error wrapper for irrecoverable errors
 #[derive(Debug)]
-pub(crate) enum LoxError {
-    /// Return value wrapped in an error to be catched during the unwinding
-    Return(ReturnVal),
+pub enum LoxError {
+    /// Return value wrapped in an error to be catched during the unwinding, boxed as it's
+    /// far larger than the other variants
+    Return(Box<ReturnVal>),
+    /// `break` wrapped in an error to be catched by the labeled statement it targets, or by
+    /// the innermost loop without a label, along with the value it exits a `loop` with
+    Break(Option<String>, Rc<LoxValue>),
+    /// `continue` wrapped in an error to be catched by the innermost loop
+    Continue,
     /// Inner interpreter errors (lexing, parsing and evaluating stages)
     Inner(InnerError),
     /// Errors thrown by any I/O function
@@ -113,6 +143,11 @@ impl std::fmt::Display for LoxError {
             LoxError::Return(_) => {
                 write!(f, "attempt to return out of a function block",)
             }
+            LoxError::Break(Some(label), _) => {
+                write!(f, "attempt to break out of unknown label `{}`", label)
+            }
+            LoxError::Break(None, _) => write!(f, "attempt to break outside of a loop"),
+            LoxError::Continue => write!(f, "attempt to continue outside of a loop"),
             LoxError::Generic(e) => write!(f, "{}", e),
             LoxError::Io(e) => write!(f, "{} {}", ErrorLevel::Error, e),
             LoxError::ParseInt(e) => write!(f, "{}", e),
@@ -152,13 +187,13 @@ impl From<std::num::ParseFloatError> for LoxError {
 
 impl From<ReturnVal> for LoxError {
     fn from(ret: ReturnVal) -> Self {
-        Self::Return(ret)
+        Self::Return(Box::new(ret))
     }
 }
 
 /// Repeats a char `n` times.
 fn pad(n: usize, ch: char) -> String {
-    std::iter::repeat(ch).take(n).collect()
+    std::iter::repeat_n(ch, n).collect()
 }
 
 /// Helper for pretty-printing errors in a line
@@ -167,30 +202,114 @@ fn pad(n: usize, ch: char) -> String {
 /// 1 |   ok$(err);
 ///   |     ^
 /// ```
-fn fmt_line_error(span: Span, text: &str) -> String {
+///
+/// Spans that cross lines underline every line they cover, from the start column to the end
+/// of the first line, then each following line up to the end column.
+fn fmt_line_error(span: Span, file_name: &str, lines: &[&str]) -> String {
     let sep = "|".blue();
     let (start, end) = (span.start(), span.end());
-    let here = format!(
-        "{}{}",
-        pad(start.column_number().saturating_sub(1) as usize, ' '),
-        pad(
-            end.column_number()
-                .saturating_add(1)
-                .saturating_sub(start.column_number()) as usize,
-            '^'
-        )
-        .red()
-    );
-    format!(
-        " {} {}:{}\n{space} {sep}\n{} {sep}\t{}\n{space} {sep}\t{here}",
+    let width = end.line_number().to_string().len();
+    let space = pad(width, ' ');
+
+    let mut out = format!(
+        " {} {}:{}\n{space} {sep}",
         "-->".blue(),
-        // Unwrapping is safe here bc we always set this variable when the program runs
-        std::env::var("LOX_SRC_FILE").unwrap(),
+        file_name,
         start,
-        start.line_number().to_string().blue(),
-        text.trim(),
+        space = space,
         sep = sep,
-        here = here.blue(),
-        space = pad(start.line_number().to_string().len(), ' '),
-    )
+    );
+    for line_number in start.line_number()..=end.line_number() {
+        let text = match lines.get(line_number as usize - 1) {
+            Some(text) => text.trim_end(),
+            None => break,
+        };
+        let from = if line_number == start.line_number() {
+            start.column_number() as usize - 1
+        } else {
+            text.len() - text.trim_start().len()
+        };
+        let to = if line_number == end.line_number() {
+            end.column_number() as usize
+        } else {
+            text.chars().count()
+        };
+        // Keep tabs so the carets line up with the source text
+        let indent: String = text
+            .chars()
+            .take(from)
+            .map(|c| if c == '\t' { c } else { ' ' })
+            .collect();
+        let here = pad(to.saturating_sub(from).max(1), '^').red();
+        out += &format!(
+            "\n{:>width$} {sep}\t{}\n{space} {sep}\t{}{}",
+            line_number.to_string().blue(),
+            text,
+            indent,
+            here,
+            width = width,
+            sep = sep,
+            space = space,
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::position::Position;
+
+    #[test]
+    fn renders_every_line_of_a_multi_line_span() {
+        colored::control::set_override(false);
+        let src = "let x = 1 +\n    \"a\" - 2;\n";
+        let span = Span::new(Position::new(1, 9), Position::new(2, 11));
+        let rendered = fmt_line_error(span, "<input>", &src.lines().collect::<Vec<_>>());
+        let expected = [
+            " --> <input>:1:9",
+            "  |",
+            "1 |\tlet x = 1 +",
+            "  |\t        ^^^",
+            "2 |\t    \"a\" - 2;",
+            "  |\t    ^^^^^^^",
+        ];
+        assert_eq!(rendered, expected.join("\n"));
+    }
+
+    #[test]
+    fn errors_name_their_own_source() {
+        colored::control::set_override(false);
+        let span = Span::new(Position::new(1, 7), Position::new(1, 7));
+        let handles: Vec<_> = ["a.lox", "b.lox"]
+            .iter()
+            .map(|name| {
+                std::thread::spawn(move || {
+                    let err = LoxError::from(InnerError::new(span, "oops"));
+                    let src = Source {
+                        name,
+                        text: "print x;",
+                    };
+                    InterpreterError::in_source(err, src).to_string()
+                })
+            })
+            .collect();
+        let rendered: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert!(rendered[0].contains(" --> a.lox:1:7\n"), "{}", rendered[0]);
+        assert!(rendered[1].contains(" --> b.lox:1:7\n"), "{}", rendered[1]);
+        let rendered = InterpreterError::from(LoxError::from(InnerError::new(span, "oops")), "x");
+        assert!(rendered.to_string().contains(" --> <input>:1:7\n"));
+    }
+
+    #[test]
+    fn underlines_the_whole_token() {
+        colored::control::set_override(false);
+        let span = Span::new(Position::new(1, 8), Position::new(1, 13));
+        let rendered = fmt_line_error(span, "<input>", &["\tprint foobar;"]);
+        assert!(
+            rendered.ends_with("1 |\t\tprint foobar;\n  |\t\t      ^^^^^^"),
+            "{}",
+            rendered
+        );
+    }
 }
@@ -0,0 +1,219 @@
+//! Source formatter behind `--fmt`.
+//!
+//! It works on the tokens rather than on the syntax tree, which has no room for comments. The
+//! line breaks of the source are kept, every line is indented by the brackets still open at its
+//! start, and the tokens on a line are spaced evenly. The comments kept by the lexer are put
+//! back next to the tokens they're attached to.
+use crate::{
+    lexer::{Comment, Placement},
+    position::{Position, Span},
+    token::{Keyword, Punctuator, Token, TokenKind},
+};
+
+const INDENT: &str = "    ";
+
+/// Formats `src`, given its tokens and the comments found between them.
+pub(crate) fn format(src: &str, tokens: &[Token], comments: &[Comment]) -> String {
+    let mut formatter = Formatter {
+        lines: src.split(['\n', '\r']).collect(),
+        out: String::new(),
+        depth: 0,
+        prev: None,
+    };
+    let attached = |idx: usize, placement: Placement| {
+        comments
+            .iter()
+            .filter(move |c| c.token == idx && c.placement == placement)
+    };
+
+    for (idx, tk) in tokens.iter().enumerate() {
+        attached(idx, Placement::Leading).for_each(|c| formatter.comment(c));
+        formatter.token(tk);
+        attached(idx, Placement::Trailing).for_each(|c| formatter.comment(c));
+    }
+    attached(tokens.len(), Placement::Leading).for_each(|c| formatter.comment(c));
+
+    if !formatter.out.is_empty() {
+        formatter.out.push('\n');
+    }
+    formatter.out
+}
+
+/// What was written last, to tell how to separate it from what comes next.
+#[derive(Clone, Copy)]
+enum Written<'t> {
+    Token(&'t Token, Option<&'t Token>),
+    Comment,
+}
+
+struct Formatter<'s, 't> {
+    /// Lines of the source, split the same way the lexer counts them
+    lines: Vec<&'s str>,
+    out: String,
+    /// Brackets opened and not closed yet
+    depth: usize,
+    /// What was written last, along with the line it ends on
+    prev: Option<(Written<'t>, u32)>,
+}
+
+impl<'s, 't> Formatter<'s, 't> {
+    fn token(&mut self, tk: &'t Token) {
+        use Punctuator::*;
+        let closes = matches!(
+            tk.kind(),
+            TokenKind::Punctuator(CloseParen | CloseBlock | CloseBracket)
+        );
+        if closes {
+            self.depth = self.depth.saturating_sub(1);
+        }
+
+        let span = *tk.span();
+        let before = match self.prev {
+            Some((Written::Token(prev, before), _)) => Some((prev, before)),
+            _ => None,
+        };
+        if !self.starts_line(span) {
+            let spaced = match before {
+                Some((prev, before)) => spaced(before, prev, tk, self.gap(*prev.span(), span)),
+                None => true,
+            };
+            if spaced {
+                self.out.push(' ');
+            }
+        }
+        self.out += &self.text(span);
+
+        if matches!(
+            tk.kind(),
+            TokenKind::Punctuator(OpenParen | OpenBlock | OpenBracket)
+        ) {
+            self.depth += 1;
+        }
+        let prev = before.map(|(prev, _)| prev);
+        self.prev = Some((Written::Token(tk, prev), span.end().line_number()));
+    }
+
+    fn comment(&mut self, comment: &Comment) {
+        if !self.starts_line(comment.span) {
+            self.out.push(' ');
+        }
+        self.out += &comment.text;
+        self.prev = Some((Written::Comment, comment.span.end().line_number()));
+    }
+
+    /// Starts a new line if `span` isn't on the line of what was written last, keeping a
+    /// single blank line if there were any in between. Returns whether it did.
+    fn starts_line(&mut self, span: Span) -> bool {
+        let line = span.start().line_number();
+        match self.prev {
+            Some((_, prev)) if prev == line => return false,
+            Some((_, prev)) => {
+                self.out.push('\n');
+                if line > prev + 1 {
+                    self.out.push('\n');
+                }
+            }
+            None => (),
+        }
+        self.out += &INDENT.repeat(self.depth);
+        true
+    }
+
+    /// Whether there's anything between two spans of the same line.
+    fn gap(&self, prev: Span, next: Span) -> bool {
+        next.start().column_number() > prev.end().column_number() + 1
+    }
+
+    /// The source text of `span`, which spans several lines for block comments and strings.
+    fn text(&self, span: Span) -> String {
+        let char_at = |pos: Position| pos.column_number() as usize - 1;
+        let (start, end) = (span.start(), span.end());
+        let line = |n: u32| self.lines[n as usize - 1];
+        if start.line_number() == end.line_number() {
+            return slice(line(start.line_number()), char_at(start), char_at(end) + 1);
+        }
+        let mut text = slice(line(start.line_number()), char_at(start), usize::MAX);
+        for n in start.line_number() + 1..end.line_number() {
+            text.push('\n');
+            text += line(n);
+        }
+        text.push('\n');
+        text + &slice(line(end.line_number()), 0, char_at(end) + 1)
+    }
+}
+
+/// The chars of `line` from `start` up to, but not including, `end`.
+fn slice(line: &str, start: usize, end: usize) -> String {
+    line.chars().skip(start).take(end - start).collect()
+}
+
+/// Whether `next` is separated from `prev` by a space, `before` being the token before `prev`.
+///
+/// Where spacing changes the meaning it's kept as written: `gap` tells if there was any.
+fn spaced(before: Option<&Token>, prev: &Token, next: &Token, gap: bool) -> bool {
+    use Punctuator::*;
+    let punctuator = |tk: &Token| match tk.kind() {
+        TokenKind::Punctuator(p) => Some(*p),
+        _ => None,
+    };
+    match (punctuator(prev), punctuator(next)) {
+        (_, Some(Semicolon | Comma | CloseParen | CloseBracket | Dot | DotDot)) => false,
+        (Some(OpenParen | OpenBracket | Dot | DotDot | Ellipsis), _) => false,
+        (Some(Comma | Semicolon | Colon), _) => true,
+        (Some(Sub | Not), _) if !before.is_some_and(ends_operand) => false,
+        (_, Some(OpenParen | OpenBracket)) => !ends_operand(prev),
+        (_, Some(Colon)) => gap,
+        _ if matches!(prev.kind(), TokenKind::Operator(_)) => false,
+        _ => true,
+    }
+}
+
+/// Whether `tk` can be the last token of an operand, so an operator after it is binary and a
+/// bracket after it calls or indexes it.
+fn ends_operand(tk: &Token) -> bool {
+    match tk.kind() {
+        TokenKind::Identifier(_)
+        | TokenKind::StringLiteral(_)
+        | TokenKind::NumericLiteral(_)
+        | TokenKind::BooleanLiteral(_) => true,
+        TokenKind::Keyword(kw) => matches!(kw, Keyword::Nil | Keyword::This | Keyword::Super),
+        TokenKind::Punctuator(p) => matches!(p, Punctuator::CloseParen | Punctuator::CloseBracket),
+        TokenKind::Operator(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lexer::Lexer;
+
+    fn fmt(src: &str) -> String {
+        let (tokens, comments) = Lexer::new(src)
+            .keep_comments()
+            .scan_tokens_with_comments()
+            .unwrap();
+        format(src, &tokens, &comments)
+    }
+
+    #[test]
+    fn keeps_comments_on_their_lines() {
+        let src = "// header\nlet x = 1;   // note\nfn f(a) {\n// leading\nreturn a; /* why */\n}\n// footer\n";
+        let expected = "// header\nlet x = 1; // note\nfn f(a) {\n    // leading\n    return a; /* why */\n}\n// footer\n";
+        assert_eq!(fmt(src), expected);
+    }
+
+    #[test]
+    fn indents_blocks_and_spaces_tokens() {
+        let src = "fn  add(a,b){\nif (a>b) {return -a ;}\n\n\n  return [a , b][0]+add(a-1, !b);\n}\nprint  add (1,2) ;";
+        let expected = "fn add(a, b) {\n    if (a > b) { return -a; }\n\n    return [a, b][0] + add(a - 1, !b);\n}\nprint add(1, 2);\n";
+        assert_eq!(fmt(src), expected);
+    }
+
+    #[test]
+    fn keeps_strings_and_block_comments_as_written() {
+        let src = "let s = \"a  \\\"b\"; /* one\n  two */\nlet r = 0..3;\nlet a = [...r];";
+        let expected = "let s = \"a  \\\"b\"; /* one\n  two */\nlet r = 0..3;\nlet a = [...r];\n";
+        assert_eq!(fmt(src), expected);
+        assert_eq!(fmt(expected), expected);
+    }
+}
@@ -0,0 +1,94 @@
+//! Extensions to the language registered before running anything.
+//!
+//! Only prefix operators can be added, each one backed by a native function: `√x` is parsed
+//! as a call to the function registered for `√`, with the same precedence as `-x`. There's no
+//! way to add other kinds of syntax.
+use crate::{
+    error::{LoxError, LoxResult},
+    interpreter::{Environment, Interpreter, LoxCallable, LoxValue},
+    sync::Rc,
+    token::Keyword,
+};
+use unicode_xid::UnicodeXID;
+
+/// Native function a custom operator is evaluated with.
+pub type OperatorFn = fn(&LoxValue) -> LoxResult<LoxValue>;
+
+/// The custom operators known to a [`Lox`](super::Lox) instance.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Grammar {
+    operators: Vec<(String, OperatorFn)>,
+}
+
+impl Grammar {
+    /// Adds a prefix operator, which is either a single character the language doesn't use
+    /// already, like `√`, or a word that isn't a keyword, which can't be used as an identifier
+    /// anymore.
+    pub fn prefix_operator(&mut self, symbol: &str, apply: OperatorFn) -> LoxResult<()> {
+        let mut chars = symbol.chars();
+        let is_valid = match (chars.next(), chars.next()) {
+            (Some(c), None) if !c.is_xid_continue() => !"(){}[],.:;+-*/%!=<>?|\"\\".contains(c),
+            (Some(c), _) if c.is_xid_start() || c == '_' => {
+                symbol.chars().all(UnicodeXID::is_xid_continue)
+                    && symbol.parse::<Keyword>().is_err()
+                    && !matches!(symbol, "true" | "false" | "nan" | "inf")
+            }
+            _ => false,
+        };
+        if !is_valid || symbol.chars().any(char::is_whitespace) {
+            return Err(LoxError::Generic(format!(
+                "`{}` cannot be used as an operator",
+                symbol
+            )));
+        }
+        if self.is_operator(symbol) {
+            return Err(LoxError::Generic(format!(
+                "operator `{}` is already defined",
+                symbol
+            )));
+        }
+        self.operators.push((symbol.to_string(), apply));
+        Ok(())
+    }
+
+    pub fn is_operator(&self, symbol: &str) -> bool {
+        self.operators.iter().any(|(op, _)| op == symbol)
+    }
+
+    /// Defines the function backing each operator in `env`, named as the operator itself
+    /// since no variable can have that name.
+    pub fn define(&self, env: &Environment) {
+        for (symbol, apply) in &self.operators {
+            let operator = Operator {
+                symbol: symbol.to_owned(),
+                apply: *apply,
+            };
+            env.define(symbol, Rc::new(LoxValue::Callable(Rc::new(operator))));
+        }
+    }
+}
+
+struct Operator {
+    symbol: String,
+    apply: OperatorFn,
+}
+
+impl LoxCallable for Operator {
+    fn call(
+        &self,
+        _: Rc<Environment>,
+        _: &Interpreter,
+        args: &[Rc<LoxValue>],
+    ) -> LoxResult<Rc<LoxValue>> {
+        (self.apply)(&args[0]).map(Rc::new)
+    }
+    fn arity(&self) -> usize {
+        1
+    }
+    fn to_string(&self) -> String {
+        format!("<native operator {}>", self.symbol)
+    }
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
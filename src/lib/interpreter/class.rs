@@ -1,14 +1,12 @@
-use crate::lib::{
+use crate::{
     error::{InnerError, LoxError, LoxResult},
-    interpreter::{values::LoxCallable, Environment, LoxValue},
-    parser::Expr,
+    interpreter::{values::LoxCallable, Environment, Interpreter, LoxValue},
+    sync::{Rc, RefCell},
     token::Token,
 };
 use std::{
-    cell::RefCell,
     collections::HashMap,
     fmt::{Display, Formatter},
-    rc::Rc,
 };
 
 use super::LoxFunction;
@@ -63,14 +61,14 @@ impl LoxCallable for LoxClass {
     fn call(
         &self,
         env: Rc<Environment>,
-        locals: &HashMap<Expr, usize>,
+        interpreter: &Interpreter,
         args: &[Rc<LoxValue>],
     ) -> LoxResult<Rc<LoxValue>> {
         let instance = LoxInstance::new(self.clone());
         if let Some(constructor) = self.find_method("init") {
             return constructor
                 .bind(&instance)?
-                .call(Rc::clone(&env), locals, args);
+                .call(Rc::clone(&env), interpreter, args);
         }
 
         Ok(Rc::new(LoxValue::Instance(instance)))
@@ -92,14 +90,18 @@ impl LoxCallable for LoxClass {
     }
 }
 
+/// An instance of a [`LoxClass`].
+///
+/// Clones share the same fields, so the instance bound to `this` inside a method is the
+/// very same the method was accessed on.
 #[derive(Clone, Debug)]
-pub(crate) struct LoxInstance {
+pub struct LoxInstance {
     class: LoxClass,
-    fields: RefCell<HashMap<String, Rc<LoxValue>>>,
+    fields: Rc<RefCell<HashMap<String, Rc<LoxValue>>>>,
 }
 
 impl LoxInstance {
-    pub fn new(class: LoxClass) -> Self {
+    pub(crate) fn new(class: LoxClass) -> Self {
         Self {
             class,
             fields: Default::default(),
@@ -119,6 +121,32 @@ impl LoxInstance {
         Err(InnerError::new(*name.span(), &format!("undefined property `{}`", name)).into())
     }
 
+    /// Whether both are the same instance.
+    pub fn is(&self, oth: &LoxInstance) -> bool {
+        Rc::ptr_eq(&self.fields, &oth.fields)
+    }
+
+    /// Gets the method `name` bound to this instance, fields are not considered.
+    pub(crate) fn method(&self, name: &str) -> Option<LoxResult<LoxFunction>> {
+        self.class.find_method(name).map(|method| method.bind(self))
+    }
+
+    /// The fields of the instance, sorted by name.
+    pub fn fields(&self) -> Vec<(String, Rc<LoxValue>)> {
+        let mut fields: Vec<_> = self
+            .fields
+            .borrow()
+            .iter()
+            .map(|(name, value)| (name.to_owned(), Rc::clone(value)))
+            .collect();
+        fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+        fields
+    }
+
+    pub fn class_name(&self) -> String {
+        LoxCallable::to_string(&self.class)
+    }
+
     pub fn set(&self, name: &Token, val: &Rc<LoxValue>) -> LoxResult<()> {
         self.fields
             .borrow_mut()
@@ -137,3 +165,165 @@ impl Display for LoxInstance {
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::{
+        test::{global, output, run},
+        LoxValue,
+    };
+
+    const VECTOR: &str = "
+        class Vec {
+            init(x, y) { this.x = x; this.y = y; }
+            __add__(oth) { return Vec(this.x + oth.x, this.y + oth.y); }
+            __eq__(oth) { return this.x == oth.x and this.y == oth.y; }
+        }
+    ";
+
+    #[test]
+    fn methods_bind_their_own_instance() {
+        let src = r#"
+            class Person {
+                init(name) { this.name = name; }
+                getName() { return this.name; }
+            }
+            let a = Person("a");
+            let b = Person("b");
+            let getA = a.getName;
+            let getB = b.getName;
+            let names = getA() + getB();
+        "#;
+        assert_eq!(*global(src, "names"), LoxValue::String("ab".into()));
+    }
+
+    #[test]
+    fn methods_mutate_the_receiving_instance() {
+        let src = r#"
+            class Counter {
+                init() { this.count = 0; }
+                increment() { this.count = this.count + 1; }
+            }
+            let a = Counter();
+            let b = Counter();
+            a.increment();
+            a.increment();
+            b.increment();
+            let counts = [a.count, b.count];
+        "#;
+        assert_eq!(global(src, "counts").to_string(), "[2, 1]");
+    }
+
+    #[test]
+    fn classes_without_init_take_no_arguments() {
+        let src = "class Foo {} let foo = Foo(); foo.x = 1; let x = foo.x;";
+        assert_eq!(*global(src, "x"), LoxValue::Integer(1));
+
+        let errors = run("class Foo {} Foo(1, 2);").unwrap_err();
+        assert!(errors[0]
+            .to_string()
+            .contains("expected 0 arguments but got 2"));
+
+        // An inherited `init` still counts
+        let src = "class A { init(a) { this.a = a; } } class B extends A {} let x = B(3).a;";
+        assert_eq!(*global(src, "x"), LoxValue::Integer(3));
+        assert!(run("class A { init(a) {} } class B extends A {} B();").is_err());
+    }
+
+    #[test]
+    fn repeated_fields_and_calls_keep_their_values() {
+        let src = "
+            class Box { init(n) { this.n = n; } }
+            class Counter {
+                init() { this.n = 0; this.box = Box(Box(1)); }
+                next() { this.n = this.n + 1; return this; }
+            }
+            fn reads(c) {
+                let a = c.box.n.n + 0;
+                let b = c.box.n.n + 1;
+                return a * 10 + b;
+            }
+            fn calls(c) {
+                let a = c.next().n;
+                let b = c.next().n;
+                return a * 10 + b;
+            }
+            let c = Counter();
+            let pure = reads(c);
+            let effects = calls(c);
+        ";
+        assert_eq!(*global(src, "pure"), LoxValue::Integer(12));
+        assert_eq!(*global(src, "effects"), LoxValue::Integer(12));
+    }
+
+    #[test]
+    fn binary_operators_call_overloaded_methods() {
+        let src = "
+            let v = Vec(1, 2) + Vec(3, 4);
+            let x = v.x;
+            let y = v.y;
+            let same = v == Vec(4, 6);
+            let different = v != Vec(4, 6);
+        ";
+        let src = format!("{}{}", VECTOR, src);
+        assert!(matches!(*global(&src, "x"), LoxValue::Integer(4)));
+        assert!(matches!(*global(&src, "y"), LoxValue::Integer(6)));
+        assert!(matches!(*global(&src, "same"), LoxValue::Boolean(true)));
+        assert!(matches!(
+            *global(&src, "different"),
+            LoxValue::Boolean(false)
+        ));
+    }
+
+    #[test]
+    fn operators_without_an_overload_keep_their_errors() {
+        assert!(run(&format!("{}Vec(1, 2) - Vec(1, 2);", VECTOR)).is_err());
+        assert!(run(&format!("{}Vec(1, 2) < 1;", VECTOR)).is_err());
+
+        let src = "class Point {} let p = Point(); print p == p; print p == Point(); print p == 1;";
+        assert_eq!(output(src), "true\nfalse\nfalse\n");
+    }
+
+    #[test]
+    fn enum_members_are_distinct_values() {
+        let src = "
+            enum Color { Red, Green, Blue, }
+            enum Size { Small }
+            let red = Color.Red;
+            print red == Color.Red;
+            print red != Color.Green;
+            print red == 0;
+            print red == \"Red\";
+            print Size.Small == Color.Red;
+            print Color.Blue;
+            print [Color.Red, Size.Small];
+        ";
+        assert_eq!(
+            output(src),
+            "true\ntrue\nfalse\nfalse\nfalse\nBlue\n[Red, Small]\n"
+        );
+    }
+
+    #[test]
+    fn enum_members_must_exist_and_be_unique() {
+        assert!(run("enum Color { Red } Color.Purple;").is_err());
+        assert!(run("enum Color { Red, Red }").is_err());
+    }
+
+    #[test]
+    fn enums_are_read_only() {
+        let error = |src| run(src).unwrap_err()[0].to_string();
+        assert_eq!(
+            error("enum Color { Red } Color.Red = 1;"),
+            "cannot assign to `Color.Red`, enums are read-only"
+        );
+        assert_eq!(
+            error("enum Color { Red } Color.Green = 1;"),
+            "cannot assign to `Color.Green`, enums are read-only"
+        );
+        assert_eq!(
+            output("enum Color { Red, Green, Blue } print Color; print Color == Color;"),
+            "enum Color { Red, Green, Blue }\ntrue\n"
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! Line coverage of a running program, enabled with `--lines`.
+//!
+//! An [`Interpreter`](super::Interpreter) that records coverage holds a [`Recorder`], which
+//! statements report themselves to as they execute.
+use std::{cell::RefCell, collections::BTreeSet, fmt};
+
+use crate::{
+    parser::{
+        visitor::{walk_stmt, Visitor},
+        Expr, Stmt,
+    },
+    token::Token,
+};
+
+/// Lines of the statements executed so far.
+#[derive(Default)]
+pub(crate) struct Recorder(RefCell<BTreeSet<u32>>);
+
+impl Recorder {
+    /// Records `stmt` as executed.
+    pub fn record(&self, stmt: &Stmt) {
+        if let Some(span) = stmt.position() {
+            self.0.borrow_mut().insert(span.start().line_number());
+        }
+    }
+
+    /// The coverage of `statements`, given the lines recorded so far.
+    pub fn coverage(&self, statements: &[Stmt]) -> Coverage {
+        let mut lines = Lines::default();
+        lines.visit_program(statements);
+        Coverage {
+            lines: lines.0,
+            executed: self.0.borrow().clone(),
+        }
+    }
+}
+
+/// Collects the line of every statement that can be executed.
+#[derive(Default)]
+struct Lines(BTreeSet<u32>);
+
+impl Visitor for Lines {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        if let Some(span) = stmt.position() {
+            self.0.insert(span.start().line_number());
+        }
+        walk_stmt(self, stmt)
+    }
+
+    fn visit_class(
+        &mut self,
+        _name: &Token,
+        _superclass: Option<&Expr>,
+        methods: &[Stmt],
+        static_methods: &[Stmt],
+    ) {
+        // Method declarations are never executed on their own, only their bodies are
+        for method in methods.iter().chain(static_methods) {
+            if let Stmt::Function(_, _, body, _) = method {
+                self.visit_stmt(body);
+            }
+        }
+    }
+}
+
+pub(crate) struct Coverage {
+    lines: BTreeSet<u32>,
+    executed: BTreeSet<u32>,
+}
+
+impl Coverage {
+    /// Lines holding statements that never ran.
+    pub fn missed(&self) -> Vec<u32> {
+        self.lines.difference(&self.executed).copied().collect()
+    }
+}
+
+impl fmt::Display for Coverage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let missed = self.missed();
+        write!(
+            f,
+            "coverage: {} of {} lines executed",
+            self.lines.len() - missed.len(),
+            self.lines.len()
+        )?;
+        if !missed.is_empty() {
+            let missed: Vec<_> = missed.iter().map(u32::to_string).collect();
+            write!(f, "\nnot executed: {}", missed.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        interpreter::{Interpreter, Resolver},
+        lexer::Lexer,
+        parser::Parser,
+    };
+
+    fn coverage(src: &str) -> Coverage {
+        let tokens = Lexer::new(src).scan_tokens().unwrap();
+        let statements = Parser::new(&tokens).parse().unwrap();
+        let interpreter = Interpreter::new(&statements).record_coverage(true);
+        Resolver::new(&interpreter).resolve(&statements).unwrap();
+        // Failed runs are still reported
+        let mut out = Vec::new();
+        let _ = interpreter.interpret_with(&mut out);
+        interpreter.coverage(&statements).unwrap()
+    }
+
+    #[test]
+    fn reports_a_never_taken_else_branch() {
+        let src = "let x = 1;
+if x == 1 {
+    x = 2;
+} else {
+    x = 3;
+}
+fn unused() {
+    return 4;
+}
+class A {
+    m() { return 5; }
+}
+A().m();";
+        let coverage = coverage(src);
+        assert_eq!(coverage.missed(), vec![5, 8]);
+        assert_eq!(
+            coverage.to_string(),
+            "coverage: 7 of 9 lines executed\nnot executed: 5, 8"
+        );
+    }
+
+    #[test]
+    fn every_run_records_its_own_lines() {
+        assert_eq!(
+            coverage("let x = 1;\nprint x + nil;\nx = 2;").missed(),
+            vec![]
+        );
+        assert_eq!(
+            coverage("let x = 1;\nif false {\n    x = 2;\n}").missed(),
+            vec![3]
+        );
+
+        let src = "print 1;";
+        let statements = Parser::new(&Lexer::new(src).scan_tokens().unwrap())
+            .parse()
+            .unwrap();
+        assert!(Interpreter::new(&statements)
+            .coverage(&statements)
+            .is_none());
+    }
+}
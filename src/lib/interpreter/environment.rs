@@ -1,60 +1,188 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+};
 
-use crate::lib::error::{LoxError, LoxResult};
+use crate::{
+    error::{LoxError, LoxResult},
+    parser::{Expr, Stmt},
+    sync::{Rc, RefCell},
+    token::{Token, TokenKind},
+};
 
 use super::LoxValue;
 
+/// Position of a local variable, as computed by the [`Resolver`](super::Resolver).
+///
+/// The variable lives `depth` scopes above the one it's used from, in the `slot`-th position
+/// of that scope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Local {
+    pub depth: usize,
+    pub slot: usize,
+}
+
+/// Resolved local variables, keyed by the expression that refers to them.
+pub(crate) type Locals = HashMap<Expr, Local>;
+
+/// Where a global variable lives, replaced in place when it's assigned or redefined.
+type Global = Rc<RefCell<Rc<LoxValue>>>;
+
+/// Statements deferred by a function call, with the environment they were deferred in.
+type Deferred = RefCell<Vec<(Stmt, Rc<Environment>)>>;
+
+/// The storage of an interned name, compared by address.
+///
+/// Holding the storage keeps the address from being reused by another name.
+#[derive(Debug)]
+struct InternedName(Rc<str>);
+
+impl PartialEq for InternedName {
+    fn eq(&self, oth: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &oth.0)
+    }
+}
+
+impl Eq for InternedName {}
+
+impl Hash for InternedName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Rc::as_ptr(&self.0) as *const u8 as usize).hash(state)
+    }
+}
+
 /// Stores variables declared during the program, and keeps track of the scopes as well.
 ///
 /// A local environment is created from, and keeps a reference to, it's parent (enclosing)
 /// environment. The global one has no enclosing env.
+///
+/// Globals are looked up by name, while locals are stored in declaration order, which
+/// matches the slots assigned by the resolver, so they can be accessed by index.
+///
+/// The global one also remembers where the globals it was asked for by name live, keyed by the
+/// name's interned storage, see [`get_global`](Environment::get_global).
+///
+/// The environment of a function call also holds the statements deferred in it, see
+/// [`defer`](Environment::defer).
 #[derive(Debug)]
-pub(crate) struct Environment {
-    values: RefCell<HashMap<Box<str>, Rc<LoxValue>>>,
+pub struct Environment {
+    values: RefCell<HashMap<Box<str>, Global>>,
+    cache: RefCell<HashMap<InternedName, Global>>,
+    slots: RefCell<Vec<(Box<str>, Rc<LoxValue>)>>,
     enclosing: Option<Rc<Environment>>,
+    deferred: Option<Deferred>,
 }
 
 impl Environment {
     /// Creates a new global environment
-    pub fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self {
             enclosing: None,
             values: Default::default(),
+            cache: Default::default(),
+            slots: Default::default(),
+            deferred: None,
         }
     }
 
     /// Creates a local environment with a reference to its parent.
-    pub fn from(oth: Rc<Self>) -> Self {
+    pub(crate) fn from(oth: Rc<Self>) -> Self {
         Self {
             enclosing: Some(oth),
             values: Default::default(),
+            cache: Default::default(),
+            slots: Default::default(),
+            deferred: None,
         }
     }
 
-    /// Define a new variable in the current scope.
-    pub fn define(&self, name: &str, val: Rc<LoxValue>) {
-        self.values.borrow_mut().insert(name.into(), val);
+    /// Creates the environment of a function call, enclosed by the function's closure.
+    pub(crate) fn call(closure: Rc<Self>) -> Self {
+        Self {
+            deferred: Some(Default::default()),
+            ..Self::from(closure)
+        }
     }
 
-    /// Assign to a value at the innermost scope where it's found.
-    pub fn assign(&self, name: &str, val: &LoxValue) -> LoxResult<()> {
-        match self.values.borrow_mut().get_mut(name) {
-            Some(v) => {
-                *v = Rc::new(val.to_owned());
-                Ok(())
+    /// Schedules `stmt` to run in `env` once the innermost function call enclosing this scope
+    /// returns.
+    ///
+    /// Returns `false` if no function call encloses it.
+    pub(crate) fn defer(&self, stmt: &Stmt, env: &Rc<Environment>) -> bool {
+        let mut scope = self;
+        loop {
+            if let Some(deferred) = &scope.deferred {
+                deferred
+                    .borrow_mut()
+                    .push((stmt.to_owned(), Rc::clone(env)));
+                return true;
             }
-            None => {
-                if let Some(env) = &self.enclosing {
-                    return env.assign(name, val);
+            match &scope.enclosing {
+                Some(enclosing) => scope = enclosing,
+                None => return false,
+            }
+        }
+    }
+
+    /// Takes the statement deferred last in this function call, along with the environment it
+    /// was deferred in.
+    pub(crate) fn take_deferred(&self) -> Option<(Stmt, Rc<Environment>)> {
+        self.deferred.as_ref()?.borrow_mut().pop()
+    }
+
+    /// Define a new variable in the current scope.
+    ///
+    /// Redefining a local variable reuses its slot, and a global its cell, so cached lookups
+    /// see the new value.
+    pub(crate) fn define(&self, name: &str, val: Rc<LoxValue>) {
+        if self.enclosing.is_none() {
+            let mut values = self.values.borrow_mut();
+            match values.get(name) {
+                Some(global) => *global.borrow_mut() = val,
+                None => {
+                    values.insert(name.into(), Rc::new(RefCell::new(val)));
                 }
-                Err(LoxError::Generic(format!("`{}` is not defined", &name)))
             }
+            return;
+        }
+
+        let mut slots = self.slots.borrow_mut();
+        match slots.iter_mut().find(|(n, _)| &**n == name) {
+            Some((_, v)) => *v = val,
+            None => slots.push((name.into(), val)),
+        }
+    }
+
+    /// Assign to a value at the innermost scope where it's found.
+    pub(crate) fn assign(&self, name: &str, val: &LoxValue) -> LoxResult<()> {
+        if let Some(global) = self.values.borrow().get(name) {
+            *global.borrow_mut() = Rc::new(val.to_owned());
+            return Ok(());
         }
+
+        if let Some((_, v)) = self
+            .slots
+            .borrow_mut()
+            .iter_mut()
+            .find(|(n, _)| &**n == name)
+        {
+            *v = Rc::new(val.to_owned());
+            return Ok(());
+        }
+
+        if let Some(env) = &self.enclosing {
+            return env.assign(name, val);
+        }
+        Err(LoxError::Generic(format!("`{}` is not defined", name)))
     }
 
     /// Searches for a variable value from the innermost scope.
-    pub fn get(&self, name: &str) -> LoxResult<Rc<LoxValue>> {
-        if let Some(t) = self.values.borrow().get(name) {
+    pub(crate) fn get(&self, name: &str) -> LoxResult<Rc<LoxValue>> {
+        if let Some(global) = self.values.borrow().get(name) {
+            return Ok(Rc::clone(&global.borrow()));
+        }
+
+        if let Some((_, t)) = self.slots.borrow().iter().find(|(n, _)| &**n == name) {
             return Ok(Rc::clone(t));
         }
 
@@ -62,25 +190,125 @@ impl Environment {
             return env.get(name);
         }
 
-        Err(LoxError::Generic(format!("`{}` is not defined", &name)))
+        Err(LoxError::Generic(format!("`{}` is not defined", name)))
+    }
+
+    /// Gets a global variable, which must be looked up from the global environment.
+    ///
+    /// The first lookup of an interned name remembers the cell of the global, later ones go
+    /// straight to it without hashing the name. The cell is updated on every assignment and
+    /// redefinition, so the cache never goes stale. The names of every run are cached apart,
+    /// as the REPL interns each input on its own, so it's cleared before every run, see
+    /// [`forget_cached`](Self::forget_cached).
+    pub(crate) fn get_global(&self, name: &Token) -> LoxResult<Rc<LoxValue>> {
+        let symbol = match name.kind() {
+            TokenKind::Identifier(symbol) => symbol,
+            _ => return self.get(&name.to_string()),
+        };
+        let key = InternedName(Rc::clone(symbol.as_rc()));
+        if let Some(global) = self.cache.borrow().get(&key) {
+            return Ok(Rc::clone(&global.borrow()));
+        }
+
+        let global = match self.values.borrow().get(symbol.as_str()) {
+            Some(global) => Rc::clone(global),
+            None => return Err(LoxError::Generic(format!("`{}` is not defined", name))),
+        };
+        let val = Rc::clone(&global.borrow());
+        self.cache.borrow_mut().insert(key, global);
+        Ok(val)
+    }
+
+    /// Forgets where the globals looked up so far live, along with their names.
+    pub(crate) fn forget_cached(&self) {
+        self.cache.borrow_mut().clear();
+    }
+
+    /// Gets a variable declared in this scope, without looking into the enclosing ones.
+    pub(crate) fn get_here(&self, name: &str) -> Option<Rc<LoxValue>> {
+        if let Some(global) = self.values.borrow().get(name) {
+            return Some(Rc::clone(&global.borrow()));
+        }
+        let slots = self.slots.borrow();
+        slots
+            .iter()
+            .find(|(n, _)| &**n == name)
+            .map(|(_, val)| Rc::clone(val))
+    }
+
+    /// Names of the variables declared in this scope, leaving out the hidden ones introduced
+    /// by desugaring, as in `<repeat>`.
+    pub(crate) fn names(&self) -> Vec<String> {
+        let values = self.values.borrow();
+        let slots = self.slots.borrow();
+        values
+            .keys()
+            .chain(slots.iter().map(|(n, _)| n))
+            .filter(|n| !n.starts_with('<'))
+            .map(|n| n.to_string())
+            .collect()
     }
 
-    /// Gets a value from a speficic scope.
+    /// Gets a local variable by its position.
     ///
     /// This function relies on the variable binding and resolution performed by the [`Resolver`].
     /// So we already know that the variable exists and where it was declared.
-    pub fn get_at(&self, distance: usize, name: &str) -> LoxResult<Rc<LoxValue>> {
-        let values = self.ancestor(distance).values.borrow();
-        let val = values.get(name).unwrap();
+    ///
+    /// [`Resolver`]: super::Resolver
+    pub(crate) fn get_at(&self, local: Local) -> LoxResult<Rc<LoxValue>> {
+        let slots = self.ancestor(local.depth).slots.borrow();
+        let (_, val) = slots.get(local.slot).ok_or_else(Self::unresolved)?;
         Ok(Rc::clone(val))
     }
 
-    pub fn assign_at(&self, distance: usize, name: &str, val: &LoxValue) -> LoxResult<()> {
-        let mut values = self.ancestor(distance).values.borrow_mut();
-        values.insert(name.into(), Rc::new(val.to_owned()));
+    /// Whether the local variable at the given position has been defined yet.
+    pub(crate) fn is_defined_at(&self, local: Local) -> bool {
+        self.ancestor(local.depth).slots.borrow().len() > local.slot
+    }
+
+    /// Defines a variable in the scope at the given distance, which must be the next one
+    /// declared there.
+    pub(crate) fn define_at(&self, local: Local, name: &str, val: Rc<LoxValue>) {
+        self.ancestor(local.depth).define(name, val)
+    }
+
+    /// Assigns to a local variable by its position.
+    pub(crate) fn assign_at(&self, local: Local, val: &LoxValue) -> LoxResult<()> {
+        let mut slots = self.ancestor(local.depth).slots.borrow_mut();
+        let (_, v) = slots.get_mut(local.slot).ok_or_else(Self::unresolved)?;
+        *v = Rc::new(val.to_owned());
         Ok(())
     }
 
+    /// Appends the names declared from this scope up to the global one to the message of a
+    /// failed lookup.
+    pub(crate) fn with_scopes(&self, message: String) -> String {
+        let mut message = message + "\nscopes searched, innermost first:";
+        let mut env = self;
+        let mut depth = 0;
+        while let Some(enclosing) = &env.enclosing {
+            message += &format!("\n    {}: {}", depth, Self::list(env.names()));
+            env = enclosing;
+            depth += 1;
+        }
+        let mut globals = env.names();
+        globals.sort();
+        message + &format!("\n    global: {}", Self::list(globals))
+    }
+
+    fn list(names: Vec<String>) -> String {
+        if names.is_empty() {
+            return String::from("(empty)");
+        }
+        names.join(", ")
+    }
+
+    fn unresolved() -> LoxError {
+        LoxError::Generic(String::from(
+            "attempt to access an unresolved variable. this is probably a bug.",
+        ))
+    }
+
     /// Helper function that retrieves a scope at a given distance from the local scope.
     fn ancestor(&self, distance: usize) -> &Self {
         let mut env = self;
@@ -90,7 +318,7 @@ impl Environment {
         env
     }
 
-    pub fn global(&self) -> &Self {
+    pub(crate) fn global(&self) -> &Self {
         let mut env = self;
         while let Some(ref e) = env.enclosing {
             env = e
@@ -98,7 +326,26 @@ impl Environment {
         env
     }
 
-    pub fn enclosing(&self) -> &Option<Rc<Self>> {
+    pub(crate) fn enclosing(&self) -> &Option<Rc<Self>> {
         &self.enclosing
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lexer::Lexer;
+
+    #[test]
+    fn forgets_the_names_of_previous_runs() {
+        let env = Environment::new();
+        env.define("g", Rc::new(LoxValue::Integer(1)));
+        for _ in 0..3 {
+            env.forget_cached();
+            let name = Lexer::new("g").scan_tokens().unwrap().remove(0);
+            assert_eq!(*env.get_global(&name).unwrap(), LoxValue::Integer(1));
+            assert_eq!(*env.get_global(&name).unwrap(), LoxValue::Integer(1));
+            assert_eq!(env.cache.borrow().len(), 1);
+        }
+    }
+}
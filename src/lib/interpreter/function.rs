@@ -1,15 +1,68 @@
-use crate::lib::{
-    interpreter::{class::LoxInstance, values::LoxCallable, Environment, LoxError, LoxValue},
-    parser::{Expr, Stmt},
+use crate::{
+    error::InnerError,
+    interpreter::{
+        class::LoxInstance, values::LoxCallable, Environment, Interpreter, Local, LoxError,
+        LoxValue,
+    },
+    parser::{statements::ReturnVal, Signature, Stmt},
+    sync::Rc,
+    typechecker::Type,
     LoxResult,
 };
-use std::{collections::HashMap, rc::Rc};
 
+/// Fails if `interpreter` [checks returns](Interpreter::check_returns) and `ret` doesn't match
+/// the annotation of `sig`.
+fn check_return(interpreter: &Interpreter, sig: &Signature, ret: &ReturnVal) -> LoxResult<()> {
+    if !interpreter.checks_returns() {
+        return Ok(());
+    }
+    let expected = Type::from_annotation(&sig.ret);
+    let found = Type::of(&ret.val);
+    if expected != Type::Unknown && expected != found {
+        return Err(InnerError::new(
+            ret.pos,
+            &format!(
+                "mismatched return type: expected `{}`, found `{}`",
+                expected, found
+            ),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Runs the statements deferred in `call`, the environment of a call that returned with `res`,
+/// last deferred first.
+///
+/// A deferred `return` replaces the returned value, and a deferred error replaces the result
+/// unless the function already failed.
+fn run_deferred(
+    res: LoxResult<()>,
+    call: &Environment,
+    interpreter: &Interpreter,
+) -> LoxResult<()> {
+    let mut res = res;
+    // Statements deferred meanwhile are deferred in the same call, and run next
+    while let Some((stmt, env)) = call.take_deferred() {
+        let deferred = stmt.execute(env, interpreter);
+        if matches!(res, Ok(()) | Err(LoxError::Return(_))) && deferred.is_err() {
+            res = deferred;
+        }
+    }
+    res
+}
+
+/// A function declared in Lox code.
+///
+/// Calls run in an environment enclosed by `closure`, which is either `statics`, the
+/// environment of the static variables, or for bound methods, the one holding `this`, which is
+/// in turn enclosed by `statics`. Bound methods share the static variables of their method.
 #[derive(Debug, Clone)]
 pub(crate) struct LoxFunction {
     declaration: Stmt,
     arity: usize,
     closure: Rc<Environment>,
+    statics: Rc<Environment>,
     is_initializer: bool,
 }
 
@@ -19,12 +72,14 @@ impl LoxFunction {
         closure: Rc<Environment>,
         is_initializer: bool,
     ) -> LoxResult<Self> {
-        if let Stmt::Function(_, ref params, _) = declaration {
+        if let Stmt::Function(_, ref params, ..) = declaration {
             let arity = params.len();
+            let statics = Rc::new(Environment::from(closure));
             Ok(Self {
                 declaration,
                 arity,
-                closure,
+                closure: Rc::clone(&statics),
+                statics,
                 is_initializer,
             })
         } else {
@@ -36,9 +91,12 @@ impl LoxFunction {
     }
 
     pub fn bind(&self, instance: &LoxInstance) -> LoxResult<LoxFunction> {
-        let env = Environment::from(Rc::clone(&self.closure));
+        let env = Environment::from(Rc::clone(&self.statics));
         env.define("this", Rc::new(LoxValue::Instance(instance.to_owned())));
-        LoxFunction::new(self.declaration.clone(), env.into(), self.is_initializer)
+        Ok(LoxFunction {
+            closure: env.into(),
+            ..self.clone()
+        })
     }
 
     pub fn is_initializer(&self) -> bool {
@@ -50,27 +108,46 @@ impl LoxCallable for LoxFunction {
     fn call(
         &self,
         _: Rc<Environment>,
-        locals: &HashMap<Expr, usize>,
+        interpreter: &Interpreter,
         args: &[Rc<LoxValue>],
     ) -> LoxResult<Rc<LoxValue>> {
-        let env = Rc::new(Environment::from(Rc::clone(&self.closure)));
-        if let Stmt::Function(_name, params, body) = &self.declaration {
+        let env = Rc::new(Environment::call(Rc::clone(&self.closure)));
+        if let Stmt::Function(name, params, body, sig) = &self.declaration {
             for (ident, val) in params.iter().zip(args) {
                 env.define(&ident.to_string(), Rc::clone(val))
             }
-            if let Err(err) = body.execute(Rc::clone(&env), locals, &mut std::io::stdout()) {
-                // Capture the return value that is unwinding the call stack
+            let res = body.execute(Rc::clone(&env), interpreter);
+            let res = run_deferred(res, &env, interpreter);
+            if let Err(err) = res {
+                // Capture the return value that is unwinding the call stack, an initializer
+                // can only return early, still giving the instance
                 if let LoxError::Return(r) = err {
+                    if self.is_initializer() {
+                        return self.closure.get_at(Local { depth: 0, slot: 0 });
+                    }
+                    check_return(interpreter, sig, &r)?;
                     return Ok(Rc::new(r.val));
                 }
                 return Err(err);
             }
-        }
 
-        if self.is_initializer() {
-            return self.closure.get_at(0, "this");
+            if self.is_initializer() {
+                // The closure of a bound method holds nothing but `this`
+                return self.closure.get_at(Local { depth: 0, slot: 0 });
+            }
+            // The implicit `nil` is checked too, reported at the name of the function
+            check_return(
+                interpreter,
+                sig,
+                &ReturnVal {
+                    val: LoxValue::Nil,
+                    pos: *name.span(),
+                },
+            )?;
         }
 
+        // Falling off the end of the body yields `nil`, the value of a trailing expression
+        // statement is discarded along with the environment of the call
         Ok(Rc::new(LoxValue::Nil))
     }
 
@@ -79,8 +156,8 @@ impl LoxCallable for LoxFunction {
     }
 
     fn to_string(&self) -> String {
-        if let Stmt::Function(name, _, _) = &self.declaration {
-            return format!("<fn {}>", name.to_string());
+        if let Stmt::Function(name, ..) = &self.declaration {
+            return format!("<fn {}>", name);
         }
         unreachable!()
     }
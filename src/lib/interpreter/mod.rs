@@ -1,18 +1,25 @@
-use crate::lib::{
+use crate::{
     error::LoxError,
     parser::{Expr, Stmt},
+    sync::Rc,
     LoxResult,
 };
 
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{cell::RefCell, io::Write};
 
-pub(crate) use self::{class::LoxClass, function::LoxFunction, values::LoxValue};
-pub(crate) use environment::Environment;
+pub(crate) use self::{
+    class::LoxClass,
+    function::LoxFunction,
+    values::{LoxCallable, LoxEnum},
+};
+pub(crate) use environment::{Environment, Local, Locals};
 pub(crate) use resolver::Resolver;
+pub use values::LoxValue;
 
 #[macro_use]
 pub(crate) mod util;
 pub(crate) mod class;
+mod coverage;
 mod environment;
 mod function;
 mod resolver;
@@ -21,11 +28,14 @@ pub(crate) mod values;
 mod builtins {
     use super::{
         values::{LoxCallable, LoxValue},
-        Environment,
+        Environment, Interpreter,
+    };
+    use crate::{
+        error::{LoxError, LoxResult},
+        sync::Rc,
     };
-    use crate::lib::{error::LoxResult, parser::Expr};
     use derive_new::*;
-    use std::{collections::HashMap, rc::Rc, time::UNIX_EPOCH};
+    use std::{convert::TryFrom, time::UNIX_EPOCH};
 
     #[derive(new)]
     pub struct Clock;
@@ -33,20 +43,60 @@ mod builtins {
     #[derive(new)]
     pub struct Read;
 
-    /// Gets the system time as a unix timestamp and return it as a [`LoxValue::Decimal`].
+    #[derive(new)]
+    pub struct RoundTo;
+
+    #[derive(new)]
+    pub struct Arity;
+
+    #[derive(new)]
+    pub struct Format;
+
+    #[derive(new)]
+    pub struct RepeatCount;
+
+    #[derive(new)]
+    pub struct PrettyPrint;
+
+    #[derive(new)]
+    pub struct TypeOf;
+
+    #[derive(new)]
+    pub struct Len;
+
+    #[derive(new)]
+    pub struct AssertThrows;
+
+    /// Where [`Pad`] puts the spaces.
+    pub enum Side {
+        Left,
+        Right,
+        Both,
+    }
+
+    #[derive(new)]
+    pub struct Pad {
+        side: Side,
+    }
+
+    /// Gets the system time as a unix timestamp in seconds and return it as a
+    /// [`LoxValue::Decimal`].
     impl LoxCallable for Clock {
         fn call(
             &self,
             _: Rc<Environment>,
-            _: &HashMap<Expr, usize>,
+            _: &Interpreter,
             _: &[Rc<LoxValue>],
         ) -> LoxResult<Rc<LoxValue>> {
             Ok(Rc::new(LoxValue::Decimal(
                 std::time::SystemTime::now()
                     .duration_since(UNIX_EPOCH)?
-                    .as_millis() as f64,
+                    .as_secs_f64(),
             )))
         }
+        fn to_string(&self) -> String {
+            String::from("<native fn clock>")
+        }
         fn as_any(&self) -> &dyn std::any::Any {
             self
         }
@@ -57,12 +107,359 @@ mod builtins {
         fn call(
             &self,
             _: Rc<Environment>,
-            _: &HashMap<Expr, usize>,
+            _: &Interpreter,
             _: &[Rc<LoxValue>],
         ) -> LoxResult<Rc<LoxValue>> {
             let mut buf = String::new();
             std::io::stdin().read_line(&mut buf)?;
-            Ok(Rc::new(LoxValue::String(buf)))
+            Ok(Rc::new(LoxValue::String(buf.into())))
+        }
+        fn as_any(&self) -> &dyn std::any::Any {
+            self
+        }
+    }
+
+    /// Rounds a number to the given amount of decimal places, returning a [`LoxValue::Decimal`].
+    /// Negative places round to tens, hundreds, and so on.
+    impl LoxCallable for RoundTo {
+        fn call(
+            &self,
+            _: Rc<Environment>,
+            _: &Interpreter,
+            args: &[Rc<LoxValue>],
+        ) -> LoxResult<Rc<LoxValue>> {
+            let x = match *args[0] {
+                LoxValue::Integer(i) => i as f64,
+                LoxValue::Decimal(d) => d,
+                ref oth => {
+                    return Err(LoxError::Generic(format!(
+                        "round_to: expected a number, got `{}`",
+                        oth
+                    )))
+                }
+            };
+            let digits = match *args[1] {
+                LoxValue::Integer(i) => i32::try_from(i)
+                    .map_err(|_| LoxError::Generic(format!("round_to: too many digits `{}`", i)))?,
+                ref oth => {
+                    return Err(LoxError::Generic(format!(
+                        "round_to: expected an integer amount of digits, got `{}`",
+                        oth
+                    )))
+                }
+            };
+
+            // Past the range of a float, every place is already as precise as it gets, and
+            // rounding to more tens than that gives zero
+            let factor = 10f64.powi(digits.abs());
+            let rounded = if digits < 0 {
+                if factor.is_finite() {
+                    (x / factor).round() * factor
+                } else {
+                    0f64.copysign(x)
+                }
+            } else {
+                match x * factor {
+                    scaled if scaled.is_finite() => scaled.round() / factor,
+                    _ => x,
+                }
+            };
+            Ok(Rc::new(LoxValue::Decimal(rounded)))
+        }
+        fn arity(&self) -> usize {
+            2
+        }
+        fn as_any(&self) -> &dyn std::any::Any {
+            self
+        }
+    }
+
+    /// Gets the amount of parameters a callable takes, as a [`LoxValue::Integer`].
+    impl LoxCallable for Arity {
+        fn call(
+            &self,
+            _: Rc<Environment>,
+            _: &Interpreter,
+            args: &[Rc<LoxValue>],
+        ) -> LoxResult<Rc<LoxValue>> {
+            match *args[0] {
+                LoxValue::Callable(ref callable) => {
+                    Ok(Rc::new(LoxValue::Integer(callable.arity() as isize)))
+                }
+                ref oth => Err(LoxError::Generic(format!(
+                    "arity: expected a callable, got `{}`",
+                    oth
+                ))),
+            }
+        }
+        fn arity(&self) -> usize {
+            1
+        }
+        fn as_any(&self) -> &dyn std::any::Any {
+            self
+        }
+    }
+
+    /// Replaces the `{}` placeholders of a format string with the following arguments, in
+    /// order, and `{0}`, `{1}`... with the argument at that index, returning a
+    /// [`LoxValue::String`]. `{{` and `}}` are literal braces.
+    impl LoxCallable for Format {
+        fn call(
+            &self,
+            _: Rc<Environment>,
+            _: &Interpreter,
+            args: &[Rc<LoxValue>],
+        ) -> LoxResult<Rc<LoxValue>> {
+            let fmt = match *args[0] {
+                LoxValue::String(ref s) => Rc::clone(s),
+                ref oth => {
+                    return Err(LoxError::Generic(format!(
+                        "format: expected a format string, got `{}`",
+                        oth
+                    )))
+                }
+            };
+            let args = &args[1..];
+
+            let mut out = String::with_capacity(fmt.len());
+            let mut next = 0;
+            let mut chars = fmt.chars().peekable();
+            while let Some(c) = chars.next() {
+                match c {
+                    '{' if chars.peek() == Some(&'{') => {
+                        chars.next();
+                        out.push('{');
+                    }
+                    '}' if chars.peek() == Some(&'}') => {
+                        chars.next();
+                        out.push('}');
+                    }
+                    '{' => {
+                        let mut index = String::new();
+                        loop {
+                            match chars.next() {
+                                Some('}') => break,
+                                Some(c) => index.push(c),
+                                None => {
+                                    return Err(LoxError::Generic(String::from(
+                                        "format: unterminated placeholder, use `{{` for a literal brace",
+                                    )))
+                                }
+                            }
+                        }
+                        if !index.chars().all(|c| c.is_ascii_digit()) {
+                            return Err(LoxError::Generic(format!(
+                                "format: invalid placeholder `{{{}}}`, expected `{{}}` or an index",
+                                index
+                            )));
+                        }
+                        let index = if index.is_empty() {
+                            next += 1;
+                            next - 1
+                        } else {
+                            index.parse().map_err(|_| {
+                                LoxError::Generic(format!("format: invalid index `{}`", index))
+                            })?
+                        };
+                        let arg = args.get(index).ok_or_else(|| {
+                            LoxError::Generic(format!(
+                                "format: no argument for placeholder {}, got {} arguments",
+                                index,
+                                args.len()
+                            ))
+                        })?;
+                        out += &arg.to_string();
+                    }
+                    '}' => {
+                        return Err(LoxError::Generic(String::from(
+                            "format: unmatched `}`, use `}}` for a literal brace",
+                        )))
+                    }
+                    c => out.push(c),
+                }
+            }
+            Ok(Rc::new(LoxValue::String(out.into())))
+        }
+        fn arity(&self) -> usize {
+            1
+        }
+        fn variadic(&self) -> bool {
+            true
+        }
+        fn as_any(&self) -> &dyn std::any::Any {
+            self
+        }
+    }
+
+    /// Checks the count of a `repeat` loop is a non-negative integer, returning it.
+    impl LoxCallable for RepeatCount {
+        fn call(
+            &self,
+            _: Rc<Environment>,
+            _: &Interpreter,
+            args: &[Rc<LoxValue>],
+        ) -> LoxResult<Rc<LoxValue>> {
+            match *args[0] {
+                LoxValue::Integer(i) if i >= 0 => Ok(Rc::clone(&args[0])),
+                ref oth => Err(LoxError::Generic(format!(
+                    "repeat count must be a non-negative integer, got `{}`",
+                    oth
+                ))),
+            }
+        }
+        fn arity(&self) -> usize {
+            1
+        }
+        fn as_any(&self) -> &dyn std::any::Any {
+            self
+        }
+    }
+
+    /// Pads the text of a value with spaces up to the given width in characters, returning a
+    /// [`LoxValue::String`]. Text already as wide is left unchanged. Centering puts the odd
+    /// space on the right.
+    impl LoxCallable for Pad {
+        fn call(
+            &self,
+            _: Rc<Environment>,
+            _: &Interpreter,
+            args: &[Rc<LoxValue>],
+        ) -> LoxResult<Rc<LoxValue>> {
+            let width = match *args[1] {
+                LoxValue::Integer(i) if i >= 0 => i as usize,
+                ref oth => {
+                    return Err(LoxError::Generic(format!(
+                        "{}: expected a non-negative integer width, got `{}`",
+                        self.name(),
+                        oth
+                    )))
+                }
+            };
+            let text = args[0].to_string();
+            let padding = width.saturating_sub(text.chars().count());
+            let (left, right) = match self.side {
+                Side::Left => (padding, 0),
+                Side::Right => (0, padding),
+                Side::Both => (padding / 2, padding - padding / 2),
+            };
+            let padded = format!("{}{}{}", " ".repeat(left), text, " ".repeat(right));
+            Ok(Rc::new(LoxValue::String(padded.into())))
+        }
+        fn arity(&self) -> usize {
+            2
+        }
+        fn as_any(&self) -> &dyn std::any::Any {
+            self
+        }
+    }
+
+    impl Pad {
+        pub fn name(&self) -> &'static str {
+            match self.side {
+                Side::Left => "pad_left",
+                Side::Right => "pad_right",
+                Side::Both => "center",
+            }
+        }
+    }
+
+    /// Gets the name of the type of a value, see [`LoxValue::type_name`].
+    impl LoxCallable for TypeOf {
+        fn call(
+            &self,
+            _: Rc<Environment>,
+            _: &Interpreter,
+            args: &[Rc<LoxValue>],
+        ) -> LoxResult<Rc<LoxValue>> {
+            Ok(Rc::new(LoxValue::String(args[0].type_name().into())))
+        }
+        fn arity(&self) -> usize {
+            1
+        }
+        fn as_any(&self) -> &dyn std::any::Any {
+            self
+        }
+    }
+
+    /// Gets the amount of characters in a string or of elements in an array, as a
+    /// [`LoxValue::Integer`].
+    impl LoxCallable for Len {
+        fn call(
+            &self,
+            _: Rc<Environment>,
+            _: &Interpreter,
+            args: &[Rc<LoxValue>],
+        ) -> LoxResult<Rc<LoxValue>> {
+            let len = match *args[0] {
+                LoxValue::String(ref s) => s.chars().count(),
+                LoxValue::Array(ref values) => values.borrow().len(),
+                ref oth => {
+                    return Err(LoxError::Generic(format!(
+                        "len: expected a string or an array, got `{}`",
+                        oth.type_name()
+                    )))
+                }
+            };
+            Ok(Rc::new(LoxValue::Integer(len as isize)))
+        }
+        fn arity(&self) -> usize {
+            1
+        }
+        fn as_any(&self) -> &dyn std::any::Any {
+            self
+        }
+    }
+
+    /// Calls a function without arguments, failing if it completes without a runtime error.
+    /// The message of the error is returned as a [`LoxValue::String`], so it can be checked too.
+    /// Control flow (`return` and `break`) isn't an error and keeps unwinding.
+    impl LoxCallable for AssertThrows {
+        fn call(
+            &self,
+            env: Rc<Environment>,
+            interpreter: &Interpreter,
+            args: &[Rc<LoxValue>],
+        ) -> LoxResult<Rc<LoxValue>> {
+            let callable = match *args[0] {
+                LoxValue::Callable(ref c) if c.arity() == 0 => c,
+                ref oth => {
+                    return Err(LoxError::Generic(format!(
+                        "assert_throws: expected a function without parameters, got `{}`",
+                        oth
+                    )))
+                }
+            };
+            match callable.call(env, interpreter, &[]) {
+                Ok(val) => Err(LoxError::Generic(format!(
+                    "assert_throws: expected an error, but the function returned `{}`",
+                    val
+                ))),
+                Err(e @ (LoxError::Return(_) | LoxError::Break(..) | LoxError::Continue)) => Err(e),
+                Err(e) => Ok(Rc::new(LoxValue::String(e.to_string().into()))),
+            }
+        }
+        fn arity(&self) -> usize {
+            1
+        }
+        fn as_any(&self) -> &dyn std::any::Any {
+            self
+        }
+    }
+
+    /// Prints a value like `print` does, but spreading nested arrays and instances over
+    /// several indented lines, see [`LoxValue::pretty`].
+    impl LoxCallable for PrettyPrint {
+        fn call(
+            &self,
+            _: Rc<Environment>,
+            interpreter: &Interpreter,
+            args: &[Rc<LoxValue>],
+        ) -> LoxResult<Rc<LoxValue>> {
+            interpreter.write(&format!("{}\n", args[0].pretty()))?;
+            Ok(Rc::new(LoxValue::Nil))
+        }
+        fn arity(&self) -> usize {
+            1
         }
         fn as_any(&self) -> &dyn std::any::Any {
             self
@@ -71,15 +468,25 @@ mod builtins {
 }
 
 /// Executes the statements generated in the parsing stage.
-/// This lifetime corresponds to the scope in [Lox::run](crate::lib::Lox::run)
-pub(crate) struct Interpreter {
+///
+/// Programs write to stdout, unless they're run with a writer of lifetime `'o`, see
+/// [`interpret_with`](Self::interpret_with).
+pub struct Interpreter<'o> {
     globals: Rc<Environment>,
     statements: Vec<Stmt>,
-    locals: RefCell<HashMap<Expr, usize>>,
+    locals: RefCell<Locals>,
+    /// List the scopes searched when a variable is not found
+    debug_scopes: bool,
+    /// Check returned values against the return type annotations
+    check_returns: bool,
+    /// Lines executed so far, if coverage is recorded
+    coverage: Option<coverage::Recorder>,
+    /// Where the running program writes, stdout if there's none
+    out: RefCell<Option<&'o mut dyn Write>>,
 }
 
-impl Interpreter {
-    pub fn new(statements: &[Stmt]) -> Self {
+impl<'o> Interpreter<'o> {
+    pub(crate) fn new(statements: &[Stmt]) -> Self {
         let statements = Vec::from(statements);
         let globals = Rc::new(Environment::new());
 
@@ -89,45 +496,173 @@ impl Interpreter {
             statements,
             globals,
             locals: Default::default(),
+            debug_scopes: false,
+            check_returns: false,
+            coverage: None,
+            out: Default::default(),
         }
     }
 
-    pub fn with_env(statements: &[Stmt], env: Rc<Environment>) -> Self {
+    /// Creates an interpreter running in `env`, whose globals must already be defined, see
+    /// [`define_globals`](Self::define_globals).
+    pub(crate) fn with_env(statements: &[Stmt], env: Rc<Environment>) -> Self {
         let statements = Vec::from(statements);
 
-        Self::define_globals(Rc::clone(&env));
-
         Self {
             statements,
             globals: env,
             locals: Default::default(),
+            debug_scopes: false,
+            check_returns: false,
+            coverage: None,
+            out: Default::default(),
         }
     }
 
-    fn define_globals(env: Rc<Environment>) {
+    pub(crate) fn define_globals(env: Rc<Environment>) {
         let clock = Rc::new(builtins::Clock::new());
         let read = Rc::new(builtins::Read::new());
+        let round_to = Rc::new(builtins::RoundTo::new());
+        let arity = Rc::new(builtins::Arity::new());
+        let format = Rc::new(builtins::Format::new());
+        let repeat_count = Rc::new(builtins::RepeatCount::new());
+        let pprint = Rc::new(builtins::PrettyPrint::new());
+        let type_of = Rc::new(builtins::TypeOf::new());
+        let len = Rc::new(builtins::Len::new());
+        let assert_throws = Rc::new(builtins::AssertThrows::new());
+        let pads = [
+            builtins::Side::Left,
+            builtins::Side::Right,
+            builtins::Side::Both,
+        ]
+        .map(|side| Rc::new(builtins::Pad::new(side)));
 
         // Define native functions
         env.define("clock", Rc::new(LoxValue::Callable(clock)));
         env.define("read", Rc::new(LoxValue::Callable(read)));
+        env.define("round_to", Rc::new(LoxValue::Callable(round_to)));
+        env.define("arity", Rc::new(LoxValue::Callable(arity)));
+        env.define("format", Rc::new(LoxValue::Callable(format)));
+        env.define("pprint", Rc::new(LoxValue::Callable(pprint)));
+        env.define("type", Rc::new(LoxValue::Callable(type_of.clone())));
+        env.define("len", Rc::new(LoxValue::Callable(len)));
+        env.define("assert_throws", Rc::new(LoxValue::Callable(assert_throws)));
+        for pad in pads {
+            env.define(pad.name(), Rc::new(LoxValue::Callable(pad)));
+        }
+        // Hidden, desugared `repeat` loops call it
+        env.define("<repeat count>", Rc::new(LoxValue::Callable(repeat_count)));
+        // `match type(x)` calls it, even if `type` is shadowed
+        env.define("<type>", Rc::new(LoxValue::Callable(type_of)));
+    }
+
+    pub(crate) fn globals(&self) -> &Environment {
+        &self.globals
+    }
+
+    /// Makes failed lookups list the names declared in every scope they searched.
+    pub(crate) fn debug_scopes(mut self, enabled: bool) -> Self {
+        self.debug_scopes = enabled;
+        self
+    }
+
+    /// Makes functions check their returned values against the return type annotation, if
+    /// they have one.
+    pub(crate) fn check_returns(mut self, enabled: bool) -> Self {
+        self.check_returns = enabled;
+        self
+    }
+
+    /// Whether [`check_returns`](Self::check_returns) is enabled.
+    pub(crate) fn checks_returns(&self) -> bool {
+        self.check_returns
+    }
+
+    /// Makes the interpreter record the lines of the statements it executes, see
+    /// [`coverage`](Self::coverage).
+    pub(crate) fn record_coverage(mut self, enabled: bool) -> Self {
+        self.coverage = enabled.then(Default::default);
+        self
     }
 
-    pub fn resolve(&self, expr: &Expr, depth: usize) -> LoxResult<()> {
-        self.locals.borrow_mut().insert(expr.to_owned(), depth);
+    /// Records `stmt` as executed, if coverage is being recorded.
+    pub(crate) fn record(&self, stmt: &Stmt) {
+        if let Some(recorder) = &self.coverage {
+            recorder.record(stmt);
+        }
+    }
+
+    /// The coverage of `statements` by what ran so far, if it's being recorded.
+    pub(crate) fn coverage(&self, statements: &[Stmt]) -> Option<coverage::Coverage> {
+        self.coverage
+            .as_ref()
+            .map(|recorder| recorder.coverage(statements))
+    }
+
+    pub(crate) fn resolve(&self, expr: &Expr, local: Local) -> LoxResult<()> {
+        self.locals.borrow_mut().insert(expr.to_owned(), local);
+        Ok(())
+    }
+
+    /// Where the resolver found the variable `expr` refers to, if it's a local one.
+    pub(crate) fn local(&self, expr: &Expr) -> Option<Local> {
+        self.locals.borrow().get(expr).copied()
+    }
+
+    /// The message of a lookup that failed in `env`, listing the scopes searched if
+    /// [`debug_scopes`](Self::debug_scopes) is enabled.
+    pub(crate) fn lookup_error(&self, env: &Environment, message: String) -> String {
+        if !self.debug_scopes {
+            return message;
+        }
+        env.with_scopes(message)
+    }
+
+    /// Writes `text` wherever the running program writes.
+    pub(crate) fn write(&self, text: &str) -> LoxResult<()> {
+        match self.out.borrow_mut().as_mut() {
+            Some(out) => out.write_all(text.as_bytes())?,
+            None => std::io::stdout().write_all(text.as_bytes())?,
+        }
         Ok(())
     }
 
     /// Executes a list of statements.
-    pub fn interpret(&self) -> Result<(), Vec<LoxError>> {
+    pub(crate) fn interpret(&self) -> Result<(), Vec<LoxError>> {
+        self.run().map(|_| ())
+    }
+
+    /// Executes a list of statements, writing their output to `writer` instead of stdout.
+    pub(crate) fn interpret_with(&self, writer: &'o mut dyn Write) -> Result<(), Vec<LoxError>> {
+        self.interpret_value(writer).map(|_| ())
+    }
+
+    /// Same as [`interpret_with`](Self::interpret_with), but returns the value of the last
+    /// statement if it's an expression statement, or nil otherwise.
+    pub(crate) fn interpret_value(
+        &self,
+        writer: &'o mut dyn Write,
+    ) -> Result<Rc<LoxValue>, Vec<LoxError>> {
+        *self.out.borrow_mut() = Some(writer);
+        self.run()
+    }
+
+    fn run(&self) -> Result<Rc<LoxValue>, Vec<LoxError>> {
         let mut errors = Vec::new();
+        let mut value = Rc::new(LoxValue::Nil);
+        // Names cached by previous runs are never looked up again
+        self.globals.forget_cached();
 
-        for stmt in &self.statements {
-            if let Err(e) = stmt.execute(
-                Rc::clone(&self.globals),
-                &*self.locals.borrow(),
-                &mut std::io::stdout(),
-            ) {
+        for (idx, stmt) in self.statements.iter().enumerate() {
+            let result = match stmt {
+                Stmt::Expression(expr) if idx + 1 == self.statements.len() => {
+                    self.record(stmt);
+                    expr.evaluate(Rc::clone(&self.globals), self)
+                        .map(|val| value = val)
+                }
+                _ => stmt.execute(Rc::clone(&self.globals), self),
+            };
+            if let Err(e) = result {
                 errors.push(e);
             };
         }
@@ -136,6 +671,1752 @@ impl Interpreter {
             return Err(errors);
         }
 
-        Ok(())
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::{error::InterpreterError, lexer::Lexer, optimizer, parser::Parser};
+
+    /// Runs `src` as a program, optimized, returning the output of its top-level statements and
+    /// the global environment it ran in.
+    pub(crate) fn run(src: &str) -> Result<(String, Rc<Environment>), Vec<LoxError>> {
+        run_with(src, |interpreter| interpreter)
+    }
+
+    /// Same as [`run`], with the interpreter set up by `setup` before running.
+    pub(crate) fn run_with(
+        src: &str,
+        setup: impl FnOnce(Interpreter) -> Interpreter,
+    ) -> Result<(String, Rc<Environment>), Vec<LoxError>> {
+        let tokens = Lexer::new(src).scan_tokens().map_err(|e| vec![e])?;
+        let statements = optimizer::optimize(Parser::new(&tokens).parse()?);
+        let interpreter = setup(Interpreter::new(&statements));
+        Resolver::new(&interpreter)
+            .resolve(&statements)
+            .map_err(|e| vec![e])?;
+
+        let mut out = Vec::new();
+        interpreter.interpret_with(&mut out)?;
+        let globals = Rc::clone(&interpreter.globals);
+        Ok((String::from_utf8(out).unwrap(), globals))
+    }
+
+    /// Runs `src` and returns the output of its top-level statements.
+    pub(crate) fn output(src: &str) -> String {
+        run(src).map(|(out, _)| out).unwrap_or_else(|errors| {
+            let errors: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+            panic!("program failed: {:?}", errors)
+        })
+    }
+
+    /// Runs `src` and returns the value of the global `name`.
+    pub(crate) fn global(src: &str, name: &str) -> Rc<LoxValue> {
+        let (_, env) = run(src).unwrap_or_else(|errors| {
+            let errors: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+            panic!("program failed: {:?}", errors)
+        });
+        env.get(name).unwrap()
+    }
+
+    #[test]
+    fn assignment_evaluates_to_the_assigned_value() {
+        assert_eq!(output("let x; print (x = 5); print x;"), "5\n5\n");
+    }
+
+    #[test]
+    fn assignment_is_right_associative() {
+        let src = "let a, b; a = b = 3;";
+        assert_eq!(*global(src, "a"), LoxValue::Integer(3));
+        assert_eq!(*global(src, "b"), LoxValue::Integer(3));
+
+        let src = r#"
+            let res;
+            fn f() {
+                let a, b;
+                a = b = "x";
+                res = a + b;
+            }
+            f();
+        "#;
+        assert_eq!(*global(src, "res"), LoxValue::String("xx".into()));
+    }
+
+    #[test]
+    fn negation_binds_tighter_than_equality() {
+        assert_eq!(
+            output("print !true == false; print !!true == true; print 1 != 2;"),
+            "true\ntrue\ntrue\n"
+        );
+    }
+
+    #[test]
+    fn type_annotations_are_ignored_at_runtime() {
+        let annotated = r#"
+            let x: number = 1;
+            fn add(a: number, b: string): string { return a + b; }
+            print add(x, "a");
+        "#;
+        let plain = r#"
+            let x = 1;
+            fn add(a, b) { return a + b; }
+            print add(x, "a");
+        "#;
+        assert_eq!(output(annotated), output(plain));
+    }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        let src = r#"
+            let calls = 0;
+            fn side(v) { calls = calls + 1; return v; }
+            print nil and side(1);
+            print false or side("b");
+            print 0 and side(2);
+            print "a" or side(3);
+            print side(nil) or side(false) or side(4);
+            print calls;
+        "#;
+        // `and` stops at a falsy operand and `or` at a truthy one, returning it as is
+        assert_eq!(output(src), "nil\nb\n2\na\n4\n5\n");
+    }
+
+    #[test]
+    fn conditionals_evaluate_only_the_chosen_branch() {
+        let src = r#"
+            let calls = 0;
+            fn side(v) { calls = calls + 1; return v; }
+            print true ? side(1) : side(2);
+            print nil ? side(3) : side(4);
+            print calls;
+        "#;
+        assert_eq!(output(src), "1\n4\n2\n");
+        // Right-associative, and looser than `or` and `|>`
+        let src = "let x = 2; print x == 1 ? \"one\" : x == 2 ? \"two\" : \"many\";";
+        assert_eq!(output(src), "two\n");
+        assert_eq!(output("print false or nil ? 1 : 2;"), "2\n");
+        assert_eq!(
+            output("fn id(x) { return x; } print 0 |> id ? 1 : 2;"),
+            "1\n"
+        );
+        // The `then` branch is a full expression, assignments included
+        assert_eq!(output("let x; true ? x = 1 : 2; print x;"), "1\n");
+    }
+
+    /// Every construct that tests a condition agrees with [`LoxValue::is_truthy`].
+    #[test]
+    fn truthiness_is_the_same_across_constructs() {
+        let values = [
+            ("nil", false),
+            ("false", false),
+            ("true", true),
+            ("0", true),
+            ("0.0", true),
+            ("nan", true),
+            (r#""""#, true),
+            (r#""false""#, true),
+            ("[]", true),
+            ("clock", true),
+            ("C", true),
+            ("C()", true),
+            ("E.A", true),
+        ];
+        // Each construct sets `truthy` to whether it took `v` as true
+        let constructs = [
+            "let truthy = false; if (v) { truthy = true; }",
+            "let truthy = true; if (v) {} else { truthy = false; }",
+            "let truthy = false; w: { while (v) { truthy = true; break w; } }",
+            "let truthy = !!v;",
+            "let truthy = false; v and (truthy = true);",
+            "let truthy = true; v or (truthy = false);",
+            "let truthy = v ? true : false;",
+        ];
+        for (value, expected) in values {
+            let prelude = format!("class C {{}} enum E {{ A }} let v = {};", value);
+            assert_eq!(global(&prelude, "v").is_truthy(), expected, "{}", value);
+            for construct in constructs {
+                let src = format!("{} {}", prelude, construct);
+                assert_eq!(
+                    *global(&src, "truthy"),
+                    LoxValue::Boolean(expected),
+                    "`{}` with `{}`",
+                    construct,
+                    value
+                );
+            }
+            let asserted = run(&format!("{} assert v;", prelude)).is_ok();
+            assert_eq!(asserted, expected, "`assert` with `{}`", value);
+        }
+    }
+
+    #[test]
+    fn operator_errors_point_at_the_operator() {
+        colored::control::set_override(false);
+        let rendered = |src: &'static str| {
+            let error = run(src).unwrap_err().remove(0);
+            InterpreterError::from(error, src).to_string()
+        };
+        let src = r#"let x = "a" - 1;"#;
+        assert!(
+            rendered(src).ends_with("1 |\tlet x = \"a\" - 1;\n  |\t            ^"),
+            "{}",
+            rendered(src)
+        );
+        // Also within a folded chain of concatenations
+        let src = r#"let x = nil; let y = x + x + "a";"#;
+        assert!(rendered(src).contains(":1:24\n"), "{}", rendered(src));
+    }
+
+    #[test]
+    fn cached_globals_see_later_assignments() {
+        let src = "
+            let g = 1;
+            fn f() { return g; }
+            let a = f();
+            g = 2;
+            let b = f();
+            let g = [3];
+            let c = f();
+            g[0] = 4;
+            let h = f();
+            let d = h[0];
+        ";
+        assert_eq!(*global(src, "a"), LoxValue::Integer(1));
+        assert_eq!(*global(src, "b"), LoxValue::Integer(2));
+        assert_eq!(global(src, "c").to_string(), "[3]");
+        assert_eq!(*global(src, "d"), LoxValue::Integer(4));
+
+        // Globals redefined from another source share the cell
+        let env = Environment::new();
+        env.define("g", Rc::new(LoxValue::Integer(1)));
+        let name = Lexer::new("g").scan_tokens().unwrap().remove(0);
+        assert_eq!(*env.get_global(&name).unwrap(), LoxValue::Integer(1));
+        env.define("g", Rc::new(LoxValue::Integer(2)));
+        assert_eq!(*env.get_global(&name).unwrap(), LoxValue::Integer(2));
+        env.assign("g", &LoxValue::Integer(3)).unwrap();
+        assert_eq!(*env.get_global(&name).unwrap(), LoxValue::Integer(3));
+    }
+
+    #[test]
+    fn functions_without_a_return_yield_nil() {
+        assert_eq!(
+            *global("fn f() { 1 + 2; } let x = f();", "x"),
+            LoxValue::Nil
+        );
+        assert_eq!(*global("fn f() {} let x = f();", "x"), LoxValue::Nil);
+        let src = "fn f(a) { if (a) { return 1; } let b = 2; } let x = f(false);";
+        assert_eq!(*global(src, "x"), LoxValue::Nil);
+        assert_eq!(output("fn f() { 1 + 2; } print f();"), "nil\n");
+    }
+
+    #[test]
+    fn stray_semicolons_are_empty_statements() {
+        assert_eq!(output(";;print 1;;"), "1\n");
+        assert_eq!(output("fn f() { ; return 2;; } ; print f();"), "2\n");
+    }
+
+    #[test]
+    fn locals_resolve_through_shadowing() {
+        let src = r#"
+            let a = "global";
+            {
+                let a = "outer";
+                {
+                    let b = "unused";
+                    let a = "inner";
+                    print a;
+                }
+                print a;
+            }
+            print a;
+        "#;
+        assert_eq!(output(src), "inner\nouter\nglobal\n");
+    }
+
+    #[test]
+    fn closures_keep_their_resolved_binding() {
+        let src = r#"
+            let a = "global";
+            {
+                fn show() { return a; }
+                print show();
+                let a = "block";
+                print show();
+                print a;
+            }
+        "#;
+        assert_eq!(output(src), "global\nglobal\nblock\n");
+
+        let src = r#"
+            fn counter() {
+                let count = 0;
+                fn inc() { count = count + 1; return count; }
+                return inc;
+            }
+            let c = counter();
+            c();
+            print c();
+        "#;
+        assert_eq!(output(src), "2\n");
+    }
+
+    #[test]
+    fn redeclaring_a_local_reuses_its_slot() {
+        let src = r#"
+            fn f() {
+                let a = 1;
+                let b = 2;
+                let a = 3;
+                return a * 10 + b;
+            }
+            print f();
+        "#;
+        assert_eq!(output(src), "32\n");
+    }
+
+    #[test]
+    fn break_exits_the_labeled_block() {
+        let src = r#"
+            let x = 1;
+            done: {
+                print "before";
+                if x == 1 { break done; }
+                print "skipped";
+            }
+            print "after";
+        "#;
+        assert_eq!(output(src), "before\nafter\n");
+    }
+
+    #[test]
+    fn break_targets_the_named_label() {
+        let src = r#"
+            outer: {
+                inner: {
+                    break outer;
+                }
+                print "skipped";
+            }
+            outer: {
+                inner: {
+                    break inner;
+                }
+                print "reached";
+            }
+        "#;
+        assert_eq!(output(src), "reached\n");
+    }
+
+    #[test]
+    fn round_to_rounds_to_decimal_places() {
+        assert_eq!(
+            *global("let x = round_to(1.23456, 3);", "x"),
+            LoxValue::Decimal(1.235)
+        );
+        assert_eq!(
+            *global("let x = round_to(2.5, 0);", "x"),
+            LoxValue::Decimal(3.0)
+        );
+        assert_eq!(
+            *global("let x = round_to(7, 0);", "x"),
+            LoxValue::Decimal(7.0)
+        );
+        assert_eq!(
+            *global("let x = round_to(1234.5, -2);", "x"),
+            LoxValue::Decimal(1200.0)
+        );
+        assert_eq!(output("print round_to(3.14159, 2) == 3.14;"), "true\n");
+    }
+
+    #[test]
+    fn round_to_handles_places_past_the_range_of_floats() {
+        let value = |src| global(src, "x").to_string();
+        assert_eq!(value("let x = round_to(1.5, 400);"), "1.5");
+        assert_eq!(
+            value("let x = round_to(10000000000.0, 300);"),
+            "10000000000"
+        );
+        assert_eq!(value("let x = round_to(1234.5, -400);"), "0");
+    }
+
+    #[test]
+    fn round_to_validates_its_arguments() {
+        assert!(run(r#"round_to("3.1", 1);"#).is_err());
+        assert!(run("round_to(3.1, 1.5);").is_err());
+    }
+
+    #[test]
+    fn repeated_string_literals_share_their_storage() {
+        let (_, env) = run(r#"let a = "same"; let b = "same"; let c = "other";"#).unwrap();
+        let string = |name| match &*env.get(name).unwrap() {
+            LoxValue::String(s) => Rc::clone(s),
+            _ => unreachable!(),
+        };
+        assert!(Rc::ptr_eq(&string("a"), &string("b")));
+        assert!(!Rc::ptr_eq(&string("a"), &string("c")));
+
+        let src = r#"let i = 0; while (i < 1000) { print "same"; i = i + 1; }"#;
+        assert_eq!(output(src), "same\n".repeat(1000));
+    }
+
+    #[test]
+    fn unicode_names_can_be_declared_and_used() {
+        let src = "let café = 2; fn λ(x) { return x * café; } let résultat = λ(21);";
+        assert!(matches!(*global(src, "résultat"), LoxValue::Integer(42)));
+    }
+
+    const PIPELINE: &str = "
+        fn double(x) { return x * 2; }
+        fn sub(x, y) { return x - y; }
+        class Math { static neg(x) { return -x; } }
+    ";
+
+    #[test]
+    fn pipes_call_the_right_hand_side_with_the_left_one() {
+        let value = |src| global(&format!("{}let x = {};", PIPELINE, src), "x").to_string();
+        assert_eq!(value("3 |> double"), "6");
+        assert_eq!(value("3 |> double()"), "6");
+        assert_eq!(value("3 |> sub(1)"), "2");
+        assert_eq!(value("3 |> double |> sub(1) |> Math.neg"), "-5");
+        // Pipes bind looser than any other operator
+        assert_eq!(value("1 + 2 |> double"), "6");
+        assert_eq!(value("2 * 3 |> sub(1)"), "5");
+    }
+
+    #[test]
+    fn pipes_need_a_callable_right_hand_side() {
+        assert!(run("let x = 1 |> 2;").is_err());
+    }
+
+    #[test]
+    fn returns_can_be_checked_against_their_annotation() {
+        let src = r#"
+            fn double(x: number): number {
+                if x < 0 { return "negative"; }
+                return x * 2;
+            }
+            let four = double(2);
+        "#;
+        let violation = format!("{}{}", src, "double(-1);");
+        assert!(run(&violation).is_ok());
+
+        let checked = |src| run_with(src, |interpreter| interpreter.check_returns(true));
+        let four = checked(src).map(|(_, env)| env.get("four").unwrap());
+        let errors = checked(&violation).unwrap_err();
+        let implicit = checked("fn f(): number { } f();").unwrap_err();
+        let unannotated = checked("fn f() { } fn g(): number { return 1; } f(); g();");
+
+        assert_eq!(
+            implicit[0].to_string(),
+            "mismatched return type: expected `number`, found `nil`"
+        );
+        assert!(unannotated.is_ok());
+
+        assert!(matches!(*four.unwrap(), LoxValue::Integer(4)));
+        assert_eq!(
+            errors[0].to_string(),
+            "mismatched return type: expected `number`, found `string`"
+        );
+    }
+
+    #[test]
+    fn failed_lookups_can_list_the_scopes_searched() {
+        let src = "
+            let top = 0;
+            fn f(a) {
+                let b = 1;
+                {
+                    let c = 2;
+                    let d = 3;
+                    return missing;
+                }
+            }
+            f(1);
+        ";
+        let error = |src| run(src).unwrap_err()[0].to_string();
+        assert_eq!(error(src), "`missing` is not defined");
+
+        let errors = run_with(src, |interpreter| interpreter.debug_scopes(true)).unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "`missing` is not defined
+scopes searched, innermost first:
+    0: c, d
+    1: b
+    2: a
+    3: (empty)
+    global: arity, assert_throws, center, clock, f, format, len, pad_left, pad_right, pprint, read, round_to, top, type"
+        );
+        // The option belongs to the interpreter it was set on
+        assert_eq!(error(src), "`missing` is not defined");
+    }
+
+    #[test]
+    fn failed_asserts_report_their_condition() {
+        assert!(run("let x = 1; assert x == 1; assert x;").is_ok());
+
+        let src = r#"fn f(a, b) { return nil; } let x = 1; assert f(x, "y") or x+1 == 3;"#;
+        let errors = run(src).unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            r#"assertion failed: `f(x, "y") or x+1 == 3`"#
+        );
+    }
+
+    #[test]
+    fn constants_cannot_be_assigned_to() {
+        assert_eq!(output("const N = 10; print N * 2;"), "20\n");
+        assert!(run("const N = 10; N = 11;").is_err());
+        assert!(run("{ const N = 10; fn f() { N = 11; } }").is_err());
+        assert!(run("const N = 10; fn f(N) { N = 11; }").is_ok());
+        assert!(run("const N = 10; let N = 1; N = 2;").is_err());
+    }
+
+    #[test]
+    fn integral_decimals_can_index_arrays() {
+        let x = global("let arr = [1, 2, 3, 4]; let x = arr[3.0];", "x");
+        assert!(matches!(*x, LoxValue::Integer(4)));
+    }
+
+    #[test]
+    fn fractional_or_huge_indices_are_errors() {
+        let error = |src| run(src).unwrap_err()[0].to_string();
+        assert!(error("let arr = [1, 2, 3, 4]; arr[3.5];").contains("expected an integer, got 3.5"));
+        assert!(
+            error("let arr = [1]; arr[100000000000000000000.0];").contains("expected an integer")
+        );
+        assert!(error("let arr = [1]; arr[0.5] = 2;").contains("expected an integer, got 0.5"));
+    }
+
+    #[test]
+    fn indexes_any_array_expression() {
+        let src = r#"
+            fn f() { return [[1, 2], [3]]; }
+            class C { init() { this.items = ["a", "b"]; } }
+            let arr = [10, 20];
+            fn local() { let arr = [30]; return arr[0]; }
+            print arr[1];
+            print f()[1][0];
+            print C().items[arr[0] - 9];
+            print [7, 8, 9][2];
+            print local();
+        "#;
+        assert_eq!(output(src), "20\n3\nb\n9\n30\n");
+    }
+
+    #[test]
+    fn index_assignments_mutate_the_shared_array() {
+        let src = r#"
+            let a = [1, 2, 3];
+            let b = a;
+            b[0] = 42;
+            fn set(arr, i, v) { arr[i] = v; }
+            set(a, 1, "x");
+            let nested = [a];
+            nested[0][2] = 5;
+            let res = (a[3] = 4);
+        "#;
+        assert_eq!(
+            output(&format!("{} print a; print b;", src)),
+            "[42, x, 5, 4]\n[42, x, 5, 4]\n"
+        );
+        assert_eq!(*global(src, "res"), LoxValue::Integer(4));
+    }
+
+    #[test]
+    fn index_assignments_past_the_end_are_errors() {
+        colored::control::set_override(false);
+        let src = "let a = [];\na[0] = 1;\na[2] = 3;";
+        let error = run(src).unwrap_err().remove(0);
+        let err = crate::error::InterpreterError::from(error, src).to_string();
+        assert!(
+            err.contains("the length is 1 but the index is 2"),
+            "{}",
+            err
+        );
+        assert!(err.contains(":3:3\n"), "{}", err);
+        assert!(run("let a = [1]; a[-1] = 0;").is_err());
+    }
+
+    #[test]
+    fn out_of_bounds_and_unindexable_accesses_are_errors() {
+        colored::control::set_override(false);
+        let error = |src: &str| {
+            let error = run(src).unwrap_err().remove(0);
+            crate::error::InterpreterError::from(error, src).to_string()
+        };
+        let err = error("let arr = [1, 2];\nprint arr[1 + 1];");
+        assert!(
+            err.contains("the length is 2 but the index is 2"),
+            "{}",
+            err
+        );
+        assert!(err.contains(":2:11\n"), "{}", err);
+        let err = error("let arr = [1, 2];\nprint arr[-1];");
+        assert!(
+            err.contains("the length is 2 but the index is -1"),
+            "{}",
+            err
+        );
+        let err = error("let s = \"ab\";\nprint s[0];");
+        assert!(
+            err.contains("cannot index `string`, only arrays can be indexed"),
+            "{}",
+            err
+        );
+    }
+
+    const RESOURCE: &str = "
+        let closed = 0;
+        class Resource {
+            close() { closed = closed + 1; }
+        }
+    ";
+
+    #[test]
+    fn with_closes_the_resource_after_the_block() {
+        let src = "with Resource() as r { closed = closed * 10; }";
+        let closed = global(&format!("{}{}", RESOURCE, src), "closed");
+        assert!(matches!(*closed, LoxValue::Integer(1)));
+    }
+
+    #[test]
+    fn with_closes_the_resource_on_early_return() {
+        let src = "
+            fn use() {
+                with Resource() as r { return closed; }
+            }
+            let before = use();
+        ";
+        let env = run(&format!("{}{}", RESOURCE, src)).unwrap().1;
+        assert!(matches!(*env.get("before").unwrap(), LoxValue::Integer(0)));
+        assert!(matches!(*env.get("closed").unwrap(), LoxValue::Integer(1)));
+    }
+
+    #[test]
+    fn with_closes_the_resource_on_error() {
+        let src = format!("{}with Resource() as r {{ nil + 1; }}", RESOURCE);
+        let tokens = Lexer::new(&src).scan_tokens().unwrap();
+        let statements = Parser::new(&tokens).parse().unwrap();
+        let interpreter = Interpreter::new(&statements);
+        Resolver::new(&interpreter).resolve(&statements).unwrap();
+
+        let mut out = Vec::new();
+
+        let errors = interpreter.interpret_with(&mut out).unwrap_err();
+        assert!(errors[0]
+            .to_string()
+            .contains("operands must be number or string"));
+        let closed = interpreter.globals.get("closed").unwrap();
+        assert!(matches!(*closed, LoxValue::Integer(1)));
+    }
+
+    #[test]
+    fn with_requires_a_close_method() {
+        assert!(run("with 1 as r {}").is_err());
+        assert!(run("class Open {} with Open() as r {}").is_err());
+        assert!(run("class Bad { close(x) {} } with Bad() as r {}").is_err());
+    }
+
+    #[test]
+    fn arity_counts_declared_parameters() {
+        let src = "fn add(a, b) { return a + b; } let x = arity(add);";
+        assert!(matches!(*global(src, "x"), LoxValue::Integer(2)));
+        assert!(matches!(
+            *global("let x = arity(clock);", "x"),
+            LoxValue::Integer(0)
+        ));
+        assert!(matches!(
+            *global("let x = arity(arity);", "x"),
+            LoxValue::Integer(1)
+        ));
+    }
+
+    #[test]
+    fn hoisted_loop_invariants_keep_the_results() {
+        let src = "let i = 0; let n = 3; let sum = 0;
+            while i < n * 2 { sum = sum + i; i = i + 1; }";
+        assert_eq!(*global(src, "sum"), LoxValue::Integer(15));
+
+        // Calls are evaluated on every iteration
+        let src = "let calls = 0; fn limit() { calls = calls + 1; return 3; }
+            let n = 2;
+            for (let i = 0; i < limit() and i < n * 2; i = i + 1) {}";
+        assert_eq!(*global(src, "calls"), LoxValue::Integer(4));
+    }
+
+    #[test]
+    fn repeat_runs_its_body_count_times() {
+        let src = "let n = 0; repeat 3 { n = n + 1; }";
+        assert_eq!(*global(src, "n"), LoxValue::Integer(3));
+        let src = "let n = 0; repeat 0 { n = n + 1; }";
+        assert_eq!(*global(src, "n"), LoxValue::Integer(0));
+        let src = "let n = 0; repeat 2 { repeat 3 { n = n + 1; } }";
+        assert_eq!(*global(src, "n"), LoxValue::Integer(6));
+        let src = "let n = 0; out: { repeat 5 { n = n + 1; if n == 2 { break out; } } }";
+        assert_eq!(*global(src, "n"), LoxValue::Integer(2));
+    }
+
+    #[test]
+    fn repeat_evaluates_its_count_once() {
+        let src = "let n = 0; let calls = 0;
+            fn count() { calls = calls + 1; return 4; }
+            repeat count() { n = n + 1; }";
+        assert_eq!(*global(src, "n"), LoxValue::Integer(4));
+        assert_eq!(*global(src, "calls"), LoxValue::Integer(1));
+    }
+
+    #[test]
+    fn match_type_branches_on_the_type_name() {
+        let describe = r#"
+            class Point {}
+            enum Color { Red }
+            fn describe(x) {
+                match type(x) {
+                    number => { return "number"; },
+                    string => { return "string"; },
+                    nil => { return "nothing"; }
+                    fn => { return "callable"; }
+                    Point => { return "point"; }
+                    Color => { return "color"; }
+                    else => { return "other " + type(x); }
+                }
+            }
+        "#;
+        let cases = [
+            ("1", "number"),
+            ("2.5", "number"),
+            (r#""a""#, "string"),
+            ("nil", "nothing"),
+            ("clock", "callable"),
+            ("Point", "callable"),
+            ("Point()", "point"),
+            ("Color.Red", "color"),
+            ("true", "other bool"),
+            ("[1]", "other array"),
+        ];
+        for (value, expected) in cases {
+            let src = format!("{} let x = describe({});", describe, value);
+            assert_eq!(global(&src, "x").to_string(), expected, "{}", value);
+        }
+
+        // Without an `else` arm nothing runs, and `type` may be shadowed
+        let src = "let x = 0; let type = nil; match type(true) { number => { x = 1; } }";
+        assert_eq!(*global(src, "x"), LoxValue::Integer(0));
+    }
+
+    #[test]
+    fn match_type_rejects_misplaced_arms() {
+        assert!(run("match type(1) { else => {} number => {} }").is_err());
+        assert!(run("match type(1) { number => {} number => {} }").is_err());
+        assert!(run("match (1) { number => {} }").is_err());
+        assert!(run("match type(1) { number => print 1; }").is_err());
+    }
+
+    #[test]
+    fn loops_evaluate_to_the_value_they_break_with() {
+        assert_eq!(
+            *global("let x = loop { break 5; };", "x"),
+            LoxValue::Integer(5)
+        );
+        let src = "
+            let i = 0;
+            let x = loop {
+                i = i + 1;
+                if (i == 3) { break i * 10; }
+            };
+        ";
+        assert_eq!(*global(src, "x"), LoxValue::Integer(30));
+        let src = "let i = 0; let x = loop { i = i + 1; if (i > 1) { break (i); } };";
+        assert_eq!(*global(src, "x"), LoxValue::Integer(2));
+        assert_eq!(*global("let x = loop { break; };", "x"), LoxValue::Nil);
+
+        let src = "
+            let i = 0;
+            loop {
+                i = i + 1;
+                print i;
+                if (i == 2) { break; }
+            }
+            fn first_over(limit) {
+                let n = 1;
+                loop { n = n * 2; if (n > limit) { return n; } }
+            }
+            print first_over(10);
+            out: { loop { break out; } print \"unreachable\"; }
+        ";
+        assert_eq!(output(src), "1\n2\n16\n");
+    }
+
+    #[test]
+    fn break_exits_the_innermost_loop_at_once() {
+        let src = r#"
+            let i = 0;
+            while (true) {
+                i = i + 1;
+                if (i == 3) { break; }
+                print i;
+            }
+            for (let j = 0; j < 3; j = j + 1) {
+                for (let k = 0; k < 3; k = k + 1) {
+                    if (k == 1) { break; }
+                    print "" + j + k;
+                }
+            }
+            repeat 5 { print "once"; break; }
+            let x = loop { while (true) { break; } break 7; };
+            print x;
+        "#;
+        assert_eq!(output(src), "1\n2\n00\n10\n20\nonce\n7\n");
+    }
+
+    #[test]
+    fn continue_skips_to_the_next_iteration() {
+        let src = r#"
+            let sum = 0;
+            for (let i = 0; i < 10; i = i + 1) {
+                if (i % 2 == 1) { continue; }
+                sum = sum + i;
+            }
+            print sum;
+            let i = 0;
+            while (i < 4) {
+                i = i + 1;
+                if (i == 2) { continue; }
+                print i;
+            }
+            let n = 0;
+            repeat 3 { n = n + 1; continue; print "unreachable"; }
+            print n;
+            let x = loop { n = n + 1; if (n < 6) { continue; } break (n); };
+            print x;
+        "#;
+        assert_eq!(output(src), "20\n1\n3\n4\n3\n6\n");
+    }
+
+    #[test]
+    fn for_each_iterates_arrays_and_ranges() {
+        let src = r#"
+            for x in [1, "two", 3] { print x; }
+            let sum = 0;
+            for i in 0..5 { sum = sum + i; }
+            print sum;
+            for i in 3..3 { print "never"; }
+            let r = 1..3;
+            print r;
+            for i in r {
+                if (i == 1) { continue; }
+                print i;
+            }
+            for i in 0..100 { if (i == 2) { break; } print i; }
+        "#;
+        assert_eq!(output(src), "1\ntwo\n3\n10\n1..3\n2\n0\n1\n");
+    }
+
+    #[test]
+    fn for_each_binds_a_fresh_variable_every_iteration() {
+        let src = r#"
+            let fns = [];
+            for i in 0..3 {
+                fn get() { return i; }
+                fns[i] = get;
+            }
+            print fns[0]() + fns[1]() * 10 + fns[2]() * 100;
+            let a = [1, 2];
+            for x in a { a[len(a)] = x; }
+            print len(a);
+        "#;
+        assert_eq!(output(src), "210\n4\n");
+    }
+
+    #[test]
+    fn only_arrays_and_ranges_can_be_iterated() {
+        let error = |src| run(src).unwrap_err()[0].to_string();
+        assert_eq!(
+            error(r#"for c in "abc" {}"#),
+            "cannot iterate over `string`, only arrays and ranges can be iterated"
+        );
+        assert_eq!(error("for i in 0..1.5 {}"), "expected an integer, got 1.5");
+    }
+
+    #[test]
+    fn continue_needs_an_enclosing_loop() {
+        let error = |src| run(src).unwrap_err()[0].to_string();
+        assert_eq!(
+            error("continue;"),
+            "cannot use `continue` outside of a loop"
+        );
+        assert_eq!(
+            error("while (true) { fn f() { continue; } }"),
+            "cannot use `continue` outside of a loop"
+        );
+    }
+
+    #[test]
+    fn break_values_need_an_enclosing_loop() {
+        let error = |src| run(src).unwrap_err()[0].to_string();
+        assert_eq!(
+            error("break 1;"),
+            "cannot use `break` with a value outside of a `loop`"
+        );
+        assert_eq!(
+            error("loop { fn f() { break; } }"),
+            "cannot use `break` without a label outside of a loop"
+        );
+        assert_eq!(
+            error("loop { while (true) { break 1; } }"),
+            "cannot use `break` with a value outside of a `loop`"
+        );
+        assert_eq!(
+            error("while (true) { fn f() { break; } }"),
+            "cannot use `break` without a label outside of a loop"
+        );
+        assert_eq!(
+            error("while (true) { let r = 1; break r; }"),
+            "use of undeclared label `r`"
+        );
+    }
+
+    #[test]
+    fn breaking_with_a_name_is_a_label_only_if_one_encloses_it() {
+        let src = "let x = loop { let r = 1; break r; };";
+        assert_eq!(*global(src, "x"), LoxValue::Integer(1));
+        let src = "let x = 0; r: { x = loop { let r = 1; break r; }; } x = x + 1;";
+        assert_eq!(*global(src, "x"), LoxValue::Integer(1));
+        let src = "let x = 0; r: { fn f() { return loop { let r = 2; break r; }; } x = f(); }";
+        assert_eq!(*global(src, "x"), LoxValue::Integer(2));
+    }
+
+    #[test]
+    fn repeat_rejects_invalid_counts() {
+        for count in &["-1", "1.5", "\"3\"", "nil"] {
+            let errors = run(&format!("repeat {} {{}}", count)).unwrap_err();
+            assert!(
+                errors[0]
+                    .to_string()
+                    .contains("repeat count must be a non-negative integer"),
+                "{}",
+                count
+            );
+        }
+
+        colored::control::set_override(false);
+        let src = "let n = 2; repeat n - 3 {}";
+        let error = run(src).unwrap_err().remove(0);
+        let rendered = InterpreterError::from(error, src).to_string();
+        assert!(
+            rendered.ends_with("1 |\tlet n = 2; repeat n - 3 {}\n  |\t                  ^^^^^"),
+            "{}",
+            rendered
+        );
+    }
+
+    #[test]
+    fn nan_and_inf_literals() {
+        assert_eq!(
+            output("print nan == nan; print nan != nan; print inf > 10000000000.0;"),
+            "false\ntrue\ntrue\n"
+        );
+        assert_eq!(
+            output("print nan + 1; print -inf; print 1 / inf;"),
+            "nan\n-inf\n0\n"
+        );
+        // Expressions holding `nan` can still be resolved
+        let src = "fn f() { let x = 1; x = nan; return x; } let y = f();";
+        assert!(matches!(*global(src, "y"), LoxValue::Decimal(d) if d.is_nan()));
+    }
+
+    #[test]
+    fn deferred_statements_run_last_first() {
+        let src = r#"
+            let log = "";
+            fn f() {
+                defer log = log + "1";
+                defer { log = log + "2"; }
+                log = log + "body ";
+            }
+            f();
+        "#;
+        assert_eq!(*global(src, "log"), LoxValue::String("body 21".into()));
+
+        // Statements deferred while running deferred ones still run
+        let src = r#"
+            let log = "";
+            fn f() { defer { defer log = log + "b"; log = log + "a"; } }
+            f();
+        "#;
+        assert_eq!(*global(src, "log"), LoxValue::String("ab".into()));
+    }
+
+    #[test]
+    fn deferred_statements_belong_to_their_call() {
+        // Each call runs its own, even those deferred by calls made from deferred statements
+        let src = r#"
+            fn inner(n) { defer { print "inner " + n; } print "body " + n; }
+            fn outer() {
+                defer inner(2);
+                inner(1);
+                print "outer";
+            }
+            outer();
+        "#;
+        assert_eq!(output(src), "body 1\ninner 1\nouter\nbody 2\ninner 2\n");
+        // A closure defers in its own call, not in the one it was declared in
+        let src = r#"
+            fn outer() {
+                fn closure() { defer { print "closure"; } }
+                closure();
+                print "outer";
+            }
+            outer();
+        "#;
+        assert_eq!(output(src), "closure\nouter\n");
+    }
+
+    #[test]
+    fn deferred_statements_run_on_early_returns() {
+        let src = r#"
+            let log = "";
+            fn f(early) {
+                defer log = log + "deferred ";
+                while true {
+                    if early { return 1; }
+                    log = log + "late ";
+                    return 2;
+                }
+            }
+            let a = f(true);
+            let b = f(false);
+        "#;
+        assert_eq!(
+            *global(src, "log"),
+            LoxValue::String("deferred late deferred ".into())
+        );
+        assert_eq!(*global(src, "a"), LoxValue::Integer(1));
+        assert_eq!(*global(src, "b"), LoxValue::Integer(2));
+
+        // The returned value is evaluated before the deferred statements run, unless they
+        // return a value themselves
+        let src = "fn f() { let x = 1; defer x = 2; return x; } let x = f();";
+        assert_eq!(*global(src, "x"), LoxValue::Integer(1));
+        let src = "fn f() { defer { return 2; } return 1; } let x = f();";
+        assert_eq!(*global(src, "x"), LoxValue::Integer(2));
+    }
+
+    #[test]
+    fn deferred_statements_run_when_a_function_fails() {
+        let src = r#"
+            let log = "";
+            fn f() { defer log = log + "cleanup"; nil + 1; }
+            f();
+        "#;
+        let tokens = Lexer::new(src).scan_tokens().unwrap();
+        let statements = Parser::new(&tokens).parse().unwrap();
+        let interpreter = Interpreter::new(&statements);
+        Resolver::new(&interpreter).resolve(&statements).unwrap();
+        let mut out = Vec::new();
+        assert!(interpreter.interpret_with(&mut out).is_err());
+        assert_eq!(
+            *interpreter.globals.get("log").unwrap(),
+            LoxValue::String("cleanup".into())
+        );
+    }
+
+    #[test]
+    fn defer_needs_an_enclosing_function() {
+        assert!(run("defer { print 1; }").is_err());
+        assert!(run("{ defer { print 1; } }").is_err());
+        assert!(run("fn f() { out: { defer { break out; } } }").is_err());
+    }
+
+    #[test]
+    fn static_variables_persist_across_calls() {
+        let src = r#"
+            let inits = 0;
+            fn next() {
+                static let count = inits = inits + 1;
+                count = count + 1;
+                return count;
+            }
+            let a = next();
+            let b = next();
+            let c = next();
+        "#;
+        assert_eq!(*global(src, "a"), LoxValue::Integer(2));
+        assert_eq!(*global(src, "b"), LoxValue::Integer(3));
+        assert_eq!(*global(src, "c"), LoxValue::Integer(4));
+        assert_eq!(*global(src, "inits"), LoxValue::Integer(1));
+
+        // Every instance shares the static variables of a method
+        let src = r#"
+            class Counter {
+                next() {
+                    static let count = 0;
+                    count = count + 1;
+                    return count;
+                }
+            }
+            Counter().next();
+            let x = Counter().next();
+        "#;
+        assert_eq!(*global(src, "x"), LoxValue::Integer(2));
+    }
+
+    #[test]
+    fn static_variables_belong_at_the_top_of_a_function() {
+        assert!(run("static let x = 1;").is_err());
+        assert!(run("fn f() { { static let x = 1; } }").is_err());
+        assert!(run("fn f() { let x = 1; static let x = 2; }").is_err());
+    }
+
+    #[test]
+    fn pads_values_to_a_width() {
+        let src =
+            r#"let x = [pad_left(42, 5), pad_right("ab", 4), center("ab", 5), center(1, 3)];"#;
+        assert_eq!(global(src, "x").to_string(), "[   42, ab  ,  ab  ,  1 ]");
+        let src = r#"let x = [pad_left("wide", 2), pad_right("wide", 4), center("é", 0)];"#;
+        assert_eq!(global(src, "x").to_string(), "[wide, wide, é]");
+        assert!(run(r#"pad_left("a", -1);"#).is_err());
+        assert!(run(r#"pad_right("a", 1.5);"#).is_err());
+    }
+
+    #[test]
+    fn clock_returns_seconds() {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs_f64();
+        let t = match *global("let t = clock();", "t") {
+            LoxValue::Decimal(t) => t,
+            ref oth => panic!("expected a decimal, got {:?}", oth),
+        };
+        assert!((t - now).abs() < 60.0, "{} is not close to {}", t, now);
+        assert_eq!(output("print clock;"), "<native fn clock>\n");
+        let errors = run("clock(1);").unwrap_err();
+        assert_eq!(errors[0].to_string(), "expected 0 arguments but got 1");
+    }
+
+    #[test]
+    fn assert_throws_passes_only_on_runtime_errors() {
+        let src = "
+            fn divide() { return 1 / 0; }
+            let msg = assert_throws(divide);
+        ";
+        assert_eq!(global(src, "msg").to_string(), "attempt to divide by zero");
+
+        let err = run("fn one() { return 1; } assert_throws(one);").unwrap_err();
+        assert!(err[0].to_string().contains("returned `1`"), "{}", err[0]);
+        // A `return` inside the function isn't an error to catch
+        let err = run("fn f() { if (true) { return nil; } } assert_throws(f);").unwrap_err();
+        assert!(err[0].to_string().contains("returned `nil`"), "{}", err[0]);
+        assert!(run("fn f(x) { x / 0; } assert_throws(f);").is_err());
+        assert!(run("assert_throws(1);").is_err());
+    }
+
+    #[test]
+    fn format_substitutes_placeholders() {
+        let src = r#"let x = format("{} + {} = {}", 1, 2.5, "3.5");"#;
+        assert_eq!(*global(src, "x"), LoxValue::String("1 + 2.5 = 3.5".into()));
+        let src = r#"let x = format("{1}{0}{1}", "a", "b");"#;
+        assert_eq!(*global(src, "x"), LoxValue::String("bab".into()));
+        let src = r#"let x = format("{{{}}} }}{{", nil);"#;
+        assert_eq!(*global(src, "x"), LoxValue::String("{nil} }{".into()));
+        assert_eq!(
+            *global(r#"let x = format("plain");"#, "x"),
+            LoxValue::String("plain".into())
+        );
+    }
+
+    #[test]
+    fn format_rejects_missing_arguments_and_stray_braces() {
+        assert!(run(r#"format("{} {}", 1);"#).is_err());
+        assert!(run(r#"format("{2}", 1, 2);"#).is_err());
+        assert!(run(r#"format("{");"#).is_err());
+        assert!(run(r#"format("}");"#).is_err());
+        let error = |src| run(src).unwrap_err()[0].to_string();
+        assert_eq!(
+            error(r#"format("{x}", 1);"#),
+            "format: invalid placeholder `{x}`, expected `{}` or an index"
+        );
+        assert_eq!(
+            error(r#"format("{x", 1);"#),
+            "format: unterminated placeholder, use `{{` for a literal brace"
+        );
+        assert!(run("format();").is_err());
+        assert!(run("format(1);").is_err());
+    }
+
+    #[test]
+    fn arguments_are_evaluated_before_checking_the_arity() {
+        let src = "let calls = 0;
+            fn bump() { calls = calls + 1; return calls; }
+            fn one(a) {}
+            one(bump(), bump(), bump());
+            let called = calls;
+            (calls = calls + 10)(bump());";
+        let tokens = Lexer::new(src).scan_tokens().unwrap();
+        let statements = Parser::new(&tokens).parse().unwrap();
+        let interpreter = Interpreter::new(&statements);
+        Resolver::new(&interpreter).resolve(&statements).unwrap();
+
+        let mut out = Vec::new();
+
+        let errors = interpreter.interpret_with(&mut out).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0]
+            .to_string()
+            .contains("expected 1 arguments but got 3"));
+        assert!(errors[1].to_string().contains("can only call functions"));
+        let globals = &interpreter.globals;
+        assert_eq!(*globals.get("called").unwrap(), LoxValue::Integer(3));
+        // The callee runs before the arguments, even if it can't be called
+        assert_eq!(*globals.get("calls").unwrap(), LoxValue::Integer(14));
+    }
+
+    #[test]
+    fn arity_rejects_non_callables() {
+        assert!(run("arity(1);").is_err());
+        assert!(run(r#"arity("add");"#).is_err());
+    }
+
+    #[test]
+    fn len_counts_characters_and_elements() {
+        assert_eq!(
+            output(
+                r#"print len(""); print len("abc"); print len("héllo"); print len([]); print len([1, 2, 3]);"#
+            ),
+            "0\n3\n5\n0\n3\n"
+        );
+        let error = |src| run(src).unwrap_err()[0].to_string();
+        assert_eq!(
+            error("let x = len(42);"),
+            "len: expected a string or an array, got `number`"
+        );
+        assert_eq!(
+            error("let x = len(nil);"),
+            "len: expected a string or an array, got `nil`"
+        );
+    }
+
+    #[test]
+    fn destructures_arrays_by_position() {
+        let src = "let [a, b, c] = [1, 2, 3]; print a + b + c;";
+        assert_eq!(output(src), "6\n");
+
+        let src = r#"
+            fn f() {
+                let [x, y] = ["x", "y", "ignored"];
+                return y + x;
+            }
+            print f();
+        "#;
+        assert_eq!(output(src), "yx\n");
+    }
+
+    #[test]
+    fn destructuring_collects_the_rest() {
+        let src = "let [head, ...tail] = [1, 2, 3]; print head; print tail;";
+        assert_eq!(output(src), "1\n[2, 3]\n");
+        let src = "let [a, b, ...rest] = [1, 2]; print rest;";
+        assert_eq!(output(src), "[]\n");
+    }
+
+    #[test]
+    fn destructuring_a_short_array_is_an_error() {
+        let errors = run("let [a, b, c] = [1, 2];").unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "expected an array of at least 3 elements, found 2"
+        );
+        let errors = run("let [a] = 1;").unwrap_err();
+        assert_eq!(errors[0].to_string(), "cannot destructure `1` as an array");
+    }
+
+    #[test]
+    fn methods_are_called_on_their_instance() {
+        let src = r#"
+            class Counter {
+                increment() {
+                    this.count = this.count + 1;
+                    return this.count;
+                }
+            }
+            let a = Counter();
+            let b = Counter();
+            a.count = 0;
+            b.count = 10;
+            a.increment();
+            print a.increment();
+            let bump = b.increment;
+            bump();
+            print b.count;
+        "#;
+        // A method taken off an instance stays bound to it
+        assert_eq!(output(src), "2\n11\n");
+        let errors = run("class C {} C().missing();").unwrap_err();
+        assert_eq!(errors[0].to_string(), "undefined property `missing`");
+    }
+
+    #[test]
+    fn this_is_the_instance_a_method_is_bound_to() {
+        let src = r#"
+            class Cell {
+                init(v) { this.v = v; }
+                get() { return this.v; }
+                getter() {
+                    fn get() { return this.v; }
+                    return get;
+                }
+            }
+            let a = Cell(1);
+            let b = Cell(2);
+            b.get = a.get;
+            print b.get();
+            let get = b.getter();
+            b.v = 3;
+            print get();
+        "#;
+        // Closures inside a method capture `this` as well
+        assert_eq!(output(src), "1\n3\n");
+    }
+
+    #[test]
+    fn classes_are_constructed_through_init() {
+        let src = r#"
+            class Point {
+                init(x, y) {
+                    this.x = x;
+                    this.y = y;
+                    if (x == y) { return; }
+                    this.diagonal = false;
+                }
+            }
+            let p = Point(1, 2);
+            let q = Point(3, 3);
+            let arities = [arity(Point), arity(q.init)];
+            let again = p.init(5, 6);
+        "#;
+        assert_eq!(output(&format!("{} print q.x + q.y;", src)), "6\n");
+        assert_eq!(global(src, "arities").to_string(), "[2, 2]");
+        // An early return still gives the instance, and so does calling `init` directly
+        assert!(matches!(&*global(src, "q"), LoxValue::Instance(_)));
+        assert_eq!(
+            output(&format!("{} print again.x; print p.x;", src)),
+            "5\n5\n"
+        );
+        assert_eq!(
+            *global("class E {} let n = arity(E);", "n"),
+            LoxValue::Integer(0)
+        );
+    }
+
+    #[test]
+    fn subclasses_inherit_and_override_methods() {
+        let src = r#"
+            class Animal {
+                name() { return "animal"; }
+                describe() { return "an " + this.name(); }
+            }
+            class Dog extends Animal {
+                name() { return "dog"; }
+            }
+            class Puppy extends Dog {}
+            print Animal().describe();
+            print Dog().describe();
+            print Puppy().name();
+        "#;
+        assert_eq!(output(src), "an animal\nan dog\ndog\n");
+    }
+
+    #[test]
+    fn super_calls_the_overridden_method_on_the_same_instance() {
+        let src = r#"
+            class Shape {
+                init(name) { this.name = name; }
+                describe() { return "a " + this.name; }
+            }
+            class Square extends Shape {
+                describe() { return super.describe() + " with equal sides"; }
+            }
+            class Cube extends Square {
+                init() { super.init("cube"); }
+                describe() {
+                    let describe = super.describe;
+                    return describe() + ", in 3D";
+                }
+            }
+            print Square("square").describe();
+            print Cube().describe();
+        "#;
+        assert_eq!(
+            output(src),
+            "a square with equal sides\na cube with equal sides, in 3D\n"
+        );
+    }
+
+    #[test]
+    fn only_classes_can_be_inherited() {
+        let error = |src| run(src).unwrap_err()[0].to_string();
+        assert_eq!(
+            error("let A = 1; class B extends A {}"),
+            "superclass must be a class, got `1`"
+        );
+        assert_eq!(
+            error("fn A() {} class B extends A {}"),
+            "superclass must be a class, got `<fn A>`"
+        );
+        assert_eq!(
+            error("class A extends A {}"),
+            "a class cannot inherit itself"
+        );
+    }
+
+    #[test]
+    fn destructures_instance_fields() {
+        let src = r#"
+            class Point {
+                init(x, y) { this.x = x; this.y = y; }
+            }
+            let {x, y} = Point(1, 2);
+            let {y: py, x: px} = Point(3, 4);
+            print x + y;
+            print px * py;
+        "#;
+        assert_eq!(output(src), "3\n12\n");
+    }
+
+    #[test]
+    fn destructuring_a_missing_field_is_an_error() {
+        let src = r#"
+            class Point { init() { this.x = 1; } }
+            let {x, z} = Point();
+        "#;
+        let errors = run(src).unwrap_err();
+        assert_eq!(errors[0].to_string(), "undefined property `z`");
+        let errors = run("let {x} = [1];").unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "cannot destructure `[1]` as an object"
+        );
+    }
+
+    #[test]
+    fn spreads_arrays_into_calls() {
+        let src = r#"
+            fn add(a, b, c) { return a + b + c; }
+            let args = [2, 3];
+            print add(...args, 4);
+            print add(1, ...args);
+        "#;
+        assert_eq!(output(src), "9\n6\n");
+    }
+
+    #[test]
+    fn spread_calls_check_the_arity_once_spread() {
+        let src = "fn add(a, b, c) { return a + b + c; } print add(...[1, 2, 3]);";
+        assert_eq!(output(src), "6\n");
+        let errors = run("fn add(a, b) { return a + b; } add(...[1, 2, 3]);").unwrap_err();
+        assert_eq!(errors[0].to_string(), "expected 2 arguments but got 3");
+        let errors = run("fn add(a, b) { return a + b; } add(...[], 1);").unwrap_err();
+        assert_eq!(errors[0].to_string(), "expected 2 arguments but got 1");
+    }
+
+    #[test]
+    fn array_literals_evaluate_elements_in_order() {
+        let src = r#"
+            let trace = "";
+            fn t(v) { trace = trace + v; return v; }
+            let arr = [t(1), t(2), [t(3)],];
+            let empty = [];
+        "#;
+        assert_eq!(global(src, "arr").to_string(), "[1, 2, [3]]");
+        assert_eq!(global(src, "trace").to_string(), "123");
+        assert_eq!(global(src, "empty").to_string(), "[]");
+        assert!(run("let arr = [,];").is_err());
+    }
+
+    #[test]
+    fn spreads_arrays_into_array_literals() {
+        let src = "let rest = [2, 3]; print [1, ...rest, 4]; print [...[], ...rest];";
+        assert_eq!(output(src), "[1, 2, 3, 4]\n[2, 3]\n");
+    }
+
+    #[test]
+    fn spreading_a_non_array_is_an_error() {
+        let errors = run("fn f(a) {} f(...1);").unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "cannot spread `1`, only arrays can be spread"
+        );
+        assert!(run(r#"let a = [..."ab"];"#).is_err());
+    }
+
+    #[test]
+    fn default_declaration_initializes_unset_variables() {
+        assert_eq!(output("let x ?= 1; print x;"), "1\n");
+        assert_eq!(output("let x; let x ?= 2; print x;"), "2\n");
+        assert_eq!(output("fn f() { let y ?= 3; return y; } print f();"), "3\n");
+    }
+
+    #[test]
+    fn default_declaration_keeps_set_variables() {
+        let src = r#"
+            let calls = 0;
+            fn compute() { calls = calls + 1; return "computed"; }
+            let x = "set";
+            let x ?= compute();
+            print x;
+            print calls;
+        "#;
+        assert_eq!(output(src), "set\n0\n");
+
+        let src = "let x = 1; { let x ?= 2; print x; } print x;";
+        assert_eq!(output(src), "2\n1\n");
+    }
+
+    /// Prefix that records the order in which `side(x)` calls happen into `log`.
+    const SIDE_EFFECTS: &str = r#"
+        let log = "";
+        fn side(name) { log = log + name; return 1; }
+    "#;
+
+    fn evaluation_order(src: &str) -> String {
+        global(&format!("{}{}", SIDE_EFFECTS, src), "log").to_string()
+    }
+
+    #[test]
+    fn binary_operands_evaluate_left_to_right() {
+        assert_eq!(evaluation_order(r#"print side("a") + side("b");"#), "ab");
+        assert_eq!(
+            evaluation_order(r#"let x = side("a") * side("b") - side("c") < side("d");"#),
+            "abcd"
+        );
+        assert_eq!(evaluation_order(r#"side("a") == side("b");"#), "ab");
+    }
+
+    #[test]
+    fn arguments_and_elements_evaluate_left_to_right() {
+        let src = r#"
+            fn three(a, b, c) {}
+            fn callee() { side("f"); return three; }
+            callee()(side("a"), side("b"), side("c"));
+        "#;
+        assert_eq!(evaluation_order(src), "fabc");
+        assert_eq!(
+            evaluation_order(r#"let arr = [side("a"), side("b"), side("c")];"#),
+            "abc"
+        );
+    }
+
+    #[test]
+    fn numeric_suffixes_decide_the_arithmetic() {
+        assert_eq!(*global("let x = 5i / 2i;", "x"), LoxValue::Integer(2));
+        assert!(matches!(*global("let x = 5f / 2;", "x"), LoxValue::Decimal(d) if d == 2.5));
+        assert!(matches!(*global("let x = 4f;", "x"), LoxValue::Decimal(d) if d == 4.0));
+    }
+
+    #[test]
+    fn only_a_zero_divisor_is_an_error() {
+        assert_eq!(*global("let x = 0 / 3;", "x"), LoxValue::Integer(0));
+        assert!(matches!(*global("let x = 0.0 / 2.5;", "x"), LoxValue::Decimal(d) if d == 0.0));
+        let error = |src| run(src).unwrap_err()[0].to_string();
+        assert_eq!(error("let x = 3 / 0;"), "attempt to divide by zero");
+        assert_eq!(error("let x = 0.0 / 0.0;"), "attempt to divide by zero");
+    }
+
+    #[test]
+    fn strings_compare_lexicographically() {
+        assert_eq!(
+            output(
+                r#"print "apple" < "banana"; print "b" < "a"; print "b" >= "b"; print "ab" > "a";"#
+            ),
+            "true\nfalse\ntrue\ntrue\n"
+        );
+        let error = |src| run(src).unwrap_err()[0].to_string();
+        let expected = "operands must be two numbers or two strings";
+        assert_eq!(error(r#"let x = "1" < 2;"#), expected);
+        assert_eq!(error(r#"let x = 1 <= "2";"#), expected);
+        assert_eq!(error("let x = nil > nil;"), expected);
+    }
+
+    #[test]
+    fn strings_repeat_when_multiplied_by_a_count() {
+        assert_eq!(
+            output(r#"print "ab" * 3 == "ababab"; print 2 * "-"; print "x" * 0 == "";"#),
+            "true\n--\ntrue\n"
+        );
+        let error = |src| run(src).unwrap_err()[0].to_string();
+        assert_eq!(
+            error(r#"let x = "ab" * -1;"#),
+            "cannot repeat a negative number of times, got `-1`"
+        );
+        assert_eq!(
+            error(r#"let x = "ab" * 1.5;"#),
+            "invalid operands to multiplication expression"
+        );
+        assert_eq!(
+            error("let x = nil * 2;"),
+            "invalid operands to multiplication expression"
+        );
+    }
+
+    #[test]
+    fn remainders_keep_the_sign_of_the_dividend() {
+        assert_eq!(
+            output("print 7 % 3; print -7 % 3; print 2 + 7 % 3 * 2;"),
+            "1\n-1\n4\n"
+        );
+        assert_eq!(*global("let x = 7i % 3i;", "x"), LoxValue::Integer(1));
+        assert!(matches!(*global("let x = 7.5 % 2;", "x"), LoxValue::Decimal(d) if d == 1.5));
+        assert_eq!(output("print 0 % 5;"), "0\n");
+
+        let error = |src| run(src).unwrap_err()[0].to_string();
+        assert_eq!(
+            error("let x = 7 % 0;"),
+            "attempt to calculate the remainder with a divisor of zero"
+        );
+        assert_eq!(error(r#"let x = "7" % 2;"#), "operands must be numbers");
+    }
+
+    #[test]
+    fn compound_assignments_apply_the_operator() {
+        let src = r#"
+            let x = 10; x += 5; x -= 3; x *= 2; x /= 4; x **= 2;
+            let s = "a"; s += 1;
+            let arr = [1, 2]; arr[1] *= 10;
+            class P { init() { this.n = 1; } }
+            let p = P(); p.n += 41;
+            let y = 1; let z = y += 1;
+        "#;
+        assert_eq!(*global(src, "x"), LoxValue::Integer(36));
+        assert_eq!(global(src, "s").to_string(), "a1");
+        assert_eq!(global(src, "arr").to_string(), "[1, 20]");
+        assert_eq!(output(&format!("{} print p.n;", src)), "42\n");
+        assert_eq!(*global(src, "z"), LoxValue::Integer(2));
+
+        let error = |src| run(src).unwrap_err()[0].to_string();
+        assert_eq!(error("let x = 1; x /= 0;"), "attempt to divide by zero");
+        assert_eq!(error("1 += 2;"), "invalid assigment target");
+        assert_eq!(
+            error("let a = [1]; a[1] += 1;"),
+            "index out of bounds: the length is 1 but the index is 1"
+        );
+    }
+
+    #[test]
+    fn compound_assignments_evaluate_their_target_once() {
+        let src = "
+            let n = 0;
+            fn idx() { n += 1; return 0; }
+            let a = [1]; a[idx()] += 5;
+            class P { init() { this.v = 1; } }
+            let p = P();
+            fn get() { n += 1; return p; }
+            get().v *= 3;
+        ";
+        assert_eq!(*global(src, "n"), LoxValue::Integer(2));
+        assert_eq!(output(&format!("{} print a; print p.v;", src)), "[6]\n3\n");
+    }
+
+    #[test]
+    fn powers_are_right_associative() {
+        assert_eq!(*global("let x = 2 ** 10;", "x"), LoxValue::Integer(1024));
+        assert_eq!(*global("let x = 2 ** 3 ** 2;", "x"), LoxValue::Integer(512));
+        assert!(matches!(*global("let x = 2 ** -1;", "x"), LoxValue::Decimal(d) if d == 0.5));
+        assert!(matches!(*global("let x = 4 ** 0.5;", "x"), LoxValue::Decimal(d) if d == 2.0));
+        // Binds tighter than `*`, but looser than a negation
+        assert_eq!(output("print 3 * 2 ** 2; print -2 ** 2;"), "12\n4\n");
+
+        let error = |src| run(src).unwrap_err()[0].to_string();
+        assert_eq!(error("let x = 2i ** 100i;"), "exponentiation overflowed");
+        assert_eq!(error("let x = nil ** 2;"), "operands must be numbers");
+    }
+
+    #[test]
+    fn folded_concatenations_match_nested_additions() {
+        let src = r#"
+            class V { __add__(other) { return "V" + other; } }
+            let s = "s";
+            let n = 2.5;
+            let v = V();
+            let folded = [s + 1 + n + nil + true + s, 1 + n + "a" + 2, v + "a" + "b"];
+            let a = s + 1; a = a + n; a = a + nil; a = a + true; a = a + s;
+            let b = 1 + n; b = b + "a"; b = b + 2;
+            let c = v + "a"; c = c + "b";
+            let nested = [a, b, c];
+        "#;
+        assert_eq!(
+            global(src, "folded").to_string(),
+            global(src, "nested").to_string()
+        );
+        assert_eq!(
+            global(src, "folded").to_string(),
+            "[s12.5niltrues, 3.5a2, Vab]"
+        );
+        let error = run(r#"let x = nil; print x + x + "a";"#).unwrap_err();
+        assert_eq!(error[0].to_string(), "operands must be number or string");
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench_`.
+    #[test]
+    #[ignore]
+    fn bench_local_variable_access() {
+        let src = r#"
+            fn run() {
+                let sum = 0;
+                let i = 0;
+                let a = 1;
+                let b = 2;
+                while (i < 300000) {
+                    let t = a + b;
+                    sum = sum + t - a;
+                    i = i + 1;
+                }
+                return sum;
+            }
+            print run();
+        "#;
+        let start = std::time::Instant::now();
+        assert_eq!(output(src), "600000\n");
+        println!("ran 300000 iterations in {:?}", start.elapsed());
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_global_variable_access() {
+        let src = r#"
+            let step = 1;
+            let limit = 300000;
+            let sum = 0;
+            let i = 0;
+            while (i < limit) {
+                sum = sum + step;
+                i = i + step;
+            }
+            print sum;
+        "#;
+        let start = std::time::Instant::now();
+        assert_eq!(output(src), "300000\n");
+        println!("ran 300000 iterations in {:?}", start.elapsed());
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_string_concatenation() {
+        let src = r#"
+            let s = "";
+            let i = 0;
+            while (i < 20000) {
+                let n = "n" + i;
+                s = "<" + n + ", " + n + ", " + n + ", " + n + ", " + n + ">";
+                i = i + 1;
+            }
+            print s;
+        "#;
+        let start = std::time::Instant::now();
+        assert_eq!(output(src), "<n19999, n19999, n19999, n19999, n19999>\n");
+        println!("ran 20000 concatenations in {:?}", start.elapsed());
     }
 }
@@ -1,10 +1,11 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use crate::lib::{
-    error::InnerError,
-    interpreter::Interpreter,
+use crate::{
+    error::{InnerError, LoxError},
+    interpreter::{Interpreter, Local},
     parser::{Expr, Stmt},
+    position::Span,
     token::Token,
     LoxResult,
 };
@@ -12,6 +13,8 @@ use crate::lib::{
 #[derive(Clone, Copy)]
 enum FunctionType {
     Method,
+    Initializer,
+    StaticMethod,
     Function,
 }
 
@@ -21,6 +24,14 @@ enum ClassType {
     SubClass,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+enum LoopType {
+    /// `while`, along with the `for` and `repeat` loops built on it
+    While,
+    /// `loop`, the only one that can be exited with a value
+    Loop,
+}
+
 pub(crate) trait Resolvable {
     fn resolve(&self, resolver: &Resolver) -> LoxResult<()>;
 }
@@ -28,8 +39,10 @@ pub(crate) trait Resolvable {
 impl Resolvable for Stmt {
     fn resolve(&self, resolver: &Resolver) -> LoxResult<()> {
         match &self {
-            Stmt::Variable(name, initializer) => {
+            Stmt::Variable(name, initializer, _) => {
                 for (name, initializer) in name.iter().zip(initializer) {
+                    resolver.check_shadowing(name);
+                    resolver.check_redeclaration(name)?;
                     resolver.declare(name);
                     if let Some(initializer) = initializer {
                         resolver.resolve(initializer)?;
@@ -37,12 +50,122 @@ impl Resolvable for Stmt {
                     resolver.define(name);
                 }
             }
+            Stmt::Labeled(label, body) => {
+                resolver.labels.borrow_mut().push(label.to_string());
+                let res = resolver.resolve(&**body);
+                resolver.labels.borrow_mut().pop();
+                res?;
+            }
+            Stmt::Break(kw, label, value) => {
+                let innermost = resolver.loops.borrow().last().copied();
+                match (label, value) {
+                    (Some(label), _) if !resolver.labels.borrow().contains(&label.to_string()) => {
+                        let msg = format!("use of undeclared label `{}`", label);
+                        return Err(InnerError::new(*label.span(), &msg).into());
+                    }
+                    (Some(_), _) => (),
+                    (None, Some(value)) if innermost == Some(LoopType::Loop) => {
+                        resolver.resolve(&**value)?
+                    }
+                    // Not a label in scope, but likely meant as one
+                    (None, Some(value)) if matches!(**value, Expr::Variable(_)) => {
+                        let msg = format!("use of undeclared label `{}`", value);
+                        return Err(InnerError::new(value.position(), &msg).into());
+                    }
+                    (None, Some(_)) => {
+                        return Err(InnerError::new(
+                            *kw.span(),
+                            "cannot use `break` with a value outside of a `loop`",
+                        )
+                        .into())
+                    }
+                    (None, None) if innermost.is_none() => {
+                        return Err(InnerError::new(
+                            *kw.span(),
+                            "cannot use `break` without a label outside of a loop",
+                        )
+                        .into())
+                    }
+                    (None, None) => (),
+                }
+            }
+            Stmt::Continue(kw) => {
+                if resolver.loops.borrow().is_empty() {
+                    return Err(InnerError::new(
+                        *kw.span(),
+                        "cannot use `continue` outside of a loop",
+                    )
+                    .into());
+                }
+            }
+            Stmt::Const(name, initializer) => {
+                resolver.check_shadowing(name);
+                resolver.check_redeclaration(name)?;
+                resolver.declare(name);
+                resolver.resolve(initializer)?;
+                resolver.define_const(name);
+            }
+            Stmt::VariableDefault(name, initializer, _) => {
+                resolver.check_redeclaration(name)?;
+                resolver.declare(name);
+                resolver.resolve(&**initializer)?;
+                resolver.define(name);
+            }
+            Stmt::Destructure(pattern, initializer) => {
+                let names = pattern.names();
+                names.iter().for_each(|name| resolver.check_shadowing(name));
+                names
+                    .iter()
+                    .try_for_each(|name| resolver.check_redeclaration(name))?;
+                names.iter().for_each(|name| resolver.declare(name));
+                resolver.resolve(initializer)?;
+                names.iter().for_each(|name| resolver.define(name));
+            }
+            Stmt::With(_, resource, name, body) => {
+                resolver.resolve(&**resource)?;
+                resolver.begin_scope();
+                resolver.define(name);
+                resolver.resolve(&**body)?;
+                resolver.end_scope();
+            }
+            Stmt::ForEach(name, iterable, body) => {
+                resolver.resolve(iterable)?;
+                resolver.begin_scope();
+                resolver.define(name);
+                resolver.loops.borrow_mut().push(LoopType::While);
+                let res = resolver.resolve(&**body);
+                resolver.loops.borrow_mut().pop();
+                resolver.end_scope();
+                res?;
+            }
+            Stmt::Static(name, initializer, target) => {
+                resolver.resolve(&**initializer)?;
+                resolver.declare_static(name, target)?;
+            }
+            Stmt::Defer(kw, body) => {
+                if resolver.current_function.borrow().is_none() {
+                    return Err(
+                        InnerError::new(*kw.span(), "cannot defer outside of a function").into(),
+                    );
+                }
+                // The labels are done by the time deferred statements run
+                let enclosing_labels = resolver.labels.take();
+                let res = resolver.resolve(&**body);
+                *resolver.labels.borrow_mut() = enclosing_labels;
+                res?;
+            }
             Stmt::Block(statements) => {
                 resolver.begin_scope();
                 resolver.resolve(statements)?;
                 resolver.end_scope();
             }
-            Stmt::Function(ref name, _, _) => {
+            Stmt::Enum(name, _) => {
+                resolver.check_redeclaration(name)?;
+                resolver.declare(name);
+                resolver.define(name);
+            }
+            Stmt::Function(ref name, ..) => {
+                resolver.check_redeclaration(name)?;
                 resolver.declare(name);
                 resolver.define(name);
                 resolver.resolve_func(self, FunctionType::Function)?;
@@ -51,15 +174,15 @@ impl Resolvable for Stmt {
                 resolver.resolve(expr)?;
             }
             Stmt::If(condition, then_branch, else_branch) => {
+                resolver.check_condition(condition);
                 resolver.resolve(condition)?;
                 resolver.resolve(&**then_branch)?;
                 if let Some(stmt) = else_branch {
                     resolver.resolve(&**stmt)?;
                 }
             }
-            Stmt::Print(expr) => {
-                resolver.resolve(expr)?;
-            }
+            Stmt::Print(expr) => resolver.resolve(expr)?,
+            Stmt::Assert(_, expr, _) => resolver.resolve(&**expr)?,
             Stmt::Return(tk, val) => {
                 if resolver.current_function.borrow().is_none() {
                     return Err(
@@ -67,17 +190,32 @@ impl Resolvable for Stmt {
                     );
                 }
                 if !val.is_nil_expr() {
+                    if let Some(FunctionType::Initializer) = *resolver.current_function.borrow() {
+                        return Err(InnerError::new(
+                            val.position(),
+                            "cannot return a value from an initializer",
+                        )
+                        .into());
+                    }
                     resolver.resolve(val)?;
                 }
             }
-            Stmt::While(condition, body) => {
+            Stmt::While(condition, body, increment) => {
+                resolver.check_condition(condition);
                 resolver.resolve(condition)?;
-                resolver.resolve(&**body)?;
+                resolver.loops.borrow_mut().push(LoopType::While);
+                let res = resolver.resolve(&**body);
+                resolver.loops.borrow_mut().pop();
+                res?;
+                if let Some(increment) = increment {
+                    resolver.resolve(&**increment)?;
+                }
             }
             Stmt::Class(name, superclass, methods, static_methods) => {
                 let enclosing_class = *resolver.current_class.borrow();
                 *resolver.current_class.borrow_mut() = Some(ClassType::Class);
 
+                resolver.check_redeclaration(name)?;
                 resolver.declare(name);
                 resolver.define(name);
 
@@ -90,21 +228,21 @@ impl Resolvable for Stmt {
                         )
                         .into());
                     }
-                    resolver.resolve(superclass)?;
+                    resolver.resolve(&**superclass)?;
                     resolver.begin_scope();
                     resolver.put(String::from("super"), true);
                 }
 
-                for static_method in &*static_methods {
-                    resolver.resolve_func(static_method, FunctionType::Method)?;
+                for static_method in static_methods {
+                    resolver.resolve_func(static_method, FunctionType::StaticMethod)?;
                 }
-                resolver.begin_scope();
-                resolver.put(String::from("this"), true);
-                for method in &*methods {
-                    let declaration = FunctionType::Method;
-                    resolver.resolve_func(method, declaration)?;
+                for method in methods {
+                    let func_type = match method.name().as_str() {
+                        "init" => FunctionType::Initializer,
+                        _ => FunctionType::Method,
+                    };
+                    resolver.resolve_func(method, func_type)?;
                 }
-                resolver.end_scope();
                 if superclass.is_some() {
                     resolver.end_scope();
                 }
@@ -124,6 +262,7 @@ impl Resolvable for Expr {
                 resolver.resolve_local(self, name)?;
             }
             Expr::Assign(ref name, ref value) => {
+                resolver.check_assignable(name)?;
                 resolver.resolve(&**value)?;
                 resolver.resolve_local(self, name)?;
             }
@@ -137,12 +276,16 @@ impl Resolvable for Expr {
                 resolver.resolve(args)?;
             }
             Expr::Array(_, vals) => resolver.resolve(vals)?,
-            Expr::Grouping(expr) => resolver.resolve(&**expr)?,
             Expr::Literal(_) => (),
             Expr::Logical(lhs, _, rhs) => {
                 resolver.resolve(&**lhs)?;
                 resolver.resolve(&**rhs)?;
             }
+            Expr::Conditional(condition, then, otherwise) => {
+                resolver.resolve(&**condition)?;
+                resolver.resolve(&**then)?;
+                resolver.resolve(&**otherwise)?;
+            }
 
             Expr::Get(object, _) => {
                 resolver.resolve(&**object)?;
@@ -159,10 +302,14 @@ impl Resolvable for Expr {
                     )
                     .into());
                 }
+                resolver.check_instance(keyword)?;
                 resolver.resolve_local(self, keyword)?
             }
             Expr::Super(ref keyword, _) => match *resolver.current_class.borrow() {
-                Some(ClassType::SubClass) => resolver.resolve_local(self, keyword)?,
+                Some(ClassType::SubClass) => {
+                    resolver.check_instance(keyword)?;
+                    resolver.resolve_local(self, keyword)?
+                }
                 None => {
                     return Err(InnerError::new(
                         *keyword.span(),
@@ -178,8 +325,20 @@ impl Resolvable for Expr {
                     .into())
                 }
             },
-            Expr::Index(_, idx) => resolver.resolve(&**idx)?,
-            Expr::IndexAssign(_, idx, val) => {
+            Expr::Index(lhs, rhs) | Expr::Range(lhs, rhs) | Expr::Compound(lhs, _, rhs) => {
+                resolver.resolve(&**lhs)?;
+                resolver.resolve(&**rhs)?;
+            }
+            Expr::Spread(_, array) => resolver.resolve(&**array)?,
+            Expr::Concat(chain) => resolver.resolve(&**chain)?,
+            Expr::Loop(_, body) => {
+                resolver.loops.borrow_mut().push(LoopType::Loop);
+                let res = resolver.resolve(&*body.0);
+                resolver.loops.borrow_mut().pop();
+                res?;
+            }
+            Expr::IndexAssign(array, idx, val) => {
+                resolver.resolve(&**array)?;
                 resolver.resolve(&**idx)?;
                 resolver.resolve(&**val)?;
             }
@@ -197,23 +356,123 @@ impl<T: Resolvable> Resolvable for Vec<T> {
     }
 }
 
-type Scope = HashMap<String, bool>;
+impl<T: Resolvable> Resolvable for Box<[T]> {
+    fn resolve(&self, resolver: &Resolver) -> LoxResult<()> {
+        for expr in self.iter() {
+            expr.resolve(resolver)?;
+        }
+        Ok(())
+    }
+}
+
+/// State of a name declared in a local scope.
+#[derive(Clone, Copy)]
+struct Binding {
+    /// Whether the initializer of the variable has been resolved
+    defined: bool,
+    /// Whether it was declared with `const`
+    constant: bool,
+    /// Position of the variable in its environment, see [`Local`]
+    slot: usize,
+    /// Where it was declared, unless it's implicit, like `this`
+    span: Option<Span>,
+}
+
+type Scope = HashMap<String, Binding>;
 
-#[derive(Clone)]
-pub(crate) struct Resolver<'i> {
-    interpreter: &'i Interpreter,
+pub(crate) struct Resolver<'i, 'o> {
+    interpreter: &'i Interpreter<'o>,
     scopes: RefCell<Vec<Scope>>,
     current_function: RefCell<Option<FunctionType>>,
     current_class: RefCell<Option<ClassType>>,
+    /// Labels of the statements enclosing the current one
+    labels: RefCell<Vec<String>>,
+    /// Loops enclosing the current statement, the innermost last
+    loops: RefCell<Vec<LoopType>>,
+    /// Constants declared at the top level, which has no scope
+    global_constants: RefCell<HashSet<String>>,
+    /// Where the names at the top level were declared
+    global_spans: RefCell<HashMap<String, Span>>,
+    /// Index of the scope holding the static variables of the current function, and of the
+    /// scope of its body
+    statics: RefCell<Option<(usize, usize)>>,
+    /// Enables warnings for likely mistakes
+    strict: bool,
+    warnings: RefCell<Vec<LoxError>>,
 }
 
-impl<'i> Resolver<'i> {
-    pub fn new(interpreter: &'i Interpreter) -> Self {
+impl<'i, 'o> Resolver<'i, 'o> {
+    pub fn new(interpreter: &'i Interpreter<'o>) -> Self {
         Self {
             interpreter,
             scopes: Default::default(),
             current_function: Default::default(),
             current_class: Default::default(),
+            labels: Default::default(),
+            loops: Default::default(),
+            global_constants: Default::default(),
+            global_spans: Default::default(),
+            statics: Default::default(),
+            strict: false,
+            warnings: Default::default(),
+        }
+    }
+
+    /// Enables or disables the strict mode warnings.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Returns the warnings emitted so far, leaving none behind.
+    pub fn take_warnings(&self) -> Vec<LoxError> {
+        self.warnings.take()
+    }
+
+    fn warn(&self, pos: Span, msg: &str) {
+        self.warnings
+            .borrow_mut()
+            .push(InnerError::new(pos, msg).into());
+    }
+
+    /// In strict mode, flags assignments used as an `if`/`while` condition.
+    fn check_condition(&self, condition: &Expr) {
+        if self.strict && matches!(condition, Expr::Assign(..)) {
+            self.warn(
+                condition.position(),
+                "assignment in condition; did you mean `==`?",
+            );
+        }
+    }
+
+    /// In strict mode, flags declarations hiding a variable of an enclosing scope, unless
+    /// their name starts with `_`.
+    fn check_shadowing(&self, name: &Token) {
+        let key = name.to_string();
+        if !self.strict || key.starts_with(['_', '<']) {
+            return;
+        }
+        let scopes = self.scopes.borrow();
+        let (innermost, enclosing) = match scopes.split_last() {
+            Some(split) => split,
+            None => return,
+        };
+        if innermost.contains_key(&key) {
+            return;
+        }
+        let shadowed = match enclosing.iter().rev().find_map(|scope| scope.get(&key)) {
+            Some(binding) => binding.span,
+            None => self.global_spans.borrow().get(&key).copied(),
+        };
+        if let Some(span) = shadowed {
+            self.warn(
+                *name.span(),
+                &format!(
+                    "`{}` shadows the variable declared at {}; prefix it with `_` if that's intended",
+                    name,
+                    span.start()
+                ),
+            );
         }
     }
 
@@ -221,41 +480,130 @@ impl<'i> Resolver<'i> {
         resolvable.resolve(self)
     }
 
+    /// Checks that `keyword`, either `this` or `super`, is used where there's an instance.
+    /// Static methods are the only functions of a class with no `this` scope.
+    fn check_instance(&self, keyword: &Token) -> LoxResult<()> {
+        if self
+            .scopes
+            .borrow()
+            .iter()
+            .any(|scope| scope.contains_key("this"))
+        {
+            return Ok(());
+        }
+        Err(InnerError::new(
+            *keyword.span(),
+            &format!("cannot use `{}` in a static method", keyword),
+        )
+        .into())
+    }
+
     fn resolve_local(&self, expr: &Expr, name: &Token) -> LoxResult<()> {
         let scopes = self.scopes.borrow();
         for (idx, scope) in scopes.iter().enumerate().rev() {
-            if scope.contains_key(&name.to_string()) {
-                self.interpreter.resolve(expr, scopes.len() - idx - 1)?;
+            if let Some(binding) = scope.get(&name.to_string()) {
+                let local = Local {
+                    depth: scopes.len() - idx - 1,
+                    slot: binding.slot,
+                };
+                self.interpreter.resolve(expr, local)?;
                 return Ok(());
             }
         }
         Ok(())
     }
 
+    /// Resolves a function, which runs in the scopes of its static variables, of `this` for
+    /// methods, and of its parameters, in that order.
     fn resolve_func(&self, stmt: &Stmt, func_type: FunctionType) -> LoxResult<()> {
         let enclosing_function = *self.current_function.borrow();
         *self.current_function.borrow_mut() = Some(func_type);
+        // Labels and loops can't be targeted from inside a nested function
+        let enclosing_labels = self.labels.take();
+        let enclosing_loops = self.loops.take();
 
+        let statics = self.scopes.borrow().len();
+        self.begin_scope();
+        if let FunctionType::Method | FunctionType::Initializer = func_type {
+            self.begin_scope();
+            self.put(String::from("this"), true);
+        }
         self.begin_scope();
-        if let Stmt::Function(_, params, body) = stmt {
-            for param in params {
+        let body_depth = self.scopes.borrow().len() + 1;
+        let enclosing_statics = self.statics.replace(Some((statics, body_depth)));
+
+        if let Stmt::Function(_, params, body, _) = stmt {
+            for (i, param) in params.iter().enumerate() {
+                if params[..i]
+                    .iter()
+                    .any(|prev| prev.to_string() == param.to_string())
+                {
+                    return Err(InnerError::new(
+                        *param.span(),
+                        &format!("duplicate parameter name `{}`", param),
+                    )
+                    .into());
+                }
                 self.declare(param);
                 self.define(param);
             }
             self.resolve(&**body)?;
         }
-        self.end_scope();
+        self.scopes.borrow_mut().truncate(statics);
         *self.current_function.borrow_mut() = enclosing_function;
+        *self.labels.borrow_mut() = enclosing_labels;
+        *self.loops.borrow_mut() = enclosing_loops;
+        *self.statics.borrow_mut() = enclosing_statics;
         Ok(())
     }
 
+    /// Declares a static variable in the scope of the current function's static variables,
+    /// resolving `target`, the expression that stands for its declaration.
+    fn declare_static(&self, name: &Token, target: &Expr) -> LoxResult<()> {
+        let (statics, body_depth) = match *self.statics.borrow() {
+            Some(statics) if self.scopes.borrow().len() == statics.1 => statics,
+            _ => {
+                return Err(InnerError::new(
+                    *name.span(),
+                    "static variables can only be declared at the top of a function body",
+                )
+                .into())
+            }
+        };
+
+        let mut scopes = self.scopes.borrow_mut();
+        let key = name.to_string();
+        if scopes[statics + 1..]
+            .iter()
+            .any(|scope| scope.contains_key(&key))
+        {
+            return Err(InnerError::new(
+                *name.span(),
+                &format!("`{}` is already declared in this function", name),
+            )
+            .into());
+        }
+        let scope = &mut scopes[statics];
+        let slot = scope.get(&key).map_or(scope.len(), |binding| binding.slot);
+        let binding = Binding {
+            defined: true,
+            constant: false,
+            slot,
+            span: Some(*name.span()),
+        };
+        scope.insert(key, binding);
+        let depth = body_depth - 1 - statics;
+        self.interpreter.resolve(target, Local { depth, slot })
+    }
+
     fn check(&self, token: &Token) -> LoxResult<()> {
         let scopes = self.scopes.borrow();
         if scopes.is_empty() {
             return Ok(());
         }
 
-        if let Some(&false) = scopes.last().unwrap().get(&token.to_string()) {
+        if let Some(Binding { defined: false, .. }) = scopes.last().unwrap().get(&token.to_string())
+        {
             return Err(InnerError::new(
                 *token.span(),
                 "Cannot read local variable in its own initializer",
@@ -275,22 +623,254 @@ impl<'i> Resolver<'i> {
     }
 
     fn define(&self, name: &Token) {
-        self.put(name.to_string(), true);
+        self.put_at(name.to_string(), true, Some(*name.span()));
     }
 
     fn declare(&self, name: &Token) {
-        self.put(name.to_string(), false);
+        self.put_at(name.to_string(), false, Some(*name.span()));
     }
 
-    pub fn put(&self, name: String, val: bool) {
-        if self.scopes.borrow().is_empty() {
-            return;
+    fn define_const(&self, name: &Token) {
+        self.define(name);
+        match self.scopes.borrow_mut().last_mut() {
+            Some(scope) => scope.get_mut(&name.to_string()).unwrap().constant = true,
+            None => {
+                self.global_constants.borrow_mut().insert(name.to_string());
+            }
         }
+    }
 
-        self.scopes
-            .borrow_mut()
-            .last_mut()
-            .unwrap()
-            .insert(name, val);
+    /// Declares `name` in the innermost scope, redeclarations keep their original slot.
+    pub fn put(&self, name: String, defined: bool) {
+        self.put_at(name, defined, None)
+    }
+
+    fn put_at(&self, name: String, defined: bool, span: Option<Span>) {
+        let mut scopes = self.scopes.borrow_mut();
+        let scope = match scopes.last_mut() {
+            Some(scope) => scope,
+            None => {
+                self.global_constants.borrow_mut().remove(&name);
+                if let Some(span) = span {
+                    self.global_spans.borrow_mut().insert(name, span);
+                }
+                return;
+            }
+        };
+
+        let slot = scope.get(&name).map_or(scope.len(), |binding| binding.slot);
+        let binding = Binding {
+            defined,
+            constant: false,
+            slot,
+            span,
+        };
+        scope.insert(name, binding);
+    }
+
+    /// Rejects declaring `name` again in a scope where it is already a constant, functions
+    /// that captured the constant have already been compiled against its value.
+    fn check_redeclaration(&self, name: &Token) -> LoxResult<()> {
+        let constant = match self.scopes.borrow().last() {
+            Some(scope) => scope
+                .get(&name.to_string())
+                .is_some_and(|binding| binding.constant),
+            None => self.global_constants.borrow().contains(&name.to_string()),
+        };
+        if constant {
+            return Err(InnerError::new(
+                *name.span(),
+                &format!("cannot redeclare constant `{}`", name),
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Rejects assignments to the closest declaration of `name` if it's a constant.
+    fn check_assignable(&self, name: &Token) -> LoxResult<()> {
+        let constant = match self
+            .scopes
+            .borrow()
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name.to_string()))
+        {
+            Some(binding) => binding.constant,
+            None => self.global_constants.borrow().contains(&name.to_string()),
+        };
+        if constant {
+            return Err(InnerError::new(
+                *name.span(),
+                &format!("cannot assign to constant `{}`", name),
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{lexer::Lexer, parser::Parser};
+
+    /// Resolves `src` in strict mode, returning the warnings emitted.
+    fn strict_warnings(src: &str) -> Vec<String> {
+        let tokens = Lexer::new(src).scan_tokens().unwrap();
+        let statements = Parser::new(&tokens).parse().unwrap();
+        let interpreter = Interpreter::new(&statements);
+        let resolver = Resolver::new(&interpreter).strict(true);
+        resolver.resolve(&statements).unwrap();
+        resolver
+            .take_warnings()
+            .iter()
+            .map(|e| e.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn warns_about_assignment_in_conditions() {
+        let warning = "assignment in condition; did you mean `==`?";
+        assert_eq!(strict_warnings("let x; if (x = 5) {}"), vec![warning]);
+        assert_eq!(strict_warnings("let x; while x = nil {}"), vec![warning]);
+        assert!(strict_warnings("let x; if (x == 5) {} while (x == 1) {}").is_empty());
+    }
+
+    #[test]
+    fn warns_about_shadowing_enclosing_variables() {
+        assert_eq!(
+            strict_warnings("let x = 1; { let x = 2; }"),
+            vec!["`x` shadows the variable declared at 1:5; prefix it with `_` if that's intended"]
+        );
+        assert_eq!(
+            strict_warnings("fn f(a) {\n    let a = 1;\n}"),
+            vec!["`a` shadows the variable declared at 1:6; prefix it with `_` if that's intended"]
+        );
+        assert_eq!(strict_warnings("{ let x; { { const x = 1; } } }").len(), 1);
+        assert_eq!(strict_warnings("let a; { let [a, b] = [1, 2]; }").len(), 1);
+    }
+
+    #[test]
+    fn shadowing_warnings_skip_deliberate_and_same_scope_declarations() {
+        // Redeclaring in the same scope doesn't hide anything
+        assert!(strict_warnings("let x = 1; let x = 2; { let y; let y; }").is_empty());
+        assert!(strict_warnings("{ let x; } { let x; }").is_empty());
+        assert!(strict_warnings("let _x = 1; { let _x = 2; }").is_empty());
+        // Parameters commonly reuse outer names
+        assert!(strict_warnings("let x; fn f(x) {}").is_empty());
+    }
+
+    #[test]
+    fn parameter_names_must_be_distinct() {
+        assert_eq!(
+            resolve_error("fn f(a, b, a) {}"),
+            "duplicate parameter name `a`"
+        );
+        assert_eq!(
+            resolve_error("class C { m(x, x) {} }"),
+            "duplicate parameter name `x`"
+        );
+        assert!(strict_warnings("fn f(a, b) {} fn g(a) {}").is_empty());
+        // The body is a scope of its own, so redeclaring a parameter there only hides it
+        assert_eq!(
+            strict_warnings("fn f(a) { let a = 1; }"),
+            vec!["`a` shadows the variable declared at 1:6; prefix it with `_` if that's intended"]
+        );
+    }
+
+    #[test]
+    fn condition_warnings_need_strict_mode() {
+        let tokens = Lexer::new("let x; if (x = 5) {}").scan_tokens().unwrap();
+        let statements = Parser::new(&tokens).parse().unwrap();
+        let interpreter = Interpreter::new(&statements);
+        let resolver = Resolver::new(&interpreter);
+        resolver.resolve(&statements).unwrap();
+        assert!(resolver.take_warnings().is_empty());
+    }
+
+    fn resolve_error(src: &str) -> String {
+        let tokens = Lexer::new(src).scan_tokens().unwrap();
+        let statements = Parser::new(&tokens).parse().unwrap();
+        let interpreter = Interpreter::new(&statements);
+        Resolver::new(&interpreter)
+            .resolve(&statements)
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn breaking_an_unknown_label_is_an_error() {
+        assert_eq!(
+            resolve_error("a: { break b; }"),
+            "use of undeclared label `b`"
+        );
+        assert_eq!(
+            resolve_error("a: { fn f() { break a; } }"),
+            "use of undeclared label `a`"
+        );
+        assert_eq!(
+            resolve_error("a: {} break a;"),
+            "use of undeclared label `a`"
+        );
+    }
+
+    #[test]
+    fn constants_cannot_be_redeclared_in_their_scope() {
+        let error = "cannot redeclare constant `N`";
+        assert_eq!(
+            resolve_error("const N = 1; fn f() { return N; } let N = 2;"),
+            error
+        );
+        assert_eq!(
+            resolve_error("{ const N = 1; fn f() { return N; } let N = 2; }"),
+            error
+        );
+        assert_eq!(resolve_error("const N = 1; fn N() {}"), error);
+        let src = "const N = 1; { let N = 2; }";
+        let tokens = Lexer::new(src).scan_tokens().unwrap();
+        let statements = Parser::new(&tokens).parse().unwrap();
+        let interpreter = Interpreter::new(&statements);
+        assert!(Resolver::new(&interpreter).resolve(&statements).is_ok());
+    }
+
+    #[test]
+    fn this_needs_an_instance_method() {
+        let error = "cannot use `this` outside of a class";
+        assert_eq!(resolve_error("print this;"), error);
+        assert_eq!(resolve_error("fn f() { return this; }"), error);
+        assert_eq!(
+            resolve_error("class C { static s() { fn f() { return this; } } }"),
+            "cannot use `this` in a static method"
+        );
+    }
+
+    #[test]
+    fn initializers_cannot_return_a_value() {
+        assert_eq!(
+            resolve_error("class P { init() { return 1; } }"),
+            "cannot return a value from an initializer"
+        );
+        let src = "class P { init() { fn f() { return 1; } return; } }";
+        let tokens = Lexer::new(src).scan_tokens().unwrap();
+        let statements = Parser::new(&tokens).parse().unwrap();
+        let interpreter = Interpreter::new(&statements);
+        assert!(Resolver::new(&interpreter).resolve(&statements).is_ok());
+    }
+
+    #[test]
+    fn super_needs_a_superclass_and_an_instance() {
+        assert_eq!(
+            resolve_error("fn f() { super.g(); }"),
+            "cannot use `super` outside of a class"
+        );
+        assert_eq!(
+            resolve_error("class A { f() { super.f(); } }"),
+            "cannot use `super` in a class with no superclass"
+        );
+        assert_eq!(
+            resolve_error("class A {} class B extends A { static f() { super.f(); } }"),
+            "cannot use `super` in a static method"
+        );
     }
 }
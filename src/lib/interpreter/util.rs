@@ -25,24 +25,31 @@ macro_rules! check_or {
     };
 }
 
-/// Performs a binary operation between two `[LoxValue]`s, using the given operator.
+/// Performs a binary operation between two numeric `[LoxValue]`s, using the given operator.
+/// The operands are [`promote`](super::values::promote)d first.
 macro_rules! binop {
     ($lhs:expr, $rhs:expr, $op:tt) => {
         {
-            // if at least one of them is decimal, then we return a decimal
-            if $lhs.is_decimal() || $rhs.is_decimal() {
-                return Ok(LoxValue::Decimal(($lhs.to_dec() $op $rhs.to_dec())));
+            match promote(&$lhs, &$rhs)? {
+                Promoted::Integers(lhs, rhs) => Ok(LoxValue::Integer(lhs $op rhs)),
+                Promoted::Decimals(lhs, rhs) => Ok(LoxValue::Decimal(lhs $op rhs)),
             }
-
-            // here we definitely have two integers
-            Ok(LoxValue::Integer(($lhs.to_int() $op $rhs.to_int())))
         }
     }
 }
+/// Compares two `[LoxValue]`s with the given operator. Strings are compared lexicographically,
+/// numbers are [`promote`](super::values::promote)d first.
 macro_rules! cmpop {
     ($lhs:expr, $rhs:expr, $op:tt) => {
         {
-            Ok(LoxValue::Boolean(($lhs.to_dec() $op $rhs.to_dec())))
+            if let (LoxValue::String(lhs), LoxValue::String(rhs)) = ($lhs, $rhs) {
+                return Ok(LoxValue::Boolean(lhs $op rhs));
+            }
+            check_or!(LoxValue::is_num, $lhs, $rhs; "operands must be two numbers or two strings");
+            match promote(&$lhs, &$rhs)? {
+                Promoted::Integers(lhs, rhs) => Ok(LoxValue::Boolean(lhs $op rhs)),
+                Promoted::Decimals(lhs, rhs) => Ok(LoxValue::Boolean(lhs $op rhs)),
+            }
         }
     }
 }
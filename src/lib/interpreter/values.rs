@@ -1,43 +1,86 @@
-use crate::lib::{
+use crate::{
     error::{LoxError, LoxResult},
-    parser::Expr,
+    sync::{Rc, RefCell, Shared},
     token::{Keyword, Numeric, TokenKind},
 };
 use std::{
-    cell::RefCell,
     cmp::PartialEq,
-    collections::HashMap,
     convert::TryFrom,
-    ops::{Add, Div, Mul, Neg, Sub},
-    rc::Rc,
+    ops::{Add, Div, Mul, Neg, Rem, Sub},
 };
 
-use super::{class::LoxInstance, Environment, LoxClass};
+use super::{class::LoxInstance, Environment, Interpreter, LoxClass};
+use derive_new::new;
 
 /// Internal language types
 #[derive(Clone)]
-pub(crate) enum LoxValue {
-    String(String),
+pub enum LoxValue {
+    /// Shared with the literal it comes from, if any
+    String(Rc<str>),
     Nil,
     Decimal(f64),
     Integer(isize),
     Boolean(bool),
     Array(RefCell<Vec<Rc<LoxValue>>>),
+    /// Integers from the start up to, but not including, the end
+    Range(isize, isize),
     Callable(Rc<dyn LoxCallable>),
     Instance(LoxInstance),
+    /// Member of an enum, only equal to itself
+    Variant(Rc<Variant>),
+    /// An enum declaration, its members can be read but not assigned
+    Enum(Rc<LoxEnum>),
 }
 
-pub(crate) trait LoxCallable {
+/// A member of an enum declaration.
+#[derive(Debug, new)]
+pub struct Variant {
+    /// Name of the enum it was declared in
+    pub enum_name: String,
+    pub name: String,
+}
+
+/// The namespace of an enum declaration, holding its members in declaration order.
+#[derive(Debug)]
+pub struct LoxEnum {
+    pub name: String,
+    pub members: Vec<Rc<Variant>>,
+}
+
+impl LoxEnum {
+    pub fn new(name: String, members: &[String]) -> Self {
+        let members = members
+            .iter()
+            .map(|member| Rc::new(Variant::new(name.clone(), member.clone())))
+            .collect();
+        Self { name, members }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Rc<Variant>> {
+        self.members
+            .iter()
+            .find(|member| member.name == name)
+            .map(Rc::clone)
+    }
+}
+
+pub trait LoxCallable: Shared {
     fn call(
         &self,
         env: Rc<Environment>,
-        locals: &HashMap<Expr, usize>,
+        interpreter: &Interpreter,
         args: &[Rc<LoxValue>],
     ) -> LoxResult<Rc<LoxValue>>;
     fn arity(&self) -> usize {
         0
     }
 
+    /// Whether the callable accepts more than [`arity`](LoxCallable::arity) arguments, in which
+    /// case the arity is the least amount it takes.
+    fn variadic(&self) -> bool {
+        false
+    }
+
     fn to_string(&self) -> String {
         String::from("<native fn>")
     }
@@ -56,29 +99,71 @@ impl LoxValue {
         }
     }
 
+    /// Name of the runtime type of the value, as used by type annotations: `number`,
+    /// `string`, `bool`, `nil`, `array`, `range`, `fn` or `enum`. Instances are named after
+    /// their class and enum members after their enum.
+    pub fn type_name(&self) -> String {
+        match self {
+            Self::Integer(_) | Self::Decimal(_) => "number".into(),
+            Self::String(_) => "string".into(),
+            Self::Boolean(_) => "bool".into(),
+            Self::Nil => "nil".into(),
+            Self::Array(_) => "array".into(),
+            Self::Range(..) => "range".into(),
+            Self::Callable(_) => "fn".into(),
+            Self::Instance(instance) => instance.class_name(),
+            Self::Variant(variant) => variant.enum_name.clone(),
+            Self::Enum(_) => "enum".into(),
+        }
+    }
+
+    /// Raises the value to the power of `exp`. Integers stay integers unless the exponent is
+    /// negative, which gives a decimal: `2 ** -1` is `0.5`.
+    pub fn pow(&self, exp: &Self) -> LoxResult<LoxValue> {
+        check_or!(LoxValue::is_num, self, exp; "operands must be numbers");
+        match promote(self, exp)? {
+            Promoted::Integers(base, exp) if exp < 0 => {
+                Ok(LoxValue::Decimal((base as f64).powf(exp as f64)))
+            }
+            Promoted::Integers(base, exp) => u32::try_from(exp)
+                .ok()
+                .and_then(|exp| base.checked_pow(exp))
+                .map(LoxValue::Integer)
+                .ok_or_else(|| LoxError::Generic("exponentiation overflowed".to_string())),
+            Promoted::Decimals(base, exp) => Ok(LoxValue::Decimal(base.powf(exp))),
+        }
+    }
+
     pub fn ge(&self, oth: &Self) -> LoxResult<LoxValue> {
-        check_or!(LoxValue::is_num, self, oth; "operands must be numbers");
         cmpop!(self, oth, >=)
     }
 
     pub fn gt(&self, oth: &Self) -> LoxResult<LoxValue> {
-        check_or!(LoxValue::is_num, self, oth; "operands must be numbers");
         cmpop!(self, oth, >)
     }
+
     pub fn le(&self, oth: &Self) -> LoxResult<LoxValue> {
-        check_or!(LoxValue::is_num, self, oth; "operands must be numbers");
         cmpop!(self, oth, <=)
     }
+
     pub fn lt(&self, oth: &Self) -> LoxResult<LoxValue> {
-        check_or!(LoxValue::is_num, self, oth; "operands must be numbers");
         cmpop!(self, oth, <)
     }
 
-    fn to_int(&self) -> isize {
+    /// Converts the value where an integer is required, decimals must have no fractional part
+    /// and fit in an [`isize`].
+    pub fn to_int(&self) -> LoxResult<isize> {
         match self {
-            Self::Decimal(d) => *d as isize,
-            Self::Integer(i) => *i,
-            _ => unreachable!(),
+            Self::Integer(i) => Ok(*i),
+            Self::Decimal(d)
+                if d.fract() == 0.0 && *d >= isize::MIN as f64 && *d < isize::MAX as f64 =>
+            {
+                Ok(*d as isize)
+            }
+            oth => Err(LoxError::Generic(format!(
+                "expected an integer, got {}",
+                oth
+            ))),
         }
     }
 
@@ -102,7 +187,7 @@ impl LoxValue {
         matches!(self, Self::String(_))
     }
 
-    pub fn as_class(&self) -> LoxResult<&LoxClass> {
+    pub(crate) fn as_class(&self) -> LoxResult<&LoxClass> {
         if let Self::Callable(c) = self {
             return c
                 .as_any()
@@ -112,7 +197,7 @@ impl LoxValue {
         Err(LoxError::Generic(String::from("not callable")))
     }
 
-    pub fn as_instance(&self) -> LoxResult<&LoxInstance> {
+    pub(crate) fn as_instance(&self) -> LoxResult<&LoxInstance> {
         if let Self::Instance(i) = self {
             return Ok(i);
         }
@@ -120,11 +205,30 @@ impl LoxValue {
     }
 }
 
+/// Operands of a numeric operation, converted to a common type by [`promote`].
+#[derive(Debug, PartialEq)]
+pub(crate) enum Promoted {
+    Integers(isize, isize),
+    Decimals(f64, f64),
+}
+
+/// Converts two numbers to a common type: two integers stay integers, while a decimal on
+/// either side turns both into decimals.
+pub(crate) fn promote(lhs: &LoxValue, rhs: &LoxValue) -> LoxResult<Promoted> {
+    match (lhs, rhs) {
+        (LoxValue::Integer(lhs), LoxValue::Integer(rhs)) => Ok(Promoted::Integers(*lhs, *rhs)),
+        (lhs, rhs) if lhs.is_num() && rhs.is_num() => {
+            Ok(Promoted::Decimals(lhs.to_dec(), rhs.to_dec()))
+        }
+        _ => Err(LoxError::Generic(String::from("operands must be numbers"))),
+    }
+}
+
 impl TryFrom<&TokenKind> for LoxValue {
     type Error = &'static str;
     fn try_from(t: &TokenKind) -> Result<Self, Self::Error> {
         match t {
-            TokenKind::StringLiteral(s) => Ok(LoxValue::String(s.to_string())),
+            TokenKind::StringLiteral(s) => Ok(LoxValue::String(Rc::clone(s.as_rc()))),
             TokenKind::BooleanLiteral(b) => Ok(LoxValue::Boolean(*b)),
             TokenKind::NumericLiteral(Numeric::Integer(i)) => Ok(LoxValue::Integer(*i)),
             TokenKind::NumericLiteral(Numeric::Decimal(d)) => Ok(LoxValue::Decimal(*d)),
@@ -136,7 +240,8 @@ impl TryFrom<&TokenKind> for LoxValue {
 
 impl std::fmt::Display for LoxValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match &*self {
+        match self {
+            LoxValue::Decimal(d) if d.is_nan() => write!(f, "nan"),
             LoxValue::Decimal(d) => write!(f, "{}", d),
             LoxValue::Integer(i) => write!(f, "{}", i),
             LoxValue::Boolean(b) => write!(f, "{}", b),
@@ -146,35 +251,100 @@ impl std::fmt::Display for LoxValue {
             LoxValue::Instance(instance) => {
                 write!(f, "{}", instance)
             }
+            LoxValue::Variant(variant) => write!(f, "{}", variant.name),
+            LoxValue::Enum(e) => {
+                let members: Vec<_> = e.members.iter().map(|m| m.name.as_str()).collect();
+                write!(f, "enum {} {{ {} }}", e.name, members.join(", "))
+            }
+            LoxValue::Range(start, end) => write!(f, "{}..{}", start, end),
+            LoxValue::Array(values) => {
+                let printable = array_items(&values.borrow(), LoxValue::to_string);
+                write!(f, "[{}]", printable.join(", "))
+            }
+        }
+    }
+}
+
+/// Renders the elements of an array with `show`, runs of `nil` between them are shown as
+/// their count. Trailing ones are left out.
+fn array_items(values: &[Rc<LoxValue>], show: impl Fn(&LoxValue) -> String) -> Vec<String> {
+    let mut counter = 0;
+    let mut printable = Vec::with_capacity(values.len());
+    for value in values {
+        match **value {
+            LoxValue::Nil => {
+                counter += 1;
+                continue;
+            }
+            _ => {
+                if counter != 0 {
+                    printable.push(format!("<{} empty values>", counter));
+                    counter = 0;
+                }
+                printable.push(show(value));
+            }
+        }
+    }
+    printable
+}
+
+impl LoxValue {
+    /// Renders the value like [`Display`](std::fmt::Display) does, except that arrays holding
+    /// arrays or instances, and instances, put each element or field on its own line, indented
+    /// two spaces deeper than their container.
+    pub fn pretty(&self) -> String {
+        self.pretty_at(0)
+    }
+
+    fn pretty_at(&self, depth: usize) -> String {
+        let is_container =
+            |value: &LoxValue| matches!(value, LoxValue::Array(_) | LoxValue::Instance(_));
+        let block = |open: &str, items: Vec<String>, close: &str| {
+            if items.is_empty() {
+                return format!("{}{}", open, close);
+            }
+            let indent = "  ".repeat(depth + 1);
+            format!(
+                "{}\n{}{}\n{}{}",
+                open,
+                indent,
+                items.join(&format!(",\n{}", indent)),
+                "  ".repeat(depth),
+                close
+            )
+        };
+
+        match self {
             LoxValue::Array(values) => {
                 let values = values.borrow();
-                let mut counter = 0;
-                let mut printable = Vec::with_capacity(values.len());
-                for value in &*values {
-                    match **value {
-                        LoxValue::Nil => {
-                            counter += 1;
-                            continue;
-                        }
-                        _ => {
-                            if counter != 0 {
-                                printable.push(format!("<{} empty values>", counter));
-                                counter = 0;
-                            }
-                            printable.push(value.to_string());
-                        }
-                    }
+                if !values.iter().any(|value| is_container(value)) {
+                    return self.to_string();
                 }
-                write!(f, "[{}]", printable.join(", "))
+                let items = array_items(&values, |value| value.pretty_at(depth + 1));
+                block("[", items, "]")
             }
+            LoxValue::Instance(instance) => {
+                let items = instance
+                    .fields()
+                    .into_iter()
+                    .map(|(name, value)| format!("{}: {}", name, value.pretty_at(depth + 1)))
+                    .collect();
+                block(
+                    &format!("{} instance {{", instance.class_name()),
+                    items,
+                    "}",
+                )
+            }
+            value => value.to_string(),
         }
     }
 }
 impl std::fmt::Debug for LoxValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match &*self {
+        match self {
             LoxValue::String(s) => write!(f, "\"{}\"", s),
-            s => write!(f, "{}", s.to_string()),
+            LoxValue::Variant(v) => write!(f, "{}.{}", v.enum_name, v.name),
+            s => write!(f, "{}", s),
         }
     }
 }
@@ -194,14 +364,29 @@ impl Div for LoxValue {
 
     fn div(self, rhs: Self) -> Self::Output {
         check_or!(LoxValue::is_num, &self, &rhs; "operands must be numbers");
-        let zero = LoxValue::Decimal(0.0);
-        if self.eq(&zero) || rhs.eq(&zero) {
+        if rhs.eq(&LoxValue::Decimal(0.0)) {
             return Err(LoxError::Generic("attempt to divide by zero".to_string()));
         }
         binop!(self, rhs, /)
     }
 }
 
+impl Rem for LoxValue {
+    type Output = LoxResult<Self>;
+
+    /// The remainder of a truncated division, taking the sign of the dividend: `-7 % 3` is
+    /// `-1`, for integers and decimals alike.
+    fn rem(self, rhs: Self) -> Self::Output {
+        check_or!(LoxValue::is_num, &self, &rhs; "operands must be numbers");
+        if rhs.eq(&LoxValue::Decimal(0.0)) {
+            return Err(LoxError::Generic(
+                "attempt to calculate the remainder with a divisor of zero".to_string(),
+            ));
+        }
+        binop!(self, rhs, %)
+    }
+}
+
 impl Mul for LoxValue {
     type Output = LoxResult<Self>;
 
@@ -215,20 +400,24 @@ impl Mul for LoxValue {
             } else {
                 (rhs, self)
             };
-            if !num.is_decimal() {
+            if !num.is_decimal() && matches!(oth, LoxValue::Array(_) | LoxValue::String(_)) {
+                let count = num.to_int()?;
+                let count = usize::try_from(count).map_err(|_| {
+                    LoxError::Generic(format!(
+                        "cannot repeat a negative number of times, got `{}`",
+                        count
+                    ))
+                })?;
                 match oth {
                     LoxValue::Array(ref vec) => {
-                        let new = std::iter::repeat(&(*vec.borrow()))
-                            .take(num.to_int() as usize)
+                        let new = std::iter::repeat_n(&(*vec.borrow()), count)
                             .flatten()
                             .cloned()
                             .collect::<Vec<_>>();
                         return Ok(LoxValue::Array(RefCell::new(new)));
                     }
-                    LoxValue::String(s) => {
-                        return Ok(LoxValue::String(s.repeat(num.to_int() as usize)))
-                    }
-                    _ => todo!(),
+                    LoxValue::String(s) => return Ok(LoxValue::String(s.repeat(count).into())),
+                    _ => unreachable!(),
                 };
             }
         }
@@ -248,7 +437,7 @@ impl Add for LoxValue {
         }
 
         if self.is_string() || rhs.is_string() {
-            return Ok(LoxValue::String(format!("{}{}", self, rhs)));
+            return Ok(LoxValue::String(format!("{}{}", self, rhs).into()));
         }
 
         Err(LoxError::Generic(
@@ -291,16 +480,141 @@ impl PartialEq for LoxValue {
                 }
                 false
             }
-            _ => {
-                if !oth.is_num() {
-                    return false;
+            LoxValue::Instance(i) => {
+                if let LoxValue::Instance(oth) = oth {
+                    return i.is(oth);
                 }
+                false
+            }
+            LoxValue::Integer(_) | LoxValue::Decimal(_) => match promote(self, oth) {
+                Ok(Promoted::Integers(lhs, rhs)) => lhs == rhs,
+                // As per IEEE 754, `nan` is not equal to anything, itself included
+                Ok(Promoted::Decimals(lhs, rhs)) => lhs == rhs,
+                Err(_) => false,
+            },
+            LoxValue::Variant(v) => {
+                if let LoxValue::Variant(oth) = oth {
+                    return Rc::ptr_eq(v, oth);
+                }
+                false
+            }
+            LoxValue::Enum(e) => matches!(oth, LoxValue::Enum(oth) if Rc::ptr_eq(e, oth)),
+            LoxValue::Range(start, end) => {
+                matches!(oth, LoxValue::Range(s, e) if s == start && e == end)
+            }
+            LoxValue::Array(_) | LoxValue::Callable(_) => false,
+        }
+    }
+}
 
-                let lhs = self.to_dec();
-                let rhs = oth.to_dec();
-
-                lhs.eq(&rhs)
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn arithmetic_promotes_to_decimal_if_either_operand_is() {
+        use LoxValue::{Decimal as D, Integer as I};
+        type Op = fn(LoxValue, LoxValue) -> LoxResult<LoxValue>;
+        let ops: [(&str, Op); 4] = [
+            ("+", |a, b| a + b),
+            ("-", |a, b| a - b),
+            ("*", |a, b| a * b),
+            ("/", |a, b| a / b),
+        ];
+        let operands = [
+            (I(6), I(4), "integer"),
+            (I(6), D(4.0), "decimal"),
+            (D(6.0), I(4), "decimal"),
+            (D(6.0), D(4.0), "decimal"),
+        ];
+
+        for (name, op) in &ops {
+            for (lhs, rhs, expected) in &operands {
+                let found = match op(lhs.clone(), rhs.clone()).unwrap() {
+                    I(_) => "integer",
+                    D(_) => "decimal",
+                    oth => panic!("{:?} {} {:?} gave {:?}", lhs, name, rhs, oth),
+                };
+                assert_eq!(found, *expected, "{:?} {} {:?}", lhs, name, rhs);
             }
         }
+        assert!(matches!(I(6) / I(4), Ok(I(1))));
+        assert!(matches!(I(6) / D(4.0), Ok(D(d)) if d == 1.5));
+    }
+
+    #[test]
+    fn comparisons_promote_their_operands() {
+        use LoxValue::{Boolean, Decimal as D, Integer as I};
+        assert!(matches!(I(1).lt(&D(1.5)), Ok(Boolean(true))));
+        assert!(matches!(D(1.5).ge(&I(2)), Ok(Boolean(false))));
+        // Too large to be told apart as decimals
+        assert!(matches!(
+            I(isize::MAX).gt(&I(isize::MAX - 1)),
+            Ok(Boolean(true))
+        ));
+        assert_ne!(I(isize::MAX), I(isize::MAX - 1));
+        assert_eq!(I(3), D(3.0));
+        assert!(promote(&I(1), &LoxValue::Nil).is_err());
+    }
+
+    #[test]
+    fn nan_is_not_equal_to_anything() {
+        use LoxValue::{Boolean, Decimal as D, Integer as I};
+        assert_ne!(D(f64::NAN), D(f64::NAN));
+        assert_ne!(D(f64::NAN), I(0));
+        assert!(matches!(D(f64::NAN).lt(&I(1)), Ok(Boolean(false))));
+        assert!(matches!(D(f64::NAN).ge(&I(1)), Ok(Boolean(false))));
+        assert!(matches!(D(f64::NAN) + I(1), Ok(D(d)) if d.is_nan()));
+        assert!(matches!(D(f64::NAN) * D(0.0), Ok(D(d)) if d.is_nan()));
+        assert_eq!(D(f64::NAN).to_string(), "nan");
+    }
+
+    #[test]
+    fn infinities_order_past_every_other_number() {
+        use LoxValue::{Boolean, Decimal as D, Integer as I};
+        assert!(matches!(D(f64::INFINITY).gt(&D(1e300)), Ok(Boolean(true))));
+        assert!(matches!(
+            D(f64::INFINITY).gt(&I(isize::MAX)),
+            Ok(Boolean(true))
+        ));
+        assert!(matches!(
+            D(f64::NEG_INFINITY).lt(&I(isize::MIN)),
+            Ok(Boolean(true))
+        ));
+        assert_eq!(D(f64::INFINITY), D(f64::INFINITY));
+        assert!(matches!(D(f64::INFINITY) - D(f64::INFINITY), Ok(D(d)) if d.is_nan()));
+        assert_eq!(D(f64::INFINITY).to_string(), "inf");
+        assert_eq!((-D(f64::INFINITY)).unwrap().to_string(), "-inf");
+    }
+
+    #[test]
+    fn pretty_prints_nested_arrays_and_instances() {
+        use crate::interpreter::test::global;
+        let src = r#"
+            class Point { init(x, y) { this.y = y; this.x = x; } }
+            class Empty {}
+            let x = [1, [2, [3, "four"]], nil, nil, Point(5, [6]), Empty(), []];
+        "#;
+        let expected = "\
+[
+  1,
+  [
+    2,
+    [3, four]
+  ],
+  <2 empty values>,
+  Point instance {
+    x: 5,
+    y: [6]
+  },
+  Empty instance {},
+  []
+]";
+        assert_eq!(global(src, "x").pretty(), expected);
+        assert_eq!(
+            global("let x = [1, [], 2];", "x").pretty(),
+            "[\n  1,\n  [],\n  2\n]"
+        );
+        assert_eq!(global("let x = [1, 2];", "x").pretty(), "[1, 2]");
     }
 }
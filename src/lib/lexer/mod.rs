@@ -1,11 +1,14 @@
 pub(crate) mod token;
 
-use crate::lib::{
-    error::InnerError,
+use crate::{
+    error::{InnerError, LoxError},
+    grammar::Grammar,
     position::{Cursor, Position, Span},
     LoxResult,
 };
-use token::{Numeric, Punctuator, Token, TokenKind};
+use std::num::IntErrorKind;
+use token::{Interner, Numeric, Punctuator, Token, TokenKind};
+use unicode_xid::UnicodeXID;
 
 use self::token::Keyword;
 
@@ -13,6 +16,34 @@ pub struct Lexer<'a> {
     buffer: Cursor<'a>,
     start: Position,
     tokens: Vec<Token>,
+    interner: Interner,
+    /// Lex integer literals that overflow as decimals instead of failing
+    lossy_integers: bool,
+    /// Custom operators to lex
+    grammar: Option<&'a Grammar>,
+    /// Comments found so far, only kept if asked to
+    comments: Option<Vec<Comment>>,
+}
+
+/// A comment kept by [`Lexer::keep_comments`], attached to the token next to it.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Comment {
+    /// Text of the comment, `//` or `/* */` included
+    pub text: String,
+    pub span: Span,
+    pub placement: Placement,
+    /// Index of the token the comment is attached to. For a leading comment at the end of
+    /// the file, that's the amount of tokens.
+    pub token: usize,
+}
+
+/// Where a comment is, relative to the token it's attached to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Placement {
+    /// On its own line, before the token
+    Leading,
+    /// After the token, on the same line
+    Trailing,
 }
 
 impl<'a> Lexer<'a> {
@@ -21,9 +52,38 @@ impl<'a> Lexer<'a> {
             start: Position::new(1, 1),
             buffer: Cursor::new(src.chars().peekable()),
             tokens: Default::default(),
+            interner: Default::default(),
+            lossy_integers: false,
+            grammar: None,
+            comments: None,
         }
     }
 
+    /// Keeps the comments instead of dropping them, so a formatter can put them back. Use
+    /// [`scan_tokens_with_comments`](Self::scan_tokens_with_comments) to get them.
+    pub fn keep_comments(mut self) -> Self {
+        self.comments = Some(Vec::new());
+        self
+    }
+
+    /// Lexes the operators registered in `grammar` as [`TokenKind::Operator`].
+    pub fn grammar(mut self, grammar: &'a Grammar) -> Self {
+        self.grammar = Some(grammar);
+        self
+    }
+
+    fn is_operator(&self, symbol: &str) -> bool {
+        self.grammar
+            .is_some_and(|grammar| grammar.is_operator(symbol))
+    }
+
+    /// When enabled, integer literals too large for an integer are lexed as decimals,
+    /// losing precision past 2^53.
+    pub fn lossy_integers(mut self, enabled: bool) -> Self {
+        self.lossy_integers = enabled;
+        self
+    }
+
     fn add_token(&mut self, tk: impl Into<TokenKind>) {
         let token = Token::new(tk.into(), Span::new(self.start, self.buffer.pos()));
         self.tokens.push(token);
@@ -41,10 +101,16 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    /// Lexes a string, decoding the escapes `\n`, `\t`, `\r`, `\\`, `\"` and `\0`.
     fn lex_string(&mut self) -> LoxResult<()> {
         let mut buf = String::new();
         loop {
             match self.buffer.next() {
+                Some('\\') => {
+                    self.buffer.next_column();
+                    let backslash = self.buffer.pos();
+                    buf.push(self.lex_escape(backslash)?);
+                }
                 Some(c) if c != '\n' => {
                     self.buffer.next_column();
                     if c == '"' {
@@ -61,18 +127,134 @@ impl<'a> Lexer<'a> {
                 }
             }
         }
-        self.add_token(TokenKind::string_literal(buf));
+        let lit = self.interner.intern(&buf);
+        self.add_token(TokenKind::string_literal(lit));
         Ok(())
     }
 
+    /// Decodes the char escaped by the backslash at `backslash`, just consumed.
+    fn lex_escape(&mut self, backslash: Position) -> LoxResult<char> {
+        let escaped = match self.buffer.peek_next() {
+            Some(c) if c != '\n' => c,
+            // Let the string report itself as unterminated
+            _ => return Ok('\\'),
+        };
+        self.buffer.next_column();
+        self.buffer.next();
+        Ok(match escaped {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '\\' => '\\',
+            '"' => '"',
+            '0' => '\0',
+            c => {
+                return Err(InnerError::new(
+                    Span::new(backslash, self.buffer.pos()),
+                    &format!("unknown escape sequence `\\{}`", c),
+                )
+                .into())
+            }
+        })
+    }
+
+    /// Whether the `r` just consumed opens a raw string: `r"`, `r#"`, `r##"`...
+    fn starts_raw_string(&self) -> bool {
+        let hashes = (0..)
+            .take_while(|&n| self.buffer.peek_nth(n) == Some('#'))
+            .count();
+        self.buffer.peek_nth(hashes) == Some('"')
+    }
+
+    /// Lexes a raw string, where escapes aren't processed. It ends with a `"` followed by as
+    /// many `#` as it started with, so `r#"say "hi""#` can hold quotes.
+    fn lex_raw_string(&mut self) -> LoxResult<()> {
+        let mut hashes = 0;
+        while let Some(c) = self.buffer.peek_next() {
+            self.buffer.next_column();
+            self.buffer.next();
+            if c == '"' {
+                break;
+            }
+            hashes += 1;
+        }
+        let opening = Span::new(self.start, self.buffer.pos());
+        let mut buf = String::new();
+        loop {
+            match self.buffer.peek_next() {
+                Some('"') if (1..=hashes).all(|n| self.buffer.peek_nth(n) == Some('#')) => {
+                    for _ in 0..=hashes {
+                        self.buffer.next_column();
+                        self.buffer.next();
+                    }
+                    break;
+                }
+                Some(c) if c != '\n' => {
+                    self.buffer.next_column();
+                    self.buffer.next();
+                    buf.push(c);
+                }
+                _ => return Err(InnerError::new(opening, "unterminated raw string").into()),
+            }
+        }
+        let lit = self.interner.intern(&buf);
+        self.add_token(TokenKind::string_literal(lit));
+        Ok(())
+    }
+
+    /// Lexes a numeric literal, with an optional suffix forcing its type: `5i` is always an
+    /// integer and `5f` always a decimal.
+    ///
+    /// A `.` only continues the number if a digit follows it, so `3.foo` is `3` `.` `foo`.
     fn lex_numeric(&mut self, start: char) -> LoxResult<()> {
-        let buf = self
-            .buffer
-            .take_char_while(start, |c| c.is_ascii_digit() || c == '.')?
-            .parse::<Numeric>()
-            .map_err(|e| {
-                InnerError::new(Span::new(self.start, self.buffer.pos()), &e.to_string())
-            })?;
+        let is_suffix = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        let mut literal = self.buffer.take_char_while(start, |c| c.is_ascii_digit())?;
+        if self.buffer.peek_next() == Some('.')
+            && matches!(self.buffer.peek_second(), Some(c) if c.is_ascii_digit())
+        {
+            self.buffer.next_column();
+            self.buffer.next();
+            literal += &self.buffer.take_char_while('.', |c| c.is_ascii_digit())?;
+        }
+        if let Some(c) = self.buffer.peek_next().filter(|&c| is_suffix(c)) {
+            self.buffer.next_column();
+            self.buffer.next();
+            literal += &self.buffer.take_char_while(c, is_suffix)?;
+        }
+        let span = Span::new(self.start, self.buffer.pos());
+        let (digits, suffix) = literal.split_at(
+            literal
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(literal.len()),
+        );
+
+        let buf = match (digits.parse::<Numeric>(), suffix) {
+            (Err(LoxError::ParseInt(e)), "" | "f") if e.kind() == &IntErrorKind::PosOverflow => {
+                if suffix.is_empty() && !self.lossy_integers {
+                    return Err(InnerError::new(span, "integer literal is too large").into());
+                }
+                Numeric::Decimal(digits.parse()?)
+            }
+            (Err(LoxError::ParseInt(e)), "i") if e.kind() == &IntErrorKind::PosOverflow => {
+                return Err(InnerError::new(span, "integer literal is too large").into());
+            }
+            (Err(e), _) => return Err(InnerError::new(span, &e.to_string()).into()),
+            (Ok(num), "") => num,
+            (Ok(num), "f") => Numeric::Decimal(num.inner()),
+            (Ok(Numeric::Integer(i)), "i") => Numeric::Integer(i),
+            (Ok(Numeric::Decimal(_)), "i") => {
+                return Err(
+                    InnerError::new(span, "decimal literal cannot have an `i` suffix").into(),
+                )
+            }
+            (Ok(_), suffix) => {
+                return Err(InnerError::new(
+                    span,
+                    &format!("invalid suffix `{}` for numeric literal", suffix),
+                )
+                .into())
+            }
+        };
         self.add_token(TokenKind::numeric_literal(buf));
         Ok(())
     }
@@ -80,14 +262,17 @@ impl<'a> Lexer<'a> {
     fn lex_identifier(&mut self, start: char) -> LoxResult<()> {
         let ident = match self
             .buffer
-            .take_char_while(start, |c| c.is_ascii_alphanumeric())?
+            .take_char_while(start, UnicodeXID::is_xid_continue)?
             .parse::<Keyword>()
         {
             Ok(kw) => TokenKind::keyword(kw),
             Err(ident) => match ident.as_str() {
                 "true" => true.into(),
                 "false" => false.into(),
-                _ => TokenKind::identifier(ident),
+                "nan" => Numeric::Decimal(f64::NAN).into(),
+                "inf" => Numeric::Decimal(f64::INFINITY).into(),
+                _ if self.is_operator(&ident) => TokenKind::Operator(self.interner.intern(&ident)),
+                _ => TokenKind::identifier(self.interner.intern(&ident)),
             },
         };
         self.add_token(ident);
@@ -97,23 +282,140 @@ impl<'a> Lexer<'a> {
     fn lex_slash(&mut self) -> LoxResult<()> {
         match self.buffer.peek_next() {
             Some('/') => {
-                self.buffer.consume_until('\n');
+                match self.comments {
+                    Some(_) => self.add_comment(),
+                    None => self.buffer.consume_until('\n'),
+                }
                 self.buffer.next_line();
             }
+            Some('*') => self.lex_block_comment()?,
             _ => self.add_if_next('=', Punctuator::AssignDiv, Punctuator::Div, Self::add_token),
         }
         Ok(())
     }
 
+    /// Skips a `/* */` comment, or keeps it like line comments. Block comments nest, so
+    /// `/* a /* b */ c */` is a single comment.
+    fn lex_block_comment(&mut self) -> LoxResult<()> {
+        self.buffer.next_column();
+        self.buffer.next();
+        let opening = Span::new(self.start, self.buffer.pos());
+        let mut text = String::from("/*");
+        let mut depth = 1;
+        let mut prev = '*';
+        while depth > 0 {
+            let c = self.buffer.next().ok_or_else(|| {
+                LoxError::from(InnerError::new(opening, "unterminated block comment"))
+            })?;
+            if prev == '\n' {
+                self.buffer.next_line();
+            } else {
+                self.buffer.next_column();
+            }
+            text.push(c);
+            prev = c;
+            match (c, self.buffer.peek_next()) {
+                ('/', Some('*')) => depth += 1,
+                ('*', Some('/')) => depth -= 1,
+                _ => continue,
+            }
+            // The second char of the delimiter can't start another one
+            self.buffer.next_column();
+            text.extend(self.buffer.next());
+            prev = ' ';
+        }
+        if self.comments.is_some() {
+            let (placement, token) = self.placement();
+            let comment = Comment {
+                text,
+                span: Span::new(self.start, self.buffer.pos()),
+                placement,
+                token,
+            };
+            self.comments.get_or_insert_with(Vec::new).push(comment);
+        }
+        self.buffer.next_column();
+        Ok(())
+    }
+
+    /// Where a comment starting at the current token would be, and the token it's attached to.
+    fn placement(&self) -> (Placement, usize) {
+        match self.tokens.last() {
+            Some(tk) if tk.span().end().line_number() == self.start.line_number() => {
+                (Placement::Trailing, self.tokens.len() - 1)
+            }
+            _ => (Placement::Leading, self.tokens.len()),
+        }
+    }
+
+    fn add_comment(&mut self) {
+        let text = format!("/{}", self.buffer.take_until('\n'));
+        let end = Position::new(
+            self.start.line_number(),
+            self.start.column_number() + text.chars().count() as u32 - 1,
+        );
+        let (placement, token) = self.placement();
+        let comment = Comment {
+            text,
+            span: Span::new(self.start, end),
+            placement,
+            token,
+        };
+        self.comments.get_or_insert_with(Vec::new).push(comment);
+    }
+
+    fn lex_dot(&mut self) {
+        if let Some('.') = self.buffer.peek_next() {
+            self.buffer.next_column();
+            self.buffer.next();
+            if let Some('.') = self.buffer.peek_next() {
+                self.buffer.next_column();
+                self.buffer.next();
+                self.add_token(Punctuator::Ellipsis);
+                return;
+            }
+            self.add_token(Punctuator::DotDot);
+            return;
+        }
+        self.add_token(Punctuator::Dot);
+    }
+
+    fn lex_star(&mut self) {
+        if let Some('*') = self.buffer.peek_next() {
+            self.buffer.next_column();
+            self.buffer.next();
+            self.add_if_next('=', Punctuator::AssignPow, Punctuator::Pow, Self::add_token);
+            return;
+        }
+        self.add_if_next('=', Punctuator::AssignMul, Punctuator::Mul, Self::add_token)
+    }
+
+    fn lex_equals(&mut self) {
+        if let Some('>') = self.buffer.peek_next() {
+            self.buffer.next_column();
+            self.buffer.next();
+            self.add_token(Punctuator::FatArrow);
+            return;
+        }
+        self.add_if_next('=', Punctuator::Eq, Punctuator::Assign, Self::add_token)
+    }
+
     fn lex_pipe(&mut self) -> LoxResult<()> {
         if let Some('>') = self.buffer.peek_next() {
             self.buffer.next_column();
             self.buffer.next();
             self.add_token(Punctuator::Pipe);
+            return Ok(());
         }
-        Ok(())
+        Err(InnerError::new(self.buffer.pos().into(), "unexpected character `|`").into())
+    }
+    pub(crate) fn scan_tokens(self) -> LoxResult<Vec<Token>> {
+        self.scan_tokens_with_comments().map(|(tokens, _)| tokens)
     }
-    pub(crate) fn scan_tokens(mut self) -> LoxResult<Vec<Token>> {
+
+    /// Scans the tokens, along with the comments if [`keep_comments`](Self::keep_comments)
+    /// was enabled.
+    pub(crate) fn scan_tokens_with_comments(mut self) -> LoxResult<(Vec<Token>, Vec<Comment>)> {
         use Punctuator::*;
         while let Some(ch) = self.buffer.next() {
             match ch {
@@ -126,20 +428,28 @@ impl<'a> Lexer<'a> {
                 '[' => self.add_token(OpenBracket),
                 ']' => self.add_token(CloseBracket),
                 ',' => self.add_token(Comma),
-                '.' => self.add_token(Dot),
+                '.' => self.lex_dot(),
+                ':' => self.add_token(Colon),
                 ';' => self.add_token(Semicolon),
                 '+' => self.add_if_next('=', AssignAdd, Add, Self::add_token),
-                '*' => self.add_if_next('=', AssignMul, Mul, Self::add_token),
+                '*' => self.lex_star(),
+                '%' => self.add_token(Mod),
                 '-' => self.add_if_next('=', AssignSub, Sub, Self::add_token),
                 '!' => self.add_if_next('=', NotEq, Not, Self::add_token),
-                '=' => self.add_if_next('=', Eq, Assign, Self::add_token),
+                '=' => self.lex_equals(),
                 '>' => self.add_if_next('=', GreaterThanOrEq, GreaterThan, Self::add_token),
                 '<' => self.add_if_next('=', LessThanOrEq, LessThan, Self::add_token),
+                '?' => self.add_if_next('=', AssignDefault, Question, Self::add_token),
                 '/' => self.lex_slash()?,
                 '"' => self.lex_string()?,
                 '|' => self.lex_pipe()?,
-                _ if ch.is_digit(10) => self.lex_numeric(ch)?,
-                _ if ch.is_ascii_alphabetic() || ch.eq(&'_') => self.lex_identifier(ch)?,
+                'r' if self.starts_raw_string() => self.lex_raw_string()?,
+                _ if ch.is_ascii_digit() => self.lex_numeric(ch)?,
+                _ if ch.is_xid_start() || ch.eq(&'_') => self.lex_identifier(ch)?,
+                _ if self.is_operator(ch.encode_utf8(&mut [0; 4])) => {
+                    let op = self.interner.intern(&ch.to_string());
+                    self.add_token(TokenKind::Operator(op))
+                }
                 err => {
                     return Err(InnerError::new(
                         self.buffer.pos().into(),
@@ -150,6 +460,403 @@ impl<'a> Lexer<'a> {
             }
             self.start = self.buffer.pos();
         }
-        Ok(self.tokens)
+        Ok((self.tokens, self.comments.unwrap_or_default()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn kinds(src: &str) -> Vec<TokenKind> {
+        Lexer::new(src)
+            .scan_tokens()
+            .unwrap()
+            .iter()
+            .map(|tk| tk.kind().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn lexes_not_eq_as_a_single_token() {
+        assert_eq!(
+            kinds("a != b"),
+            vec![
+                TokenKind::identifier("a"),
+                Punctuator::NotEq.into(),
+                TokenKind::identifier("b"),
+            ]
+        );
+        assert_eq!(
+            kinds("!!x"),
+            vec![
+                Punctuator::Not.into(),
+                Punctuator::Not.into(),
+                TokenKind::identifier("x"),
+            ]
+        );
+        assert_eq!(
+            kinds("! ="),
+            vec![Punctuator::Not.into(), Punctuator::Assign.into()]
+        );
+    }
+
+    #[test]
+    fn drops_comments_unless_asked_to_keep_them() {
+        let src = "// header\nlet x = 1; // note\n\nprint x;\n// footer";
+        let (tokens, comments) = Lexer::new(src).scan_tokens_with_comments().unwrap();
+        assert_eq!(tokens.len(), 8);
+        assert!(comments.is_empty());
+
+        let (tokens, comments) = Lexer::new(src)
+            .keep_comments()
+            .scan_tokens_with_comments()
+            .unwrap();
+        assert_eq!(tokens.len(), 8);
+        let found: Vec<_> = comments
+            .iter()
+            .map(|c| (c.text.as_str(), c.placement, c.token, c.span.to_string()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("// header", Placement::Leading, 0, "[1:1..1:9]".to_string()),
+                (
+                    "// note",
+                    Placement::Trailing,
+                    4,
+                    "[2:12..2:18]".to_string()
+                ),
+                ("// footer", Placement::Leading, 8, "[5:1..5:9]".to_string()),
+            ]
+        );
+        assert_eq!(tokens[4].to_string(), ";");
+        assert_eq!(tokens[4].span().end().line_number(), 2);
+    }
+
+    #[test]
+    fn skips_nested_block_comments() {
+        let ident = |name| TokenKind::identifier(name);
+        assert_eq!(kinds("a /* b */ c"), vec![ident("a"), ident("c")]);
+        assert_eq!(kinds("/* a /* b */ c */ x"), vec![ident("x")]);
+        assert_eq!(
+            kinds("/*/ still open */ y /**/z"),
+            vec![ident("y"), ident("z")]
+        );
+        assert_eq!(
+            kinds("a / /* * / */ b"),
+            vec![ident("a"), Punctuator::Div.into(), ident("b")]
+        );
+    }
+
+    #[test]
+    fn keeps_block_comments_spanning_lines() {
+        let src = "let x = 1; /* one\n /* two */ */\nprint x;";
+        let (tokens, comments) = Lexer::new(src)
+            .keep_comments()
+            .scan_tokens_with_comments()
+            .unwrap();
+        assert_eq!(comments.len(), 1);
+        assert_eq!(comments[0].text, "/* one\n /* two */ */");
+        assert_eq!(comments[0].placement, Placement::Trailing);
+        assert_eq!(comments[0].token, 4);
+        assert_eq!(comments[0].span.to_string(), "[1:12..2:13]");
+        assert_eq!(tokens[5].to_string(), "print");
+        assert_eq!(tokens[5].span().to_string(), "[3:1..3:5]");
+    }
+
+    #[test]
+    fn unterminated_block_comments_point_at_the_opening() {
+        colored::control::set_override(false);
+        let src = "let a; /* open /* nested */";
+        let err = Lexer::new(src).scan_tokens().unwrap_err();
+        assert_eq!(err.to_string(), "unterminated block comment");
+        let rendered = crate::error::InterpreterError::from(err, src).to_string();
+        assert!(rendered.contains(":1:8\n"), "{}", rendered);
+    }
+
+    #[test]
+    fn lexes_underscores_inside_identifiers() {
+        assert_eq!(
+            kinds("round_to _a b_2"),
+            vec![
+                TokenKind::identifier("round_to"),
+                TokenKind::identifier("_a"),
+                TokenKind::identifier("b_2"),
+            ]
+        );
+    }
+
+    #[test]
+    fn lexes_unicode_identifiers() {
+        assert_eq!(
+            kinds("café λ _ñ ω2"),
+            vec![
+                TokenKind::identifier("café"),
+                TokenKind::identifier("λ"),
+                TokenKind::identifier("_ñ"),
+                TokenKind::identifier("ω2"),
+            ]
+        );
+        let err = Lexer::new("let €uro = 1;").scan_tokens().unwrap_err();
+        assert!(err.to_string().contains("unexpected character `€`"));
+    }
+
+    #[test]
+    fn lexes_multi_char_operators_as_single_tokens() {
+        use Punctuator::*;
+        let expected: Vec<TokenKind> = vec![
+            Eq.into(),
+            NotEq.into(),
+            LessThanOrEq.into(),
+            GreaterThanOrEq.into(),
+            AssignAdd.into(),
+            AssignSub.into(),
+            AssignMul.into(),
+            AssignDiv.into(),
+            Pipe.into(),
+        ];
+        assert_eq!(kinds("== != <= >= += -= *= /= |>"), expected);
+
+        // Without whitespace between them, operators still split on their longest match
+        assert_eq!(
+            kinds("a<=b==c!=!d"),
+            vec![
+                TokenKind::identifier("a"),
+                LessThanOrEq.into(),
+                TokenKind::identifier("b"),
+                Eq.into(),
+                TokenKind::identifier("c"),
+                NotEq.into(),
+                Not.into(),
+                TokenKind::identifier("d"),
+            ]
+        );
+        assert_eq!(kinds("===>=="), vec![Eq.into(), FatArrow.into(), Eq.into()]);
+    }
+
+    #[test]
+    fn lexes_ellipsis() {
+        assert_eq!(
+            kinds("...a.b"),
+            vec![
+                Punctuator::Ellipsis.into(),
+                TokenKind::identifier("a"),
+                Punctuator::Dot.into(),
+                TokenKind::identifier("b"),
+            ]
+        );
+        assert_eq!(
+            kinds("a..b"),
+            vec![
+                TokenKind::identifier("a"),
+                Punctuator::DotDot.into(),
+                TokenKind::identifier("b"),
+            ]
+        );
+    }
+
+    #[test]
+    fn dots_only_continue_numbers_before_a_digit() {
+        use Punctuator::*;
+        let num = |n| TokenKind::numeric_literal(Numeric::Integer(n));
+        assert_eq!(
+            kinds("3.5 3.5f"),
+            vec![
+                TokenKind::numeric_literal(Numeric::Decimal(3.5)),
+                TokenKind::numeric_literal(Numeric::Decimal(3.5)),
+            ]
+        );
+        assert_eq!(
+            kinds("3.foo 3 . foo"),
+            vec![
+                num(3),
+                Dot.into(),
+                TokenKind::identifier("foo"),
+                num(3),
+                Dot.into(),
+                TokenKind::identifier("foo"),
+            ]
+        );
+        assert_eq!(kinds("3."), vec![num(3), Dot.into()]);
+        assert_eq!(kinds("3...5"), vec![num(3), Ellipsis.into(), num(5)]);
+        assert_eq!(kinds("3..5"), vec![num(3), DotDot.into(), num(5)]);
+    }
+
+    #[test]
+    fn stars_lex_greedily() {
+        use Punctuator::*;
+        assert_eq!(
+            kinds("a ** b *= c * d"),
+            vec![
+                TokenKind::identifier("a"),
+                Pow.into(),
+                TokenKind::identifier("b"),
+                AssignMul.into(),
+                TokenKind::identifier("c"),
+                Mul.into(),
+                TokenKind::identifier("d"),
+            ]
+        );
+        assert_eq!(kinds("***"), vec![Pow.into(), Mul.into()]);
+        assert_eq!(
+            kinds("a **= b"),
+            vec![
+                TokenKind::identifier("a"),
+                AssignPow.into(),
+                TokenKind::identifier("b"),
+            ]
+        );
+    }
+
+    #[test]
+    fn lone_pipe_is_an_error() {
+        let err = Lexer::new("a | b").scan_tokens().unwrap_err();
+        assert_eq!(err.to_string(), "unexpected character `|`");
+    }
+
+    #[test]
+    fn numeric_suffixes_force_the_literal_type() {
+        let num = |src| match Lexer::new(src).scan_tokens().unwrap()[0].kind() {
+            TokenKind::NumericLiteral(num) => *num,
+            kind => panic!("expected a number, got {}", kind),
+        };
+        assert!(matches!(num("5"), Numeric::Integer(5)));
+        assert!(matches!(num("5i"), Numeric::Integer(5)));
+        assert!(matches!(num("5f"), Numeric::Decimal(d) if d == 5.0));
+        assert!(matches!(num("5.5f"), Numeric::Decimal(d) if d == 5.5));
+        assert!(matches!(num("99999999999999999999f"), Numeric::Decimal(d) if d == 1e20));
+    }
+
+    #[test]
+    fn invalid_numeric_suffixes_are_errors() {
+        let err = |src| Lexer::new(src).scan_tokens().unwrap_err().to_string();
+        assert_eq!(err("5.5i"), "decimal literal cannot have an `i` suffix");
+        assert_eq!(err("5u"), "invalid suffix `u` for numeric literal");
+        assert_eq!(err("5foo"), "invalid suffix `foo` for numeric literal");
+        assert_eq!(err("99999999999999999999i"), "integer literal is too large");
+    }
+
+    #[test]
+    fn overflowing_integers_error_by_default() {
+        let err = Lexer::new("99999999999999999999")
+            .scan_tokens()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "integer literal is too large");
+    }
+
+    #[test]
+    fn overflowing_integers_become_decimals_when_lossy() {
+        let tokens = Lexer::new("99999999999999999999 7")
+            .lossy_integers(true)
+            .scan_tokens()
+            .unwrap();
+        assert!(matches!(
+            tokens[0].kind(),
+            TokenKind::NumericLiteral(Numeric::Decimal(d)) if *d == 1e20
+        ));
+        assert!(matches!(
+            tokens[1].kind(),
+            TokenKind::NumericLiteral(Numeric::Integer(7))
+        ));
+    }
+
+    #[test]
+    fn identical_identifiers_share_a_symbol() {
+        let tokens = Lexer::new("foo bar foo").scan_tokens().unwrap();
+        match (tokens[0].kind(), tokens[1].kind(), tokens[2].kind()) {
+            (
+                TokenKind::Identifier(first),
+                TokenKind::Identifier(other),
+                TokenKind::Identifier(second),
+            ) => {
+                assert!(std::ptr::eq(first.as_str(), second.as_str()));
+                assert_ne!(first, other);
+            }
+            kinds => panic!("expected identifiers, got {:?}", kinds),
+        }
+    }
+
+    #[test]
+    fn decodes_escapes_in_strings() {
+        assert_eq!(
+            kinds(r#""line1\nline2""#),
+            vec![TokenKind::string_literal("line1\nline2")]
+        );
+        assert_eq!(
+            kinds(r#""\t\r\\\"\0""#),
+            vec![TokenKind::string_literal("\t\r\\\"\0")]
+        );
+        assert_eq!(
+            kinds(r#""she said \"hi\"" + "\\""#),
+            vec![
+                TokenKind::string_literal(r#"she said "hi""#),
+                Punctuator::Add.into(),
+                TokenKind::string_literal("\\"),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_escapes_point_at_the_sequence() {
+        colored::control::set_override(false);
+        let src = r#"let s = "a\qb";"#;
+        let err = Lexer::new(src).scan_tokens().unwrap_err();
+        assert_eq!(err.to_string(), r"unknown escape sequence `\q`");
+        let rendered = crate::error::InterpreterError::from(err, src).to_string();
+        assert!(rendered.contains(":1:11\n"), "{}", rendered);
+        let err = Lexer::new(r#""ends with \"#).scan_tokens().unwrap_err();
+        assert_eq!(err.to_string(), "unterminated string");
+    }
+
+    #[test]
+    fn lexes_raw_strings_without_processing_escapes() {
+        assert_eq!(
+            kinds(r#"r"a\nb""#),
+            vec![TokenKind::string_literal("a\\nb")]
+        );
+        assert_eq!(
+            kinds(r##"r#"say "hi""#"##),
+            vec![TokenKind::string_literal(r#"say "hi""#)]
+        );
+        assert_eq!(
+            kinds(r###"r##"a"#b"## + r"""###),
+            vec![
+                TokenKind::string_literal(r##"a"#b"##),
+                Punctuator::Add.into(),
+                TokenKind::string_literal(""),
+            ]
+        );
+        // Without a quote right after it, `r` is a plain identifier
+        assert_eq!(
+            kinds(r#"r + r "a""#),
+            vec![
+                TokenKind::identifier("r"),
+                Punctuator::Add.into(),
+                TokenKind::identifier("r"),
+                TokenKind::string_literal("a"),
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_raw_strings_point_at_the_opening() {
+        colored::control::set_override(false);
+        for src in [r##"let s = r#"a"b";"##, "let s = r\"a\nb\";"] {
+            let err = Lexer::new(src).scan_tokens().unwrap_err();
+            assert_eq!(err.to_string(), "unterminated raw string");
+            let rendered = crate::error::InterpreterError::from(err, src).to_string();
+            assert!(rendered.contains(":1:9\n"), "{}", rendered);
+        }
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench_`.
+    #[test]
+    #[ignore]
+    fn bench_lexing_repeated_identifiers() {
+        let src = "let counter = counter + other_counter * counter;\n".repeat(100_000);
+        let start = std::time::Instant::now();
+        let tokens = Lexer::new(&src).scan_tokens().unwrap();
+        println!("lexed {} tokens in {:?}", tokens.len(), start.elapsed());
     }
 }
@@ -1,6 +1,6 @@
-use std::str::FromStr;
+use std::{collections::HashSet, str::FromStr};
 
-use crate::lib::{error::LoxError, position::Span};
+use crate::{error::LoxError, position::Span, sync::Rc};
 
 #[derive(Debug, Hash, Clone, PartialEq, Eq)]
 pub struct Token {
@@ -35,10 +35,12 @@ impl std::fmt::Display for Token {
 pub(crate) enum TokenKind {
     Keyword(Keyword),
     Punctuator(Punctuator),
-    Identifier(Box<str>),
-    StringLiteral(Box<str>),
+    Identifier(Symbol),
+    StringLiteral(Symbol),
     NumericLiteral(Numeric),
     BooleanLiteral(bool),
+    /// A prefix operator registered in the [`Grammar`](crate::grammar::Grammar)
+    Operator(Symbol),
 }
 
 impl From<bool> for TokenKind {
@@ -70,11 +72,11 @@ impl TokenKind {
         Self::Keyword(kw)
     }
 
-    pub fn identifier<I: Into<Box<str>>>(ident: I) -> Self {
+    pub fn identifier<I: Into<Symbol>>(ident: I) -> Self {
         Self::Identifier(ident.into())
     }
 
-    pub fn string_literal<S: Into<Box<str>>>(lit: S) -> Self {
+    pub fn string_literal<S: Into<Symbol>>(lit: S) -> Self {
         Self::StringLiteral(lit.into())
     }
 
@@ -93,10 +95,77 @@ impl std::fmt::Display for TokenKind {
             TokenKind::NumericLiteral(Numeric::Integer(n)) => write!(f, "{}", n),
             TokenKind::NumericLiteral(Numeric::Decimal(n)) => write!(f, "{}", n),
             TokenKind::BooleanLiteral(ref b) => write!(f, "{}", b),
+            TokenKind::Operator(ref op) => write!(f, "{}", op),
         }
     }
 }
 
+/// A reference counted string, cheap to clone and compare.
+///
+/// Symbols handed out by the same [`Interner`] share their storage, so comparing them is
+/// usually just a pointer comparison.
+#[derive(Debug, Clone, Eq)]
+pub(crate) struct Symbol(Rc<str>);
+
+impl Symbol {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The shared storage of the symbol.
+    pub fn as_rc(&self) -> &Rc<str> {
+        &self.0
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, oth: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &oth.0) || self.0 == oth.0
+    }
+}
+
+impl std::hash::Hash for Symbol {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(s: &str) -> Self {
+        Self(s.into())
+    }
+}
+
+impl From<String> for Symbol {
+    fn from(s: String) -> Self {
+        Self(s.into())
+    }
+}
+
+impl std::fmt::Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Deduplicates the strings of a source file, so every occurrence of a name shares one
+/// allocation.
+#[derive(Debug, Default)]
+pub(crate) struct Interner {
+    symbols: HashSet<Rc<str>>,
+}
+
+impl Interner {
+    pub fn intern(&mut self, s: &str) -> Symbol {
+        if let Some(symbol) = self.symbols.get(s) {
+            return Symbol(Rc::clone(symbol));
+        }
+        let symbol: Rc<str> = s.into();
+        self.symbols.insert(Rc::clone(&symbol));
+        Symbol(symbol)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Punctuator {
     OpenParen,
@@ -106,11 +175,14 @@ pub enum Punctuator {
     OpenBracket,
     CloseBracket,
     Pipe,
+    FatArrow,
     Assign,
     AssignAdd,
     AssignSub,
     AssignMul,
     AssignDiv,
+    AssignPow,
+    AssignDefault,
     Not,
     Eq,
     NotEq,
@@ -118,8 +190,14 @@ pub enum Punctuator {
     Sub,
     Mul,
     Div,
+    Mod,
+    Pow,
     Dot,
+    DotDot,
+    Ellipsis,
     Comma,
+    Colon,
+    Question,
     Semicolon,
     GreaterThan,
     GreaterThanOrEq,
@@ -141,17 +219,25 @@ impl std::fmt::Display for Punctuator {
                 Punctuator::CloseBracket => "]",
                 Punctuator::Comma => ",",
                 Punctuator::Dot => ".",
+                Punctuator::DotDot => "..",
+                Punctuator::Ellipsis => "...",
+                Punctuator::Colon => ":",
+                Punctuator::Question => "?",
                 Punctuator::Semicolon => ";",
                 Punctuator::Assign => "=",
                 Punctuator::AssignAdd => "+=",
                 Punctuator::AssignSub => "-=",
                 Punctuator::AssignDiv => "/=",
                 Punctuator::AssignMul => "*=",
+                Punctuator::AssignPow => "**=",
+                Punctuator::AssignDefault => "?=",
                 Punctuator::Eq => "==",
                 Punctuator::Sub => "-",
                 Punctuator::Add => "+",
                 Punctuator::Div => "/",
                 Punctuator::Mul => "*",
+                Punctuator::Mod => "%",
+                Punctuator::Pow => "**",
                 Punctuator::GreaterThan => ">",
                 Punctuator::GreaterThanOrEq => ">=",
                 Punctuator::LessThan => "<",
@@ -159,6 +245,7 @@ impl std::fmt::Display for Punctuator {
                 Punctuator::Not => "!",
                 Punctuator::NotEq => "!=",
                 Punctuator::Pipe => "|>",
+                Punctuator::FatArrow => "=>",
             }
         )
     }
@@ -182,6 +269,52 @@ pub enum Keyword {
     This,
     Extends,
     Static,
+    Break,
+    With,
+    As,
+    Const,
+    Assert,
+    Enum,
+    Repeat,
+    Defer,
+    Match,
+    Loop,
+    Continue,
+    In,
+}
+
+impl Keyword {
+    /// Every keyword, in declaration order.
+    pub const ALL: [Keyword; 28] = [
+        Keyword::And,
+        Keyword::Class,
+        Keyword::Else,
+        Keyword::Let,
+        Keyword::While,
+        Keyword::Fn,
+        Keyword::For,
+        Keyword::If,
+        Keyword::Nil,
+        Keyword::Or,
+        Keyword::Print,
+        Keyword::Return,
+        Keyword::Super,
+        Keyword::This,
+        Keyword::Extends,
+        Keyword::Static,
+        Keyword::Break,
+        Keyword::With,
+        Keyword::As,
+        Keyword::Const,
+        Keyword::Assert,
+        Keyword::Enum,
+        Keyword::Repeat,
+        Keyword::Defer,
+        Keyword::Match,
+        Keyword::Loop,
+        Keyword::Continue,
+        Keyword::In,
+    ];
 }
 
 impl std::fmt::Display for Keyword {
@@ -206,6 +339,18 @@ impl std::fmt::Display for Keyword {
                 Keyword::This => "this",
                 Keyword::Extends => "extends",
                 Keyword::Static => "static",
+                Keyword::Break => "break",
+                Keyword::With => "with",
+                Keyword::As => "as",
+                Keyword::Const => "const",
+                Keyword::Assert => "assert",
+                Keyword::Enum => "enum",
+                Keyword::Repeat => "repeat",
+                Keyword::Defer => "defer",
+                Keyword::Match => "match",
+                Keyword::Loop => "loop",
+                Keyword::Continue => "continue",
+                Keyword::In => "in",
             }
         )
     }
@@ -226,6 +371,18 @@ impl FromStr for Keyword {
             "nil" => Ok(Keyword::Nil),
             "or" => Ok(Keyword::Or),
             "print" => Ok(Keyword::Print),
+            "break" => Ok(Keyword::Break),
+            "with" => Ok(Keyword::With),
+            "as" => Ok(Keyword::As),
+            "const" => Ok(Keyword::Const),
+            "assert" => Ok(Keyword::Assert),
+            "enum" => Ok(Keyword::Enum),
+            "repeat" => Ok(Keyword::Repeat),
+            "defer" => Ok(Keyword::Defer),
+            "match" => Ok(Keyword::Match),
+            "loop" => Ok(Keyword::Loop),
+            "continue" => Ok(Keyword::Continue),
+            "in" => Ok(Keyword::In),
             "return" => Ok(Keyword::Return),
             "super" => Ok(Keyword::Super),
             "this" => Ok(Keyword::This),
@@ -255,7 +412,11 @@ impl std::cmp::Eq for Numeric {}
 
 impl std::cmp::PartialEq for Numeric {
     fn eq(&self, oth: &Self) -> bool {
-        self.inner().eq(&oth.inner())
+        // A `nan` literal is still the same token as itself
+        match (self, oth) {
+            (Self::Decimal(lhs), Self::Decimal(rhs)) if lhs.is_nan() => rhs.is_nan(),
+            _ => self.inner().eq(&oth.inner()),
+        }
     }
 }
 
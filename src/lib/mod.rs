@@ -1,83 +1,195 @@
-use error::{InterpreterError, LoxError, LoxResult};
+use error::{InterpreterError, Source};
 use interpreter::Resolver;
-use std::{io::Write, path::PathBuf, rc::Rc};
+use std::{io::Write, path::PathBuf};
 
 mod error;
+mod formatter;
+mod grammar;
 mod interpreter;
 mod lexer;
+mod optimizer;
 pub(crate) mod parser;
 pub(crate) mod position;
+mod sync;
+mod typechecker;
 
 pub(crate) use lexer::token;
 use lexer::Lexer;
+use token::{Keyword, Punctuator, TokenKind};
 
+use grammar::Grammar;
 use interpreter::Interpreter;
 use parser::Parser;
+use typechecker::TypeChecker;
 
-use self::{interpreter::Environment, parser::Stmt};
+use self::{interpreter::Environment, parser::Stmt, sync::Rc};
 
-pub struct Lox;
+pub use self::{
+    error::{LoxError, LoxResult},
+    grammar::OperatorFn,
+    interpreter::LoxValue,
+};
+
+/// Optional passes toggled from the command line.
+#[derive(Debug, Default, Clone)]
+pub struct Config {
+    /// Run the type checker before executing a program
+    pub typecheck: bool,
+    /// Warn about code that is valid but likely a mistake
+    pub strict: bool,
+    /// Lex integer literals too large for an integer as (imprecise) decimals
+    pub lossy_integers: bool,
+    /// Report the lines that never executed once a file finishes running
+    pub lines: bool,
+    /// Remove `assert` statements before running
+    pub strip_asserts: bool,
+    /// List the scopes searched when a variable is not found
+    pub debug_scopes: bool,
+    /// Check returned values against the return type annotations at runtime
+    pub check_returns: bool,
+    /// Dump the syntax tree of every parsed input to stderr
+    pub debug_parse: bool,
+    /// Print the syntax tree of a file to stdout instead of running it
+    pub dump_ast: bool,
+    /// Print a file formatted to stdout instead of running it
+    pub fmt: bool,
+}
+
+/// Entry point of the interpreter.
+///
+/// Every run builds its own environment, values never outlive it, so a single `Lox` can be
+/// shared between threads, each one running its own independent programs. With the `sync`
+/// feature environments and values can be shared between threads as well.
+pub struct Lox {
+    config: Config,
+    grammar: Grammar,
+}
+
+/// State kept between the inputs of a REPL session.
+struct ReplSession {
+    env: Rc<Environment>,
+    /// Result of the last evaluated expression
+    last: Option<Rc<LoxValue>>,
+}
+
+impl ReplSession {
+    fn new() -> Self {
+        let env = Rc::new(Environment::new());
+        Interpreter::define_globals(Rc::clone(&env));
+        Self { env, last: None }
+    }
+
+    /// Completions for the word being typed at the end of `input`: keywords, and the globals
+    /// defined so far, native functions included.
+    ///
+    /// The REPL reads plain lines, so they're listed by the `:complete` command rather than
+    /// on tab.
+    fn complete(&self, input: &str) -> Vec<String> {
+        let start = input
+            .rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .map_or(0, |idx| idx + 1);
+        let prefix = &input[start..];
+        if prefix.is_empty() {
+            return Vec::new();
+        }
+
+        let mut candidates: Vec<_> = Keyword::ALL
+            .iter()
+            .map(Keyword::to_string)
+            .chain(self.env.names())
+            .filter(|name| name.starts_with(prefix) && name != prefix)
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        candidates
+    }
+}
 
 impl Lox {
-    pub(crate) fn do_file(path: PathBuf) -> LoxResult<()> {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            grammar: Default::default(),
+        }
+    }
+
+    /// Registers a prefix operator evaluated with the native function `apply`, with the same
+    /// precedence as `-x`.
+    ///
+    /// The symbol is either a single character the language doesn't use already, like `√`, or
+    /// a word that isn't a keyword, which can't be used as an identifier anymore.
+    pub fn prefix_operator(mut self, symbol: &str, apply: OperatorFn) -> LoxResult<Self> {
+        self.grammar.prefix_operator(symbol, apply)?;
+        Ok(self)
+    }
+
+    /// Runs the script at `path`, or dumps its tree or formats it, reporting its errors to
+    /// stderr. Returns whether there were none.
+    pub fn do_file(&self, path: PathBuf) -> LoxResult<bool> {
         let src = std::fs::read_to_string(&path)?;
 
         if src.trim().is_empty() {
-            return Ok(());
+            return Ok(true);
         }
 
-        std::env::set_var("LOX_SRC_FILE", &*path.to_string_lossy());
+        let name = path.to_string_lossy();
+        let src = Source {
+            name: &name,
+            text: &src,
+        };
 
-        let run = |src| {
-            let statements = Self::parse(src)?;
-            Self::execute(statements)
+        let run = |src: Source| {
+            if self.config.fmt {
+                print!("{}", self.format(src.text)?);
+                return Ok(());
+            }
+            let statements = self.parse(src.text.to_string())?;
+            if self.config.dump_ast {
+                println!("{}", Self::render_tree(&statements));
+                return Ok(());
+            }
+            self.execute(statements, src)
         };
 
-        if let Err(errors) = run(src.to_string()) {
+        if let Err(errors) = run(src) {
             for e in errors {
-                let e = InterpreterError::from(e, &src);
+                let e = InterpreterError::in_source(e, src);
                 eprintln!("{}\n", e);
             }
+            return Ok(false);
         }
-        Ok(())
+        Ok(true)
     }
 
-    pub(crate) fn do_repl() -> LoxResult<()> {
+    pub fn do_repl(&self) -> LoxResult<()> {
         println!("\u{001b}c");
         let mut buf = String::with_capacity(4096);
-        std::env::set_var("LOX_SRC_FILE", "REPL");
-        let env = Rc::new(Environment::new());
+        let mut session = ReplSession::new();
+        self.grammar.define(&session.env);
         loop {
-            print!("> ");
+            print!("{}", if buf.is_empty() { "> " } else { ". " });
             std::io::stdout().flush()?;
-            std::io::stdin().read_line(&mut buf)?;
-
-            let run = |src| {
-                let statements = Self::parse(src)?;
-                if statements.len().eq(&1) {
-                    if let Some(Stmt::Expression(expr)) = statements.first() {
-                        println!(
-                            "{}",
-                            expr.evaluate(Rc::clone(&env), &Default::default())
-                                .map_err(|e| vec![e])?
-                                .to_string()
-                        );
-                        return Ok(());
-                    }
-                }
-                if statements.is_empty() {
-                    return Ok(());
-                }
+            let mut line = String::new();
+            if std::io::stdin().read_line(&mut line)? == 0 {
+                return Ok(());
+            }
 
-                let interpreter = Interpreter::with_env(&statements, Rc::clone(&env));
-                let resolver = Resolver::new(&interpreter);
-                resolver.resolve(&statements).map_err(|e| vec![e])?;
-                interpreter.interpret()
-            };
+            // A trailing `\` always continues the input on the next line, and a blank line
+            // runs it even if it's incomplete
+            if let Some(line) = line.trim_end().strip_suffix('\\') {
+                buf.push_str(line);
+                buf.push('\n');
+                continue;
+            }
+            let blank = line.trim().is_empty();
+            buf.push_str(&line);
+            if !blank && self.is_incomplete(&buf) {
+                continue;
+            }
 
-            if let Err(errors) = run(buf.to_string()) {
+            if let Err(errors) = self.eval_line(&mut session, &buf, &mut std::io::stdout()) {
                 for e in errors {
-                    let e = InterpreterError::from(e, &buf);
+                    let e = InterpreterError::in_source(e, Self::repl_source(&buf));
                     eprintln!("{}\n", e);
                 }
             }
@@ -85,19 +197,480 @@ impl Lox {
         }
     }
 
-    fn parse(src: String) -> Result<Vec<Stmt>, Vec<LoxError>> {
-        let tokens = Lexer::new(&src).scan_tokens().map_err(|e| vec![e])?;
-        Parser::new(&tokens).parse()
+    /// Whether the REPL input `src` has unclosed parentheses, braces or brackets, so more lines
+    /// are needed before running it. Input that doesn't lex is complete, reporting its error.
+    fn is_incomplete(&self, src: &str) -> bool {
+        let tokens = match Lexer::new(src)
+            .lossy_integers(self.config.lossy_integers)
+            .grammar(&self.grammar)
+            .scan_tokens()
+        {
+            Ok(tokens) => tokens,
+            Err(_) => return false,
+        };
+        let depth = tokens.iter().fold(0isize, |depth, tk| match tk.kind() {
+            TokenKind::Punctuator(
+                Punctuator::OpenParen | Punctuator::OpenBlock | Punctuator::OpenBracket,
+            ) => depth + 1,
+            TokenKind::Punctuator(
+                Punctuator::CloseParen | Punctuator::CloseBlock | Punctuator::CloseBracket,
+            ) => depth - 1,
+            _ => depth,
+        });
+        depth > 0
     }
 
-    fn execute(statements: Vec<Stmt>) -> Result<(), Vec<LoxError>> {
+    /// Runs a single REPL input, writing its results to `out`. A lone expression, whose `;`
+    /// can be left out, has its value echoed unless it's `nil`.
+    ///
+    /// Besides Lox code, the REPL understands the following commands:
+    /// - `:last` prints the result of the last evaluated expression again.
+    /// - `:complete <input>` lists the completions of the word `<input>` ends with.
+    fn eval_line(
+        &self,
+        session: &mut ReplSession,
+        src: &str,
+        out: &mut dyn Write,
+    ) -> Result<(), Vec<LoxError>> {
+        let io_err = |e: std::io::Error| vec![LoxError::from(e)];
+
+        if src.trim() == ":last" {
+            return match &session.last {
+                Some(val) => writeln!(out, "{}", val),
+                None => writeln!(out, "{}", LoxValue::Nil),
+            }
+            .map_err(io_err);
+        }
+        if let Some(input) = src.trim_end().strip_prefix(":complete ") {
+            return writeln!(out, "{}", session.complete(input).join(" ")).map_err(io_err);
+        }
+
+        let statements = match self.parse(src.to_string()) {
+            Ok(statements) => statements,
+            // A lone expression can leave out its `;`
+            Err(errors) => match self.parse(format!("{};", src.trim_end())) {
+                Ok(statements) if matches!(statements.as_slice(), [Stmt::Expression(_)]) => {
+                    statements
+                }
+                _ => return Err(errors),
+            },
+        };
+        if let [Stmt::Expression(_)] = statements.as_slice() {
+            let interpreter =
+                self.configure(Interpreter::with_env(&statements, Rc::clone(&session.env)));
+            let val = interpreter.interpret_value(out)?;
+            // Echoed unless there's nothing to show, like the result of calling a function
+            // that returns nothing
+            if !matches!(*val, LoxValue::Nil) {
+                writeln!(out, "{}", val).map_err(io_err)?;
+            }
+            session.last = Some(val);
+            return Ok(());
+        }
         if statements.is_empty() {
             return Ok(());
         }
+        // Type errors are reported on the code as written, not as optimized
+        self.typecheck(&statements)?;
+        let statements = self.optimize(statements);
+
+        let interpreter =
+            self.configure(Interpreter::with_env(&statements, Rc::clone(&session.env)));
+        self.resolve(&interpreter, &statements, Self::repl_source(src))?;
+        interpreter.interpret_with(out)
+    }
+
+    /// Names the inputs of the REPL in errors.
+    fn repl_source(text: &str) -> Source<'_> {
+        Source { name: "REPL", text }
+    }
+
+    fn parse(&self, src: String) -> Result<Vec<Stmt>, Vec<LoxError>> {
+        let tokens = Lexer::new(&src)
+            .lossy_integers(self.config.lossy_integers)
+            .grammar(&self.grammar)
+            .scan_tokens()
+            .map_err(|e| vec![e])?;
+        let statements = Parser::new(&tokens).parse()?;
+        if let Some(dump) = self.parse_dump(&statements) {
+            eprintln!("{}", dump);
+        }
+        Ok(statements)
+    }
+
+    /// Formats a program, keeping its comments. Programs that don't parse are reported rather
+    /// than formatted.
+    fn format(&self, src: &str) -> Result<String, Vec<LoxError>> {
+        let (tokens, comments) = Lexer::new(src)
+            .lossy_integers(self.config.lossy_integers)
+            .grammar(&self.grammar)
+            .keep_comments()
+            .scan_tokens_with_comments()
+            .map_err(|e| vec![e])?;
+        Parser::new(&tokens).parse()?;
+        Ok(formatter::format(src, &tokens, &comments))
+    }
+
+    /// Renders the syntax tree, with the span of every node, when parse debugging is on.
+    fn parse_dump(&self, statements: &[Stmt]) -> Option<String> {
+        if !self.config.debug_parse {
+            return None;
+        }
+        Some(Self::render_tree(statements))
+    }
+
+    /// Renders the syntax tree of a program, a line per node, indenting the children of a
+    /// node under it.
+    fn render_tree(statements: &[Stmt]) -> String {
+        let nodes: Vec<_> = statements
+            .iter()
+            .map(|stmt| format!("{:#?}", stmt))
+            .collect();
+        nodes.join("\n")
+    }
+
+    /// Runs a program, returning the value of its last statement if it's an expression
+    /// statement, so `x;` gives the value of `x` but `print x;` gives nil.
+    ///
+    /// What the program prints goes to stdout.
+    pub fn do_string(&self, src: &str) -> Result<Rc<LoxValue>, Vec<LoxError>> {
+        let statements = self.parse(src.to_string())?;
+        if statements.is_empty() {
+            return Ok(Rc::new(LoxValue::Nil));
+        }
+        let src = Source {
+            name: "<input>",
+            text: src,
+        };
+        let mut stdout = std::io::stdout();
+        let (interpreter, _) = self.prepare(statements, src)?;
+        interpreter.interpret_value(&mut stdout)
+    }
+
+    /// Checks, optimizes and resolves a program, ready to run in a new environment. The
+    /// optimized statements are returned along with the interpreter.
+    fn prepare<'o>(
+        &self,
+        statements: Vec<Stmt>,
+        src: Source,
+    ) -> Result<(Interpreter<'o>, Vec<Stmt>), Vec<LoxError>> {
+        self.typecheck(&statements)?;
+        let statements = self.optimize(statements);
+
+        let interpreter = self
+            .configure(Interpreter::new(&statements))
+            .record_coverage(self.config.lines);
+        self.grammar.define(interpreter.globals());
+        self.resolve(&interpreter, &statements, src)?;
+        Ok((interpreter, statements))
+    }
+
+    fn execute(&self, statements: Vec<Stmt>, src: Source) -> Result<(), Vec<LoxError>> {
+        if statements.is_empty() {
+            return Ok(());
+        }
+        let (interpreter, statements) = self.prepare(statements, src)?;
+        let result = interpreter.interpret();
+        if let Some(coverage) = interpreter.coverage(&statements) {
+            eprintln!("{}", coverage);
+        }
+        result
+    }
+
+    /// Applies the runtime options of the configuration to `interpreter`.
+    fn configure<'o>(&self, interpreter: Interpreter<'o>) -> Interpreter<'o> {
+        interpreter
+            .debug_scopes(self.config.debug_scopes)
+            .check_returns(self.config.check_returns)
+    }
+
+    /// Resolves the program's variables, reporting any warnings found to stderr.
+    fn resolve(
+        &self,
+        interpreter: &Interpreter,
+        statements: &[Stmt],
+        src: Source,
+    ) -> Result<(), Vec<LoxError>> {
+        let resolver = Resolver::new(interpreter).strict(self.config.strict);
+        let resolved = statements
+            .iter()
+            .try_for_each(|stmt| resolver.resolve(stmt));
+        for warning in resolver.take_warnings() {
+            eprintln!("{}\n", InterpreterError::warning(warning, src));
+        }
+        resolved.map_err(|e| vec![e])
+    }
+
+    fn optimize(&self, statements: Vec<Stmt>) -> Vec<Stmt> {
+        let statements = optimizer::optimize(statements);
+        if self.config.strip_asserts {
+            return optimizer::strip_asserts(statements);
+        }
+        statements
+    }
+
+    fn typecheck(&self, statements: &[Stmt]) -> Result<(), Vec<LoxError>> {
+        if self.config.typecheck {
+            TypeChecker::check(statements)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Feeds each line to a fresh REPL session, returning the concatenated output.
+    fn repl(lines: &[&str]) -> String {
+        let lox = Lox::new(Config::default());
+        let mut session = ReplSession::new();
+        let mut out = Vec::new();
+        for line in lines {
+            if let Err(errors) = lox.eval_line(&mut session, line, &mut out) {
+                let errors: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+                panic!("`{}` failed: {:?}", line, errors);
+            }
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn parse_dump_is_only_rendered_with_the_flag() {
+        let src = String::from("print 1 + 2;");
+        let lox = Lox::new(Config::default());
+        let statements = lox.parse(src.clone()).unwrap();
+        assert_eq!(lox.parse_dump(&statements), None);
+
+        let lox = Lox::new(Config {
+            debug_parse: true,
+            ..Default::default()
+        });
+        let statements = lox.parse(src).unwrap();
+        assert_eq!(
+            lox.parse_dump(&statements).unwrap(),
+            "Print [1:7..1:11]\n  Binary `+` [1:7..1:11]\n    Literal `1` [1:7..1:7]\n    Literal `2` [1:11..1:11]"
+        );
+    }
+
+    #[test]
+    fn last_reprints_the_previous_result() {
+        assert_eq!(repl(&[":last"]), "nil\n");
+        assert_eq!(
+            repl(&["1 + 2;", ":last", "let x = 1;", ":last"]),
+            "3\n3\n3\n"
+        );
+        assert_eq!(repl(&["1 + 2;", "\"a\";", ":last\n"]), "3\na\na\n");
+    }
+
+    #[test]
+    fn bare_expressions_echo_their_value() {
+        assert_eq!(
+            repl(&["1 + 2", "\"a\" + \"b\";\n", "[1, 2]"]),
+            "3\nab\n[1, 2]\n"
+        );
+        // Statements and nil results print nothing
+        assert_eq!(
+            repl(&["let x = 1;", "fn f() {}", "f()", "nil;", "x"]),
+            "1\n"
+        );
+        assert_eq!(repl(&["nil", ":last"]), "nil\n");
+
+        let lox = Lox::new(Config::default());
+        let mut session = ReplSession::new();
+        let mut out = Vec::new();
+        let errors = lox
+            .eval_line(&mut session, "let x = 1", &mut out)
+            .unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "expected `;` after variable declaration"
+        );
+    }
+
+    #[test]
+    fn unclosed_delimiters_keep_the_input_open() {
+        let lox = Lox::new(Config::default());
+        assert!(lox.is_incomplete("fn f() {"));
+        assert!(lox.is_incomplete("fn f() {\n  return [1,\n"));
+        assert!(lox.is_incomplete("print (1 +"));
+        assert!(!lox.is_incomplete("fn f() {\n  return 1;\n}\n"));
+        assert!(!lox.is_incomplete("print 1;"));
+        // Delimiters in strings and comments don't count
+        assert!(!lox.is_incomplete(r#"print "{";"#));
+        assert!(!lox.is_incomplete("print 1; // {"));
+        // Stray closers and lexing errors are reported at once
+        assert!(!lox.is_incomplete("}"));
+        assert!(!lox.is_incomplete(r#"fn f() { print "oops"#));
+
+        assert_eq!(
+            repl(&[
+                "class A {\n  get() {\n    return 7;\n  }\n}\n",
+                "A().get();"
+            ]),
+            "7\n"
+        );
+    }
+
+    #[test]
+    fn default_declaration_survives_repl_lines() {
+        assert_eq!(
+            repl(&[
+                "let x ?= 1;",
+                "let x ?= 2;",
+                "x;",
+                "x = nil;",
+                "let x ?= 3;",
+                "x;"
+            ]),
+            "1\n3\n"
+        );
+    }
+
+    #[test]
+    fn completes_keywords_natives_and_globals() {
+        let lox = Lox::new(Config::default());
+        let mut session = ReplSession::new();
+        assert_eq!(session.complete("pri"), vec!["print"]);
+        assert_eq!(session.complete("let x = cl"), vec!["class", "clock"]);
+        assert!(session.complete("let x = ").is_empty());
+
+        let mut out = Vec::new();
+        assert!(lox
+            .eval_line(&mut session, "let counter = 1;", &mut out)
+            .is_ok());
+        assert!(lox
+            .eval_line(&mut session, "fn count() {}", &mut out)
+            .is_ok());
+        assert_eq!(session.complete("print cou"), vec!["count", "counter"]);
+        assert_eq!(session.complete("counter"), Vec::<String>::new());
+
+        assert_eq!(
+            repl(&["let total = 0;", ":complete print to", ":complete xyz"]),
+            "total\n\n"
+        );
+    }
+
+    #[test]
+    fn custom_prefix_operators_call_their_native() {
+        fn sqrt(x: &LoxValue) -> LoxResult<LoxValue> {
+            match *x {
+                LoxValue::Integer(i) => Ok(LoxValue::Decimal((i as f64).sqrt())),
+                LoxValue::Decimal(d) => Ok(LoxValue::Decimal(d.sqrt())),
+                ref oth => Err(LoxError::Generic(format!(
+                    "cannot take the root of {}",
+                    oth
+                ))),
+            }
+        }
+        let lox = Lox::new(Config::default())
+            .prefix_operator("√", sqrt)
+            .unwrap()
+            .prefix_operator("root", sqrt)
+            .unwrap();
+        let mut session = ReplSession::new();
+        lox.grammar.define(&session.env);
+        let mut out = Vec::new();
+        let src = "let x = √16 + 1; print x; print -√√16; print root 9 * 2;";
+        assert!(lox.eval_line(&mut session, src, &mut out).is_ok());
+        assert_eq!(String::from_utf8(out).unwrap(), "5\n-2\n6\n");
+
+        let mut out = Vec::new();
+        assert!(lox.eval_line(&mut session, "√true;", &mut out).is_err());
+        assert!(lox
+            .eval_line(&mut session, "let root = 1;", &mut out)
+            .is_err());
+        assert!(Lox::new(Config::default()).parse("√4;".into()).is_err());
+    }
+
+    #[test]
+    fn rejects_operators_that_clash_with_the_language() {
+        let id = |x: &LoxValue| Ok(x.to_owned());
+        for symbol in &["", "-", "+", "1", "a b", "let", "true", "√√"] {
+            assert!(
+                Lox::new(Config::default())
+                    .prefix_operator(symbol, id)
+                    .is_err(),
+                "{:?}",
+                symbol
+            );
+        }
+        let lox = Lox::new(Config::default())
+            .prefix_operator("√", id)
+            .unwrap();
+        assert!(lox.prefix_operator("√", id).is_err());
+    }
+
+    #[test]
+    fn lox_can_be_shared_across_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Lox>();
+
+        let lox = std::sync::Arc::new(Lox::new(Config::default()));
+        let handles: Vec<_> = (1..=2)
+            .map(|n| {
+                let lox = std::sync::Arc::clone(&lox);
+                std::thread::spawn(move || {
+                    let src = format!(
+                        "let total = 0; for (let i = 0; i < 1000; i = i + 1) {{ total = total + {}; }}",
+                        n
+                    );
+                    let mut session = ReplSession::new();
+                    let mut out = Vec::new();
+                    assert!(lox.eval_line(&mut session, &src, &mut out).is_ok());
+                    assert!(lox.eval_line(&mut session, "total;", &mut out).is_ok());
+                    String::from_utf8(out).unwrap()
+                })
+            })
+            .collect();
+
+        let outputs: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(outputs, vec!["1000\n", "2000\n"]);
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn environments_can_be_shared_across_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Environment>();
+        assert_send_sync::<LoxValue>();
+
+        let lox = std::sync::Arc::new(Lox::new(Config::default()));
+        let mut session = ReplSession::new();
+        let mut out = Vec::new();
+        let src = "let squares = [0, 0, 0];";
+        assert!(lox.eval_line(&mut session, src, &mut out).is_ok());
+
+        let handles: Vec<_> = (1..=2)
+            .map(|n| {
+                let lox = std::sync::Arc::clone(&lox);
+                let mut session = ReplSession {
+                    env: Rc::clone(&session.env),
+                    last: None,
+                };
+                std::thread::spawn(move || {
+                    let src = format!("squares[{}] = {} * {}; let square{} = true;", n, n, n, n);
+                    assert!(lox.eval_line(&mut session, &src, &mut Vec::new()).is_ok());
+                })
+            })
+            .collect();
+        handles.into_iter().for_each(|h| h.join().unwrap());
+
+        let src = "print squares; print square1 and square2;";
+        assert!(lox.eval_line(&mut session, src, &mut out).is_ok());
+        assert_eq!(String::from_utf8(out).unwrap(), "[0, 1, 4]\ntrue\n");
+    }
 
-        let interpreter = Interpreter::new(&statements);
-        let resolver = Resolver::new(&interpreter);
-        resolver.resolve(&statements).map_err(|e| vec![e])?;
-        interpreter.interpret()
+    #[test]
+    fn do_string_returns_the_last_expression_value() {
+        let lox = Lox::new(Config::default());
+        let value = lox.do_string("let x = 2; fn triple(n) { return n * 3; } triple(x);");
+        assert_eq!(*value.unwrap(), LoxValue::Integer(6));
+        let value = lox.do_string("let x = 2; print x;").unwrap();
+        assert_eq!(*value, LoxValue::Nil);
+        // Only the last statement counts
+        assert_eq!(*lox.do_string("1; let y = 2;").unwrap(), LoxValue::Nil);
+        assert_eq!(*lox.do_string("").unwrap(), LoxValue::Nil);
+        assert!(lox.do_string("undefined;").is_err());
     }
 }
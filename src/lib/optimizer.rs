@@ -0,0 +1,772 @@
+//! Rewrites the syntax tree into an equivalent, cheaper to run one.
+//!
+//! Passes run before the [`Resolver`](crate::interpreter::Resolver), since it records
+//! the expressions it resolves as they are.
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    parser::{
+        visitor::{walk_expr_mut, walk_stmt, walk_stmt_mut, Visitor, VisitorMut},
+        Expr, Pattern, Stmt,
+    },
+    token::{Numeric, Token, TokenKind},
+};
+
+/// Runs every pass over `statements`.
+pub(crate) fn optimize(mut statements: Vec<Stmt>) -> Vec<Stmt> {
+    ConstPropagation::default().visit_program_mut(&mut statements);
+    LoopInvariants::default().visit_program_mut(&mut statements);
+    CommonSubexpressions::default().visit_program_mut(&mut statements);
+    statements
+}
+
+/// Removes every `assert` statement, so they cost nothing at runtime.
+pub(crate) fn strip_asserts(mut statements: Vec<Stmt>) -> Vec<Stmt> {
+    statements.retain(|stmt| !matches!(stmt, Stmt::Assert(..)));
+    StripAsserts.visit_program_mut(&mut statements);
+    statements
+}
+
+struct StripAsserts;
+
+impl VisitorMut for StripAsserts {
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        // Nested statements always live in a block
+        if let Stmt::Block(statements) = stmt {
+            statements.retain(|stmt| !matches!(stmt, Stmt::Assert(..)));
+        }
+        walk_stmt_mut(self, stmt)
+    }
+}
+
+/// Replaces the uses of constants initialized with a literal by the literal itself.
+///
+/// ```text
+/// const N = 10;
+/// while i < N { ... }  // becomes `while i < 10 { ... }`
+/// ```
+#[derive(Default)]
+struct ConstPropagation {
+    /// Names declared in each scope, with the value of the ones that can be propagated.
+    /// The first one is the global scope.
+    scopes: Vec<HashMap<String, Option<Token>>>,
+}
+
+impl ConstPropagation {
+    fn declare(&mut self, name: &Token, value: Option<Token>) {
+        if self.scopes.is_empty() {
+            self.scopes.push(HashMap::new());
+        }
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(name.to_string(), value);
+    }
+
+    fn lookup(&self, name: &Token) -> Option<&Token> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name.to_string()))
+            .and_then(Option::as_ref)
+    }
+
+    fn scoped(&mut self, names: &[Token], body: &mut Stmt) {
+        self.scopes.push(HashMap::new());
+        names.iter().for_each(|name| self.declare(name, None));
+        self.visit_stmt_mut(body);
+        self.scopes.pop();
+    }
+}
+
+impl VisitorMut for ConstPropagation {
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        match stmt {
+            Stmt::Const(name, initializer) => {
+                self.visit_expr_mut(initializer);
+                let value = match initializer {
+                    Expr::Literal(tk) => Some(tk.to_owned()),
+                    _ => None,
+                };
+                self.declare(name, value);
+            }
+            Stmt::Variable(names, initializers, _) => {
+                for initializer in initializers.iter_mut().flatten() {
+                    self.visit_expr_mut(initializer);
+                }
+                names.iter().for_each(|name| self.declare(name, None));
+            }
+            Stmt::VariableDefault(name, initializer, _) | Stmt::Static(name, initializer, _) => {
+                self.visit_expr_mut(initializer);
+                self.declare(name, None);
+            }
+            Stmt::Destructure(pattern, initializer) => {
+                self.visit_expr_mut(initializer);
+                pattern
+                    .names()
+                    .into_iter()
+                    .for_each(|name| self.declare(name, None));
+            }
+            Stmt::Enum(name, _) => self.declare(name, None),
+            Stmt::Function(name, params, body, _) => {
+                self.declare(name, None);
+                self.scoped(params, body);
+            }
+            Stmt::Class(name, superclass, methods, static_methods) => {
+                self.declare(name, None);
+                if let Some(superclass) = superclass {
+                    self.visit_expr_mut(superclass);
+                }
+                for method in methods.iter_mut().chain(static_methods) {
+                    if let Stmt::Function(_, params, body, _) = method {
+                        self.scoped(params, body);
+                    }
+                }
+            }
+            Stmt::Block(statements) => {
+                self.scopes.push(HashMap::new());
+                self.visit_program_mut(statements);
+                self.scopes.pop();
+            }
+            Stmt::With(_, resource, name, body) => {
+                self.visit_expr_mut(resource);
+                self.scoped(&[name.to_owned()], body);
+            }
+            Stmt::ForEach(name, iterable, body) => {
+                self.visit_expr_mut(iterable);
+                self.scoped(&[name.to_owned()], body);
+            }
+            _ => walk_stmt_mut(self, stmt),
+        }
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        if let Expr::Variable(name) = expr {
+            if let Some(value) = self.lookup(name) {
+                *expr = Expr::Literal(Token::new(value.kind().to_owned(), *name.span()));
+            }
+            return;
+        }
+        walk_expr_mut(self, expr)
+    }
+}
+
+/// Evaluates the parts of a loop condition that can't change between iterations once, before
+/// the loop.
+///
+/// ```text
+/// while i < n * 2 { i = i + 1; }
+/// // becomes
+/// { let <invariant 0> = n * 2; while i < <invariant 0> { i = i + 1; } }
+/// ```
+///
+/// Only operators over literals and primitive variables the loop never writes to are hoisted,
+/// and only from the parts of the condition that are always evaluated, since the condition runs
+/// at least once anyway. A variable is primitive if it was declared earlier in the same block
+/// with a value built from literals and primitive variables, and nothing since could have
+/// changed it. Any other value might be an instance, whose overloaded operators can read
+/// anything, fields included. Any call in the loop could write to any variable, so those loops
+/// are left alone.
+#[derive(Default)]
+struct LoopInvariants {
+    /// Amount of values hoisted so far, used to name them.
+    count: usize,
+}
+
+impl LoopInvariants {
+    /// Whether `expr` is made of literals and the variables in `primitives`, so it can't
+    /// evaluate to an instance.
+    fn is_primitive(expr: &Expr, primitives: &HashSet<String>) -> bool {
+        match expr {
+            Expr::Literal(_) => true,
+            Expr::Variable(name) => primitives.contains(&name.to_string()),
+            Expr::Unary(_, operand) | Expr::Concat(operand) => {
+                Self::is_primitive(operand, primitives)
+            }
+            Expr::Binary(lhs, _, rhs) => {
+                Self::is_primitive(lhs, primitives) && Self::is_primitive(rhs, primitives)
+            }
+            _ => false,
+        }
+    }
+
+    /// Hoists from the loops among `statements`, a block or the whole program.
+    fn hoist_block(&mut self, statements: &mut [Stmt]) {
+        let mut primitives = HashSet::new();
+        for stmt in statements {
+            let mut writes = Writes::default();
+            writes.visit_stmt(stmt);
+            if matches!(stmt, Stmt::While(..)) && !writes.calls {
+                let unchanged = primitives
+                    .iter()
+                    .filter(|name| !writes.names.contains(*name))
+                    .cloned()
+                    .collect();
+                self.hoist_loop(stmt, &unchanged);
+            }
+
+            if writes.calls {
+                primitives.clear();
+            } else {
+                primitives.retain(|name| !writes.names.contains(name));
+            }
+            match stmt {
+                Stmt::Variable(names, initializers, _) => {
+                    for (name, initializer) in names.iter().zip(initializers) {
+                        if let Some(initializer) = initializer {
+                            if Self::is_primitive(initializer, &primitives) {
+                                primitives.insert(name.to_string());
+                            }
+                        }
+                    }
+                }
+                Stmt::Const(name, initializer) if Self::is_primitive(initializer, &primitives) => {
+                    primitives.insert(name.to_string());
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Hoists the invariant parts of the condition of `stmt`, a `while` loop, reading nothing
+    /// but literals and the variables in `primitives`.
+    fn hoist_loop(&mut self, stmt: &mut Stmt, primitives: &HashSet<String>) {
+        let condition = match stmt {
+            Stmt::While(condition, ..) => condition,
+            _ => return,
+        };
+        let mut hoisted = Vec::new();
+        self.hoist(condition, primitives, &mut hoisted);
+        if hoisted.is_empty() {
+            return;
+        }
+
+        let (names, values): (Vec<_>, Vec<_>) = hoisted.into_iter().unzip();
+        let types = vec![None; names.len()];
+        let values = values.into_iter().map(Some).collect();
+        let decl = Stmt::Variable(names, values, types);
+        let cycle = std::mem::replace(stmt, Stmt::Block(vec![]));
+        *stmt = Stmt::Block(vec![decl, cycle]);
+    }
+
+    /// Replaces the invariant subexpressions of `expr`, returning them along with their names.
+    fn hoist(
+        &mut self,
+        expr: &mut Expr,
+        primitives: &HashSet<String>,
+        hoisted: &mut Vec<(Token, Expr)>,
+    ) {
+        match expr {
+            Expr::Binary(..) | Expr::Unary(..) | Expr::Concat(_)
+                if Self::is_primitive(expr, primitives) =>
+            {
+                let name = Token::new(
+                    TokenKind::identifier(format!("<invariant {}>", self.count)),
+                    expr.position(),
+                );
+                self.count += 1;
+                let value = std::mem::replace(expr, Expr::Variable(name.to_owned()));
+                hoisted.push((name, value));
+            }
+            Expr::Binary(lhs, _, rhs) => {
+                self.hoist(lhs, primitives, hoisted);
+                self.hoist(rhs, primitives, hoisted);
+            }
+            // The right hand side of a logical expression may never run
+            Expr::Unary(_, operand) | Expr::Logical(operand, ..) | Expr::Concat(operand) => {
+                self.hoist(operand, primitives, hoisted)
+            }
+            _ => (),
+        }
+    }
+}
+
+impl VisitorMut for LoopInvariants {
+    fn visit_program_mut(&mut self, statements: &mut [Stmt]) {
+        statements
+            .iter_mut()
+            .for_each(|stmt| self.visit_stmt_mut(stmt));
+        self.hoist_block(statements);
+    }
+
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt);
+        if let Stmt::Block(statements) = stmt {
+            self.hoist_block(statements);
+        }
+    }
+}
+
+/// Names a statement may write to, or declare, and whether it calls anything.
+#[derive(Default)]
+struct Writes {
+    names: HashSet<String>,
+    calls: bool,
+}
+
+impl Writes {
+    /// Names of the variables read by `expr`.
+    fn reads(expr: &Expr) -> Vec<String> {
+        match expr {
+            Expr::Variable(name) => vec![name.to_string()],
+            Expr::Get(object, _) | Expr::Unary(_, object) | Expr::Concat(object) => {
+                Self::reads(object)
+            }
+            Expr::Binary(lhs, _, rhs) | Expr::Logical(lhs, _, rhs) => {
+                let mut names = Self::reads(lhs);
+                names.extend(Self::reads(rhs));
+                names
+            }
+            _ => vec![],
+        }
+    }
+
+    fn write(&mut self, name: &Token) {
+        self.names.insert(name.to_string());
+    }
+}
+
+impl Visitor for Writes {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Variable(names, ..) => names.iter().for_each(|name| self.write(name)),
+            Stmt::VariableDefault(name, ..)
+            | Stmt::Const(name, _)
+            | Stmt::Static(name, ..)
+            | Stmt::ForEach(name, ..)
+            | Stmt::Function(name, ..)
+            | Stmt::Class(name, ..)
+            | Stmt::Enum(name, _) => self.write(name),
+            Stmt::Destructure(pattern, _) => {
+                Pattern::names(pattern)
+                    .into_iter()
+                    .for_each(|name| self.write(name));
+            }
+            // Closing the resource is a call
+            Stmt::With(..) => self.calls = true,
+            _ => (),
+        }
+        walk_stmt(self, stmt)
+    }
+
+    fn visit_assign(&mut self, name: &Token, value: &Expr) {
+        self.write(name);
+        self.visit_expr(value);
+    }
+
+    fn visit_index_assign(&mut self, array: &Expr, idx: &Expr, value: &Expr) {
+        if let Expr::Variable(name) = array {
+            self.write(name);
+        }
+        self.visit_expr(array);
+        self.visit_expr(idx);
+        self.visit_expr(value);
+    }
+
+    fn visit_compound(&mut self, target: &Expr, _op: &Token, value: &Expr) {
+        if let Expr::Index(array, _) = target {
+            if let Expr::Variable(name) = &**array {
+                self.write(name);
+            }
+        }
+        self.visit_expr(target);
+        self.visit_expr(value);
+    }
+
+    fn visit_call(&mut self, callee: &Expr, _paren: &Token, args: &[Expr]) {
+        self.calls = true;
+        self.visit_expr(callee);
+        args.iter().for_each(|arg| self.visit_expr(arg));
+    }
+}
+
+/// Evaluates the subexpressions repeated across the statements of a block once, into a
+/// hidden variable.
+///
+/// ```text
+/// { print a.b.c; print a.b.c.d; }
+/// // becomes
+/// { let <cse 0> = a.b.c; print <cse 0>; print <cse 0>.d; }
+/// ```
+///
+/// Only runs of statements that can't change any variable or field are considered, so
+/// anything with a call or an assignment ends the run, and so does any operator over a value
+/// that might be an instance, since it might call an overload. Subexpressions are made of
+/// variables, fields and operators, the ones on the right of `and`/`or` being left out, since
+/// they might never be evaluated. They are compared by their structure and the kinds of their
+/// tokens, leaving positions out.
+#[derive(Default)]
+struct CommonSubexpressions {
+    /// Amount of values extracted so far, used to name them.
+    count: usize,
+}
+
+impl CommonSubexpressions {
+    fn is_pure(expr: &Expr) -> bool {
+        match expr {
+            Expr::Literal(_) | Expr::Variable(_) | Expr::This(_) => true,
+            Expr::Get(object, _) => Self::is_pure(object),
+            Expr::Unary(_, operand) => Self::is_pure(operand) && !Self::may_be_instance(operand),
+            Expr::Concat(chain) => Self::is_pure(chain),
+            Expr::Binary(lhs, _, rhs) => {
+                Self::is_pure(lhs)
+                    && Self::is_pure(rhs)
+                    && !Self::may_be_instance(lhs)
+                    && !Self::may_be_instance(rhs)
+            }
+            Expr::Logical(lhs, _, rhs) => Self::is_pure(lhs) && Self::is_pure(rhs),
+            _ => false,
+        }
+    }
+
+    /// Whether `expr`, a pure expression, might evaluate to an instance. Operators in pure
+    /// expressions never call an overload, so they give numbers, strings or booleans.
+    fn may_be_instance(expr: &Expr) -> bool {
+        match expr {
+            Expr::Literal(_) | Expr::Unary(..) | Expr::Binary(..) | Expr::Concat(_) => false,
+            Expr::Logical(lhs, _, rhs) => Self::may_be_instance(lhs) || Self::may_be_instance(rhs),
+            _ => true,
+        }
+    }
+
+    /// Whether `a` and `b`, pure expressions, are the same but for their positions.
+    fn same(a: &Expr, b: &Expr) -> bool {
+        let same_kind = |a: &Token, b: &Token| a.kind() == b.kind();
+        match (a, b) {
+            // Numbers compare by value, but `1` and `1.0` don't divide the same
+            (Expr::Literal(a), Expr::Literal(b)) => match (a.kind(), b.kind()) {
+                (
+                    TokenKind::NumericLiteral(Numeric::Integer(a)),
+                    TokenKind::NumericLiteral(Numeric::Integer(b)),
+                ) => a == b,
+                (
+                    TokenKind::NumericLiteral(Numeric::Decimal(a)),
+                    TokenKind::NumericLiteral(Numeric::Decimal(b)),
+                ) => a.to_bits() == b.to_bits(),
+                (TokenKind::NumericLiteral(_), TokenKind::NumericLiteral(_)) => false,
+                (a, b) => a == b,
+            },
+            (Expr::Variable(a), Expr::Variable(b)) => same_kind(a, b),
+            (Expr::This(_), Expr::This(_)) => true,
+            (Expr::Get(a, x), Expr::Get(b, y)) => same_kind(x, y) && Self::same(a, b),
+            (Expr::Unary(x, a), Expr::Unary(y, b)) => same_kind(x, y) && Self::same(a, b),
+            (Expr::Concat(a), Expr::Concat(b)) => Self::same(a, b),
+            (Expr::Binary(a, x, c), Expr::Binary(b, y, d))
+            | (Expr::Logical(a, x, c), Expr::Logical(b, y, d)) => {
+                same_kind(x, y) && Self::same(a, b) && Self::same(c, d)
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether `part` is `expr` or one of its subexpressions.
+    fn contains(expr: &Expr, part: &Expr) -> bool {
+        if Self::same(expr, part) {
+            return true;
+        }
+        match expr {
+            Expr::Get(object, _) => Self::contains(object, part),
+            Expr::Unary(_, operand) | Expr::Concat(operand) => Self::contains(operand, part),
+            Expr::Binary(lhs, _, rhs) | Expr::Logical(lhs, _, rhs) => {
+                Self::contains(lhs, part) || Self::contains(rhs, part)
+            }
+            _ => false,
+        }
+    }
+
+    /// Amount of nodes in `expr`, a pure expression.
+    fn size(expr: &Expr) -> usize {
+        match expr {
+            Expr::Get(object, _) => 1 + Self::size(object),
+            Expr::Unary(_, operand) | Expr::Concat(operand) => 1 + Self::size(operand),
+            Expr::Binary(lhs, _, rhs) | Expr::Logical(lhs, _, rhs) => {
+                1 + Self::size(lhs) + Self::size(rhs)
+            }
+            _ => 1,
+        }
+    }
+
+    /// The expressions of `stmt`, if it can't change any variable or field.
+    fn pure_exprs(stmt: &Stmt) -> Option<Vec<&Expr>> {
+        let exprs = match stmt {
+            Stmt::Expression(expr) | Stmt::Print(expr) => vec![expr],
+            Stmt::Variable(_, initializers, _) => initializers.iter().flatten().collect(),
+            _ => return None,
+        };
+        exprs
+            .into_iter()
+            .map(|e| Some(e).filter(|e| Self::is_pure(e)))
+            .collect()
+    }
+
+    /// Collects the subexpressions of `expr` that are always evaluated.
+    fn occurrences(expr: &Expr, found: &mut Vec<Expr>) {
+        match expr {
+            Expr::Get(object, _) => Self::occurrences(object, found),
+            Expr::Unary(_, operand) | Expr::Logical(operand, ..) => {
+                Self::occurrences(operand, found)
+            }
+            Expr::Binary(lhs, _, rhs) => {
+                Self::occurrences(lhs, found);
+                Self::occurrences(rhs, found);
+            }
+            // The chain itself stands for the concatenation
+            Expr::Concat(chain) => return Self::occurrences(chain, found),
+            _ => return,
+        }
+        if !matches!(expr, Expr::Logical(..)) {
+            found.push(expr.to_owned());
+        }
+    }
+
+    fn replace(expr: &mut Expr, repeated: &Expr, name: &Token) {
+        if Self::same(expr, repeated) {
+            *expr = Expr::Variable(name.to_owned());
+            return;
+        }
+        walk_expr_mut(&mut Replace(repeated, name), expr)
+    }
+
+    /// Extracts the repeated subexpressions of `statements[start..end]`, a run of statements
+    /// that change nothing.
+    fn extract(&mut self, statements: &mut Vec<Stmt>, start: usize, end: usize) {
+        let mut declared = HashSet::new();
+        let mut occurrences: Vec<(Expr, usize)> = Vec::new();
+        for (idx, stmt) in statements.iter().enumerate().take(end).skip(start) {
+            if let Stmt::Variable(names, ..) = stmt {
+                declared.extend(names.iter().map(Token::to_string));
+            }
+            let mut found = Vec::new();
+            for expr in Self::pure_exprs(stmt).unwrap_or_default() {
+                Self::occurrences(expr, &mut found);
+            }
+            occurrences.extend(found.into_iter().map(|expr| (expr, idx)));
+        }
+
+        // Bigger expressions first, so that `a.b.c` is extracted rather than `a.b`
+        let mut repeated: Vec<(Expr, usize)> = Vec::new();
+        occurrences.sort_by_key(|(expr, _)| std::cmp::Reverse(Self::size(expr)));
+        for (expr, idx) in &occurrences {
+            let count = occurrences
+                .iter()
+                .filter(|(e, _)| Self::same(e, expr))
+                .count();
+            let reads_declared = Writes::reads(expr).iter().any(|n| declared.contains(n));
+            let is_part = repeated.iter().any(|(e, _)| Self::contains(e, expr));
+            if count < 2 || reads_declared || is_part {
+                continue;
+            }
+            // Occurrences are sorted by statement within the same size
+            repeated.push((expr.to_owned(), *idx));
+        }
+
+        let mut decls = Vec::new();
+        for (expr, first) in repeated {
+            let name = Token::new(
+                TokenKind::identifier(format!("<cse {}>", self.count)),
+                expr.position(),
+            );
+            self.count += 1;
+            for stmt in &mut statements[first..end] {
+                match stmt {
+                    Stmt::Expression(e) | Stmt::Print(e) => Self::replace(e, &expr, &name),
+                    Stmt::Variable(_, initializers, _) => initializers
+                        .iter_mut()
+                        .flatten()
+                        .for_each(|e| Self::replace(e, &expr, &name)),
+                    _ => (),
+                }
+            }
+            decls.push((
+                first,
+                Stmt::Variable(vec![name], vec![Some(expr)], vec![None]),
+            ));
+        }
+        // Later positions first, so earlier ones stay valid
+        decls.sort_by_key(|(first, _)| std::cmp::Reverse(*first));
+        for (first, decl) in decls {
+            statements.insert(first, decl);
+        }
+    }
+}
+
+/// Replaces every subexpression that is the same as `.0` with the variable `.1`.
+struct Replace<'a>(&'a Expr, &'a Token);
+
+impl VisitorMut for Replace<'_> {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        CommonSubexpressions::replace(expr, self.0, self.1)
+    }
+}
+
+impl VisitorMut for CommonSubexpressions {
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt);
+        let statements = match stmt {
+            Stmt::Block(statements) => statements,
+            _ => return,
+        };
+
+        // Runs of statements that change nothing, last first so extracting from one doesn't
+        // move the others
+        let mut runs = Vec::new();
+        let mut start = 0;
+        for (idx, stmt) in statements.iter().enumerate() {
+            if Self::pure_exprs(stmt).is_none() {
+                runs.push((start, idx));
+                start = idx + 1;
+            }
+        }
+        runs.push((start, statements.len()));
+        for (start, end) in runs.into_iter().rev() {
+            if end - start > 1 {
+                self.extract(statements, start, end);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{lexer::Lexer, parser::Parser};
+
+    fn optimized(src: &str) -> String {
+        let tokens = Lexer::new(src).scan_tokens().unwrap();
+        let statements = optimize(Parser::new(&tokens).parse().unwrap());
+        statements
+            .iter()
+            .map(|stmt| format!("{:?}", stmt))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn propagates_literal_constants() {
+        let src = "const N = 10; let i = 0; while i < N { i = i + 1; }";
+        assert!(optimized(src).contains("While(Binary `<`(Variable `i`, Literal `10`)"));
+
+        let src = "const N = 10; fn f() { return N; }";
+        assert!(optimized(src).contains("Return(Literal `10`)"));
+    }
+
+    #[test]
+    fn strips_every_assert() {
+        let src = "assert false; fn f() { if true { assert 1 == 2; } return 1; }";
+        let tokens = Lexer::new(src).scan_tokens().unwrap();
+        let statements = strip_asserts(Parser::new(&tokens).parse().unwrap());
+        assert_eq!(statements.len(), 1);
+        let out = format!("{:?}", statements[0]);
+        assert!(!out.contains("Assert"), "{}", out);
+        assert!(out.contains("Return"), "{}", out);
+    }
+
+    #[test]
+    fn leaves_variables_and_shadowed_constants_alone() {
+        let src = "let N = 10; let i = 0; while i < N { i = i + 1; }";
+        assert!(optimized(src).contains("While(Binary `<`(Variable `i`, Variable `N`)"));
+
+        let src = "const N = 10; fn f(N) { return N; } { let N = 1; print N; }";
+        let out = optimized(src);
+        assert!(out.contains("Return(Variable `N`)"), "{}", out);
+        assert!(out.contains("Print(Variable `N`)"), "{}", out);
+
+        let src = "const N = clock(); print N;";
+        assert!(optimized(src).contains("Print(Variable `N`)"));
+    }
+
+    #[test]
+    fn hoists_loop_invariants() {
+        let src = "let i = 0; let n = 5; while -n < i and i < n * 2 - 1 { i = i + 1; }";
+        let out = optimized(src);
+        assert!(
+            out.contains(
+                "Block(Variable `<invariant 0>`(Unary `-`(Variable `n`)), \
+                 While(Logical `and`(Binary `<`(Variable `<invariant 0>`, Variable `i`), \
+                 Binary `<`(Variable `i`, Binary `-`"
+            ),
+            "{}",
+            out
+        );
+    }
+
+    #[test]
+    fn keeps_loop_dependent_expressions() {
+        // `i + 1` depends on a variable written in the loop
+        let src = "let i = 0; let n = 5; while i + 1 < n { i = i + 1; }";
+        assert!(optimized(src)
+            .starts_with("Variable `i`(Literal `0`)\nVariable `n`(Literal `5`)\nWhile"));
+
+        // `f` could write to `n`
+        let src = "let i = 0; let n = 5; while i < n * 2 { i = i + 1; f(); }";
+        assert!(!optimized(src).contains("invariant"));
+
+        // The right hand side of `or` may never run
+        let src = "let i = 0; let n = 5; while i < 1 or i < n * 2 { i = i + 1; }";
+        assert!(!optimized(src).contains("invariant"));
+    }
+
+    #[test]
+    fn keeps_operators_that_might_be_overloaded() {
+        for src in &[
+            // `b` might be an instance, whose `__add__` reads its fields
+            "let i = 0; let b = B(); while i < b + 3 { i = i + 1; b.v = b.v + 1; }",
+            "fn f(n) { let i = 0; while i < n * 2 { i = i + 1; } }",
+            // `g` could have changed `n` before the loop
+            "let i = 0; let n = 5; g(); while i < n * 2 { i = i + 1; }",
+            "let i = 0; let n = 5; n = B(); while i < n * 2 { i = i + 1; }",
+            "let n = 5; { let i = 0; while i < n * 2 { i = i + 1; } }",
+        ] {
+            assert!(!optimized(src).contains("invariant"), "{}", optimized(src));
+        }
+
+        let src = "let i = 0; let n = 5; let m = n * 2; while i < m + 1 { i = i + 1; }";
+        assert!(optimized(src).contains("Variable `<invariant 0>`(Binary `+`(Variable `m`"));
+    }
+
+    #[test]
+    fn extracts_repeated_subexpressions() {
+        let src = "fn f(a) { print a.b.c; let x = a.b.c.d; print a.b.c.e or a.b.c; }";
+        let out = optimized(src);
+        assert!(
+            out.contains(
+                "Block(Variable `<cse 0>`(Get `c`(Get `b`(Variable `a`))), \
+                 Print(Variable `<cse 0>`), \
+                 Variable `x`(Get `d`(Variable `<cse 0>`)), \
+                 Print(Logical `or`(Get `e`(Variable `<cse 0>`), Variable `<cse 0>`)))"
+            ),
+            "{}",
+            out
+        );
+
+        // Told apart by the kind of their literals
+        let src = "{ print 1.0 / 2; print 1 / 2; print \"1\" + \"2\"; print 1 + 2; }";
+        assert!(!optimized(src).contains("cse"), "{}", optimized(src));
+        let src = "{ print 1 / 2; print -(1 / 2); }";
+        assert!(optimized(src).contains("Variable `<cse 0>`(Binary `/`"));
+    }
+
+    #[test]
+    fn only_extracts_between_changes() {
+        // Calls could change anything, assignments change their variable
+        for src in &[
+            "fn f(a) { print a.next().c; print a.next().c; }",
+            "fn f(a) { print a.b + 1; a = 2; print a.b + 1; }",
+            "fn f(a) { print a.b; a.b = 2; print a.b; }",
+            "fn f(a) { print a.b; g(); print a.b; }",
+            "fn f(a) { let x = a.b; let a = 1; print a.b; }",
+            // Never evaluated if `a` is `nil`
+            "fn f(a) { print a and a.b; print a and a.b; }",
+            // Overloads could change anything
+            "fn f(a) { print a.b + 1; print a.b + 1; }",
+            "fn f(a) { print -a.b; print a.b; }",
+        ] {
+            assert!(!optimized(src).contains("cse"), "{}", optimized(src));
+        }
+
+        let src = "fn f(a) { print a.b; print a.b; a = 1; print a.c; print a.c; }";
+        let out = optimized(src);
+        assert!(out.contains("Variable `<cse 0>`(Get `c`"), "{}", out);
+        assert!(out.contains("Variable `<cse 1>`(Get `b`"), "{}", out);
+    }
+}
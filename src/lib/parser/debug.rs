@@ -0,0 +1,264 @@
+//! Span-aware `Debug` output for the syntax tree.
+//!
+//! `{:?}` prints a node and its children on a single line, while `{:#?}` prints one node
+//! per line, indented as a tree, along with the source span each node covers:
+//!
+//! ```text
+//! Binary `+` [1:1..1:5]
+//!   Literal `1` [1:1..1:1]
+//!   Literal `2` [1:5..1:5]
+//! ```
+use std::fmt;
+
+use super::{Expr, Pattern, Stmt};
+use crate::{
+    position::Span,
+    token::{Token, TokenKind},
+};
+
+/// A node of the tree printed by the `Debug` impls of [`Expr`] and [`Stmt`].
+struct Node {
+    label: String,
+    span: Option<Span>,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn new(label: impl Into<String>, span: Option<Span>, children: Vec<Node>) -> Self {
+        Self {
+            label: label.into(),
+            span,
+            children,
+        }
+    }
+
+    fn fmt_line(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.label)?;
+        if !self.children.is_empty() {
+            write!(f, "(")?;
+            for (i, child) in self.children.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                child.fmt_line(f)?;
+            }
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+
+    fn fmt_tree(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        write!(f, "{:indent$}{}", "", self.label, indent = depth * 2)?;
+        if let Some(span) = self.span {
+            write!(f, " {}", span)?;
+        }
+        for child in &self.children {
+            writeln!(f)?;
+            child.fmt_tree(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if f.alternate() {
+            self.fmt_tree(f, 0)
+        } else {
+            self.fmt_line(f)
+        }
+    }
+}
+
+/// Joins token names as "`a`, `b`".
+fn names<'a>(tokens: impl IntoIterator<Item = &'a Token>) -> String {
+    tokens
+        .into_iter()
+        .map(|tk| format!("`{}`", tk))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn expr_nodes(exprs: &[Expr]) -> Vec<Node> {
+    exprs.iter().map(Expr::node).collect()
+}
+
+fn stmt_nodes(stmts: &[Stmt]) -> Vec<Node> {
+    stmts.iter().map(Stmt::node).collect()
+}
+
+impl Expr {
+    fn node(&self) -> Node {
+        let (label, children) = match self {
+            Expr::Binary(lhs, op, rhs) => {
+                (format!("Binary `{}`", op), vec![lhs.node(), rhs.node()])
+            }
+            Expr::Unary(op, rhs) => (format!("Unary `{}`", op), vec![rhs.node()]),
+            Expr::Assign(name, value) => (format!("Assign `{}`", name), vec![value.node()]),
+            Expr::Call(callee, _, args) => {
+                let mut children = vec![callee.node()];
+                children.extend(expr_nodes(args));
+                ("Call".into(), children)
+            }
+            Expr::Get(object, name) => (format!("Get `{}`", name), vec![object.node()]),
+            Expr::Set(object, name, value) => {
+                (format!("Set `{}`", name), vec![object.node(), value.node()])
+            }
+            // Quoted so that `"1"` and `1` can be told apart
+            Expr::Literal(tk) => match tk.kind() {
+                TokenKind::StringLiteral(s) => (format!("Literal `\"{}\"`", s), vec![]),
+                _ => (format!("Literal `{}`", tk), vec![]),
+            },
+            Expr::Logical(lhs, op, rhs) => {
+                (format!("Logical `{}`", op), vec![lhs.node(), rhs.node()])
+            }
+            Expr::Conditional(condition, then, otherwise) => (
+                "Conditional".into(),
+                vec![condition.node(), then.node(), otherwise.node()],
+            ),
+            Expr::Super(_, method) => (format!("Super `{}`", method), vec![]),
+            Expr::This(_) => ("This".into(), vec![]),
+            Expr::Variable(name) => (format!("Variable `{}`", name), vec![]),
+            Expr::Array(_, values) => ("Array".into(), expr_nodes(values)),
+            Expr::Index(array, idx) => ("Index".into(), vec![array.node(), idx.node()]),
+            Expr::Range(start, end) => ("Range".into(), vec![start.node(), end.node()]),
+            Expr::IndexAssign(array, idx, value) => (
+                "IndexAssign".into(),
+                vec![array.node(), idx.node(), value.node()],
+            ),
+            Expr::Compound(target, op, value) => (
+                format!("Compound `{}`", op),
+                vec![target.node(), value.node()],
+            ),
+            Expr::Spread(_, array) => ("Spread".into(), vec![array.node()]),
+            Expr::Concat(chain) => ("Concat".into(), vec![chain.node()]),
+            Expr::Loop(_, body) => ("Loop".into(), vec![body.0.node()]),
+        };
+        Node::new(label, Some(self.position()), children)
+    }
+}
+
+impl Stmt {
+    fn node(&self) -> Node {
+        let (label, children) = match self {
+            Stmt::Expression(expr) => ("Expression".into(), vec![expr.node()]),
+            Stmt::Print(expr) => ("Print".into(), vec![expr.node()]),
+            Stmt::Return(_, value) => ("Return".into(), vec![value.node()]),
+            Stmt::If(condition, then_branch, else_branch) => {
+                let mut children = vec![condition.node(), then_branch.node()];
+                children.extend(else_branch.iter().map(|stmt| stmt.node()));
+                ("If".into(), children)
+            }
+            Stmt::Function(name, params, body, _) => (
+                format!("Function `{}`({})", name, names(params)),
+                vec![body.node()],
+            ),
+            Stmt::Class(name, superclass, methods, static_methods) => {
+                let mut children: Vec<_> = superclass.iter().map(|expr| expr.node()).collect();
+                children.extend(stmt_nodes(methods));
+                children.extend(stmt_nodes(static_methods));
+                (format!("Class `{}`", name), children)
+            }
+            Stmt::Variable(vars, initializers, _) => (
+                format!("Variable {}", names(vars)),
+                initializers.iter().flatten().map(Expr::node).collect(),
+            ),
+            Stmt::VariableDefault(name, initializer, _) => (
+                format!("VariableDefault `{}`", name),
+                vec![initializer.node()],
+            ),
+            Stmt::Enum(name, members) => (format!("Enum `{}` {}", name, names(members)), vec![]),
+            Stmt::Const(name, initializer) => {
+                (format!("Const `{}`", name), vec![initializer.node()])
+            }
+            Stmt::Destructure(pattern, initializer) => {
+                let label = match &**pattern {
+                    Pattern::Array(_, vars, rest) => {
+                        let mut label = format!("Destructure [{}", names(vars));
+                        if let Some(rest) = rest {
+                            let sep = if vars.is_empty() { "" } else { ", " };
+                            label += &format!("{}...`{}`", sep, rest);
+                        }
+                        label + "]"
+                    }
+                    Pattern::Object(_, fields) => {
+                        let fields: Vec<_> = fields
+                            .iter()
+                            .map(|(field, name)| format!("`{}`: `{}`", field, name))
+                            .collect();
+                        format!("Destructure {{{}}}", fields.join(", "))
+                    }
+                };
+                (label, vec![initializer.node()])
+            }
+            Stmt::While(condition, body, increment) => {
+                let mut children = vec![condition.node(), body.node()];
+                children.extend(increment.iter().map(|increment| increment.node()));
+                ("While".into(), children)
+            }
+            Stmt::ForEach(name, iterable, body) => (
+                format!("ForEach `{}`", name),
+                vec![iterable.node(), body.node()],
+            ),
+            Stmt::Block(stmts) => ("Block".into(), stmt_nodes(stmts)),
+            Stmt::Labeled(label, body) => (format!("Labeled `{}`", label), vec![body.node()]),
+            Stmt::Assert(_, condition, _) => ("Assert".into(), vec![condition.node()]),
+            Stmt::With(_, resource, name, body) => (
+                format!("With `{}`", name),
+                vec![resource.node(), body.node()],
+            ),
+            Stmt::Defer(_, body) => ("Defer".into(), vec![body.node()]),
+            Stmt::Static(name, initializer, _) => {
+                (format!("Static `{}`", name), vec![initializer.node()])
+            }
+            Stmt::Break(_, label, value) => (
+                match label {
+                    Some(label) => format!("Break `{}`", label),
+                    None => "Break".into(),
+                },
+                value.iter().map(|expr| expr.node()).collect(),
+            ),
+            Stmt::Continue(_) => ("Continue".into(), vec![]),
+        };
+        Node::new(label, self.position(), children)
+    }
+}
+
+impl fmt::Debug for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.node().fmt(f)
+    }
+}
+
+impl fmt::Debug for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.node().fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{lexer::Lexer, parser::Parser};
+
+    #[test]
+    fn alternate_debug_shows_spans() {
+        let src = "print -a + f(1, \"b\");";
+        let tokens = Lexer::new(src).scan_tokens().unwrap();
+        let stmts = Parser::new(&tokens).parse().unwrap();
+
+        let expected = "\
+Print [1:7..1:19]
+  Binary `+` [1:7..1:19]
+    Unary `-` [1:7..1:8]
+      Variable `a` [1:8..1:8]
+    Call [1:12..1:19]
+      Variable `f` [1:12..1:12]
+      Literal `1` [1:14..1:14]
+      Literal `\"b\"` [1:17..1:19]";
+        assert_eq!(format!("{:#?}", stmts[0]), expected);
+        assert_eq!(
+            format!("{:?}", stmts[0]),
+            "Print(Binary `+`(Unary `-`(Variable `a`), Call(Variable `f`, Literal `1`, Literal `\"b\"`)))"
+        );
+    }
+}
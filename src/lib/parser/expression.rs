@@ -1,13 +1,21 @@
-use crate::lib::{
+use crate::{
     error::*,
-    interpreter::{Environment, LoxValue},
+    interpreter::{Environment, Interpreter, Local, LoxCallable, LoxValue},
     position::Span,
+    sync::{Rc, RefCell},
     token::{Keyword, Punctuator, Token, TokenKind},
 };
-use std::{cell::RefCell, collections::HashMap, convert::TryInto, rc::Rc};
+use std::{
+    convert::{TryFrom, TryInto},
+    hash::{Hash, Hasher},
+};
+
+use super::Stmt;
 
-#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+#[derive(Clone, Hash, Eq, PartialEq)]
 /// Language expressions
+///
+/// Like [`Stmt`], large payloads are boxed, keeping expressions at most 64 bytes.
 pub(crate) enum Expr {
     /// Binary expression (Expr, Operator, Expr)
     Binary(Box<Expr>, Token, Box<Expr>),
@@ -16,45 +24,90 @@ pub(crate) enum Expr {
     /// Assign expression (name: Token, value: Expression)
     Assign(Token, Box<Expr>),
     /// Call expression (callee: Expr, Token: paren, args: Vec<Expr>)
-    Call(Box<Expr>, Token, Vec<Expr>),
+    Call(Box<Expr>, Token, Box<[Expr]>),
     /// Class `get` expression (object: Expr, name: Token)
     Get(Box<Expr>, Token),
     /// Class set expression (object: Expr, name: Token, value: Expr)
     Set(Box<Expr>, Token, Box<Expr>),
-    /// Represents the parentheses groups
-    Grouping(Box<Expr>),
     /// Literal values
     Literal(Token),
     /// Logical expression (lhs: Expr, op: Token, rhs: Expr)
     Logical(Box<Expr>, Token, Box<Expr>),
+    /// Conditional expression (condition: Expr, then: Expr, else: Expr)
+    /// ```text
+    /// let x = cond ? 1 : 2;
+    /// ```
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
     /// Super expression (keyword: Token, method: Token)
-    Super(Token, Token),
+    Super(Token, Box<Token>),
     /// Class `this` expression
     This(Token),
     /// Variable expression (name: Token)
     Variable(Token),
     /// Array (start_token: Token, values: Vec<Expr>)
-    Array(Token, Vec<Expr>),
-    /// Index (name: Token, idx: Expr)
-    Index(Token, Box<Expr>),
-    /// ArrayAssing (name: Token, idx: Expr, val: Expr)
-    IndexAssign(Token, Box<Expr>, Box<Expr>),
+    Array(Token, Box<[Expr]>),
+    /// Index (array: Expr, idx: Expr)
+    Index(Box<Expr>, Box<Expr>),
+    /// ArrayAssing (array: Expr, idx: Expr, val: Expr)
+    IndexAssign(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// Compound assignment to a field or element (target: Expr, op: Token, value: Expr), a
+    /// `Get` or `Index` target whose object and index are evaluated once
+    /// ```text
+    /// a[i()] += 1;
+    /// ```
+    Compound(Box<Expr>, Token, Box<Expr>),
+    /// Range (start: Expr, end: Expr), the integers from `start` up to, but not including, `end`
+    /// ```text
+    /// for i in 0..10 { print i; }
+    /// ```
+    Range(Box<Expr>, Box<Expr>),
+    /// Spread (ellipsis: Token, array: Expr), only valid as a call argument or array element
+    Spread(Token, Box<Expr>),
+    /// Chain of string concatenations (chain: Expr), a left-nested `Binary` `+` of literals
+    /// and variables with a string among them, built into a single string at once instead of
+    /// one intermediate string per `+`
+    Concat(Box<Expr>),
+    /// Infinite loop (keyword: Token, body: Stmt), evaluates to the value of the `break`
+    /// exiting it, or nil for a plain `break;`
+    /// ```text
+    /// let x = loop { break 5; };
+    /// ```
+    Loop(Token, LoopBody),
+}
+
+/// Body of an [`Expr::Loop`].
+///
+/// Statements can't be hashed nor compared, so a loop is told apart by its keyword alone,
+/// whose position is enough to identify it.
+#[derive(Clone)]
+pub(crate) struct LoopBody(pub Box<Stmt>);
+
+impl Hash for LoopBody {
+    fn hash<H: Hasher>(&self, _: &mut H) {}
+}
+
+impl PartialEq for LoopBody {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
 }
 
+impl Eq for LoopBody {}
+
 impl Expr {
     pub fn evaluate(
         &self,
         env: Rc<Environment>,
-        locals: &HashMap<Expr, usize>,
+        interpreter: &Interpreter,
     ) -> LoxResult<Rc<LoxValue>> {
         let pos = &self.position();
-        let var_lookup = |name, expr| {
-            if let Some(idx) = locals.get(expr) {
-                return env.get_at(*idx, name);
+        let var_lookup = |name: &Token, expr| {
+            if let Some(local) = interpreter.local(expr) {
+                return env.get_at(local);
             }
-            env.global()
-                .get(name)
-                .map_err(|e| InnerError::new(*pos, &e.to_string()).into())
+            env.global().get_global(name).map_err(|e| {
+                InnerError::new(*pos, &interpreter.lookup_error(&env, e.to_string())).into()
+            })
         };
 
         match self {
@@ -65,9 +118,8 @@ impl Expr {
                 })?;
                 Ok(Rc::new(val))
             }
-            Expr::Grouping(expr) => (*expr).evaluate(env, locals),
             Expr::Unary(op, rhs) => {
-                let rhs = rhs.evaluate(env, locals)?;
+                let rhs = rhs.evaluate(env, interpreter)?;
 
                 use Punctuator::*;
 
@@ -87,38 +139,15 @@ impl Expr {
             }
 
             Expr::Binary(lhs, op, rhs) => {
-                let lhs = lhs.evaluate(Rc::clone(&env), locals)?;
-                let rhs = rhs.evaluate(env, locals)?;
-                use Punctuator::*;
-                let lhs = (*lhs).to_owned();
-                let rhs = (*rhs).to_owned();
-
-                let result = match *op.kind() {
-                    TokenKind::Punctuator(Sub) => lhs - rhs,
-                    TokenKind::Punctuator(Mul) => lhs * rhs,
-                    TokenKind::Punctuator(Div) => lhs / rhs,
-                    TokenKind::Punctuator(Add) => lhs + rhs,
-                    TokenKind::Punctuator(GreaterThan) => lhs.gt(&rhs),
-                    TokenKind::Punctuator(GreaterThanOrEq) => lhs.ge(&rhs),
-                    TokenKind::Punctuator(LessThan) => lhs.lt(&rhs),
-                    TokenKind::Punctuator(LessThanOrEq) => lhs.le(&rhs),
-                    TokenKind::Punctuator(Eq) => Ok(LoxValue::Boolean(lhs == rhs)),
-                    TokenKind::Punctuator(NotEq) => Ok(LoxValue::Boolean(lhs != rhs)),
-                    _ => Err(InnerError::new(
-                        *pos,
-                        "attempt to evaluate an invalid binary expression. this is probably a bug.",
-                    )
-                    .into()),
-                }
-                .map_err(|e: LoxError| {
-                    let err: LoxError = InnerError::new(*pos, &e.to_string()).into();
-                    err
-                })?;
-                Ok(Rc::new(result))
+                let lhs = lhs.evaluate(Rc::clone(&env), interpreter)?;
+                let rhs = rhs.evaluate(Rc::clone(&env), interpreter)?;
+                Self::binary(lhs, op, rhs, env, interpreter, pos)
             }
+            Expr::Concat(chain) => Self::concat(chain, env, interpreter),
+            Expr::Loop(_, body) => Self::run_loop(&body.0, env, interpreter),
 
             Expr::Logical(lhs, op, rhs) => {
-                let lhs = lhs.evaluate(Rc::clone(&env), locals)?;
+                let lhs = lhs.evaluate(Rc::clone(&env), interpreter)?;
 
                 if let TokenKind::Keyword(Keyword::Or) = *op.kind() {
                     if lhs.is_truthy() {
@@ -128,18 +157,32 @@ impl Expr {
                     return Ok(lhs);
                 }
 
-                rhs.evaluate(env, locals)
+                rhs.evaluate(env, interpreter)
             }
-            Expr::Variable(ref name) => var_lookup(&name.to_string(), self),
+            Expr::Conditional(condition, then, otherwise) => {
+                if condition
+                    .evaluate(Rc::clone(&env), interpreter)?
+                    .is_truthy()
+                {
+                    then.evaluate(env, interpreter)
+                } else {
+                    otherwise.evaluate(env, interpreter)
+                }
+            }
+            Expr::Variable(ref name) => var_lookup(name, self),
             Expr::Assign(name, val) => {
-                let val = val.evaluate(Rc::clone(&env), locals)?;
-                if let Some(idx) = locals.get(self) {
-                    env.assign_at(*idx, &name.to_string(), &val)?;
+                let val = val.evaluate(Rc::clone(&env), interpreter)?;
+                if let Some(local) = interpreter.local(self) {
+                    env.assign_at(local, &val)?;
                 } else {
                     env.global()
                         .assign(&name.to_string(), &val)
                         .map_err(|e: LoxError| {
-                            let err: LoxError = InnerError::new(*pos, &e.to_string()).into();
+                            let err: LoxError = InnerError::new(
+                                *pos,
+                                &interpreter.lookup_error(&env, e.to_string()),
+                            )
+                            .into();
                             err
                         })?;
                 }
@@ -147,30 +190,55 @@ impl Expr {
             }
 
             Expr::Call(callee, _, args) => {
-                let callee = callee.evaluate(Rc::clone(&env), locals)?;
-                let args: Vec<_> = args
-                    .iter()
-                    .map(|arg| arg.evaluate(Rc::clone(&env), locals))
-                    .collect::<LoxResult<_>>()?;
+                // As in the reference implementation, the callee and then every argument are
+                // evaluated before checking the arity, so their side effects happen even if the
+                // call fails
+                let callee = callee.evaluate(Rc::clone(&env), interpreter)?;
+                let args = Self::evaluate_elements(args, &env, interpreter)?;
 
                 if let LoxValue::Callable(c) = &*callee {
-                    if c.arity() != args.len() {
+                    if c.variadic() && c.arity() > args.len() {
+                        return Err(InnerError::new(
+                            *pos,
+                            &format!(
+                                "expected at least {} arguments but got {}",
+                                c.arity(),
+                                args.len()
+                            ),
+                        )
+                        .into());
+                    }
+                    if !c.variadic() && c.arity() != args.len() {
                         return Err(InnerError::new(
                             *pos,
-                            &format!("expected {} arguments, got {}", c.arity(), args.len()),
+                            &format!("expected {} arguments but got {}", c.arity(), args.len()),
                         )
                         .into());
                     }
-                    return c.call(env, locals, &args);
+                    // Natives fail without a position, they are reported at the call
+                    return c.call(env, interpreter, &args).map_err(|e| match e {
+                        LoxError::Generic(msg) => InnerError::new(*pos, &msg).into(),
+                        e => e,
+                    });
                 }
                 Err(InnerError::new(*pos, "can only call functions or class constructors").into())
             }
             Expr::Get(object, name) => {
-                let object = object.evaluate(Rc::clone(&env), locals)?;
+                let object = object.evaluate(Rc::clone(&env), interpreter)?;
                 if let LoxValue::Instance(i) = &*object {
                     return i.get(name);
                 }
 
+                if let LoxValue::Enum(e) = &*object {
+                    return e
+                        .get(&name.to_string())
+                        .map(|member| Rc::new(LoxValue::Variant(member)))
+                        .ok_or_else(|| {
+                            let msg = format!("enum `{}` has no member `{}`", e.name, name);
+                            InnerError::new(*name.span(), &msg).into()
+                        });
+                }
+
                 if let Ok(class) = object.as_class() {
                     return class
                         .find_static(&name.to_string())
@@ -184,80 +252,372 @@ impl Expr {
                 .into())
             }
             Expr::Set(object, name, value) => {
-                let object = &object.evaluate(Rc::clone(&env), locals)?;
+                let object = &object.evaluate(Rc::clone(&env), interpreter)?;
                 if let LoxValue::Instance(ref i) = **object {
-                    let value = value.evaluate(Rc::clone(&env), locals)?;
+                    let value = value.evaluate(Rc::clone(&env), interpreter)?;
                     (*i).set(name, &value)?;
                     return Ok(value);
                 }
+                if let LoxValue::Enum(e) = &**object {
+                    let msg = format!(
+                        "cannot assign to `{}.{}`, enums are read-only",
+                        e.name, name
+                    );
+                    return Err(InnerError::new(*pos, &msg).into());
+                }
                 Err(InnerError::new(*pos, "only instances have fields").into())
             }
-            Expr::This(kw) => var_lookup(&kw.to_string(), self),
+            Expr::This(kw) => var_lookup(kw, self),
             Expr::Super(_, method) => {
                 // Safe to unwrap here because we resolved the `super` expression already
-                // so we know it exists
-                let distance = locals.get(self).unwrap();
-                let superclass = env.get_at(*distance, "super")?;
-                let object = env.get_at(distance - 1, "this")?;
+                // so we know it exists. `super` and `this` are alone in their scopes, with
+                // the one of the method's static variables in between.
+                let local = interpreter.local(self).unwrap();
+                let superclass = env.get_at(local)?;
+                let object = env.get_at(Local {
+                    depth: local.depth - 2,
+                    slot: 0,
+                })?;
                 let method = superclass
                     .as_class()
                     .map_err(|e| InnerError::new(*method.span(), &e.to_string()))?
                     .find_method(&method.to_string())
                     .ok_or_else(|| {
-                        InnerError::new(
-                            *pos,
-                            &format!("undefined property `{}`", method.to_string()),
-                        )
+                        InnerError::new(*pos, &format!("undefined property `{}`", method))
                     })?;
                 method
                     .bind(object.as_instance()?)
                     .map(|f| Rc::new(LoxValue::Callable(Rc::new(f))))
             }
             Expr::Array(_, values) => {
-                let values: Vec<_> = values
-                    .iter()
-                    .map(|val| val.evaluate(Rc::clone(&env), locals))
-                    .collect::<LoxResult<_>>()?;
+                let values = Self::evaluate_elements(values, &env, interpreter)?;
                 Ok(Rc::new(LoxValue::Array(RefCell::new(values))))
             }
-            Expr::Index(name, idx) => {
-                let name = var_lookup(&name.to_string(), self)?;
-                let idx = match *idx.evaluate(env, locals)? {
-                    LoxValue::Integer(i) if i >= 0 => i as usize,
-                    _ => return Ok(Rc::new(LoxValue::Nil)),
+            Expr::Spread(..) => Err(InnerError::new(
+                *pos,
+                "spread is only allowed in arguments and array literals",
+            )
+            .into()),
+            Expr::Range(start, end) => {
+                let start = Self::index(start, Rc::clone(&env), interpreter)?;
+                let end = Self::index(end, env, interpreter)?;
+                Ok(Rc::new(LoxValue::Range(start, end)))
+            }
+            Expr::Index(array, idx) => {
+                let array = array.evaluate(Rc::clone(&env), interpreter)?;
+                let vec = match *array {
+                    LoxValue::Array(ref vec) => vec,
+                    ref oth => return Err(Self::unindexable(oth, pos)),
                 };
-                match *name {
-                    LoxValue::Array(ref vec) => match vec.borrow().get(idx) {
-                        Some(val) => Ok(Rc::clone(val)),
-                        None => Ok(Rc::new(LoxValue::Nil)),
-                    },
-                    _ => Err(InnerError::new(*pos, "attempt to index unindexable type").into()),
+                let i = Self::index(idx, env, interpreter)?;
+                let vec = vec.borrow();
+                match usize::try_from(i).ok().and_then(|i| vec.get(i)) {
+                    Some(val) => Ok(Rc::clone(val)),
+                    None => Err(Self::out_of_bounds(i, vec.len(), idx)),
                 }
             }
-            Expr::IndexAssign(name, idx, val) => {
-                let name = var_lookup(&name.to_string(), self)?;
-                let idx = match *idx.evaluate(Rc::clone(&env), locals)? {
-                    LoxValue::Integer(i) if i >= 0 => i as usize,
-                    _ => return Ok(Rc::new(LoxValue::Nil)),
+            Expr::IndexAssign(array, target, val) => {
+                let array = array.evaluate(Rc::clone(&env), interpreter)?;
+                let vec = match *array {
+                    LoxValue::Array(ref vec) => vec,
+                    ref oth => return Err(Self::unindexable(oth, pos)),
                 };
-                let value = val.evaluate(env, locals)?;
-                match *name {
-                    LoxValue::Array(ref vec) => {
-                        if vec.borrow().len() < idx {
-                            vec.borrow_mut().resize(idx + 1, Rc::new(LoxValue::Nil));
-                        }
-                        match vec.borrow_mut().get_mut(idx) {
-                            Some(curr) => {
-                                *curr = Rc::clone(&value);
-                                Ok(value)
-                            }
-                            None => Ok(Rc::new(LoxValue::Nil)),
+                let i = Self::index(target, Rc::clone(&env), interpreter)?;
+                let value = val.evaluate(env, interpreter)?;
+                // Assigning right past the end appends, so arrays can be built one at a time
+                let mut vec = vec.borrow_mut();
+                match usize::try_from(i) {
+                    Ok(idx) if idx == vec.len() => vec.push(Rc::clone(&value)),
+                    Ok(idx) if idx < vec.len() => vec[idx] = Rc::clone(&value),
+                    _ => return Err(Self::out_of_bounds(i, vec.len(), target)),
+                }
+                Ok(value)
+            }
+            Expr::Compound(target, op, value) => match &**target {
+                Expr::Get(object, name) => {
+                    let object = object.evaluate(Rc::clone(&env), interpreter)?;
+                    let instance = match &*object {
+                        LoxValue::Instance(instance) => instance,
+                        LoxValue::Enum(e) => {
+                            let msg = format!(
+                                "cannot assign to `{}.{}`, enums are read-only",
+                                e.name, name
+                            );
+                            return Err(InnerError::new(*pos, &msg).into());
                         }
+                        _ => return Err(InnerError::new(*pos, "only instances have fields").into()),
+                    };
+                    let current = instance.get(name)?;
+                    let value = value.evaluate(Rc::clone(&env), interpreter)?;
+                    let value = Self::binary(current, op, value, env, interpreter, pos)?;
+                    instance.set(name, &value)?;
+                    Ok(value)
+                }
+                Expr::Index(array, idx) => {
+                    let array = array.evaluate(Rc::clone(&env), interpreter)?;
+                    let vec = match *array {
+                        LoxValue::Array(ref vec) => vec,
+                        ref oth => return Err(Self::unindexable(oth, pos)),
+                    };
+                    let i = Self::index(idx, Rc::clone(&env), interpreter)?;
+                    let element = |vec: &Vec<Rc<LoxValue>>| {
+                        usize::try_from(i)
+                            .ok()
+                            .filter(|&i| i < vec.len())
+                            .ok_or_else(|| Self::out_of_bounds(i, vec.len(), idx))
+                    };
+                    let current = {
+                        let vec = vec.borrow();
+                        Rc::clone(&vec[element(&vec)?])
+                    };
+                    let value = value.evaluate(Rc::clone(&env), interpreter)?;
+                    let value = Self::binary(current, op, value, env, interpreter, pos)?;
+                    // The value may have resized the array meanwhile
+                    let mut vec = vec.borrow_mut();
+                    let i = element(&vec)?;
+                    vec[i] = Rc::clone(&value);
+                    Ok(value)
+                }
+                _ => Err(InnerError::new(*pos, "invalid assigment target").into()),
+            },
+        }
+    }
+
+    fn unindexable(value: &LoxValue, pos: &Span) -> LoxError {
+        let msg = format!(
+            "cannot index `{}`, only arrays can be indexed",
+            value.type_name()
+        );
+        InnerError::new(*pos, &msg).into()
+    }
+
+    /// Error for the index `i` computed by `idx`, which is not within an array of `len` elements.
+    fn out_of_bounds(i: isize, len: usize, idx: &Expr) -> LoxError {
+        let msg = format!(
+            "index out of bounds: the length is {} but the index is {}",
+            len, i
+        );
+        InnerError::new(idx.position(), &msg).into()
+    }
+
+    /// Applies the binary operator `op` to evaluated operands, `pos` being the position of the
+    /// whole expression.
+    fn binary(
+        lhs: Rc<LoxValue>,
+        op: &Token,
+        rhs: Rc<LoxValue>,
+        env: Rc<Environment>,
+        interpreter: &Interpreter,
+        pos: &Span,
+    ) -> LoxResult<Rc<LoxValue>> {
+        if let Some(result) = Self::overloaded(&lhs, op, &rhs, env, interpreter, pos) {
+            return result;
+        }
+
+        use Punctuator::*;
+        let lhs = (*lhs).to_owned();
+        let rhs = (*rhs).to_owned();
+
+        let result = match *op.kind() {
+            TokenKind::Punctuator(Sub) => lhs - rhs,
+            TokenKind::Punctuator(Mul) => lhs * rhs,
+            TokenKind::Punctuator(Div) => lhs / rhs,
+            TokenKind::Punctuator(Mod) => lhs % rhs,
+            TokenKind::Punctuator(Pow) => lhs.pow(&rhs),
+            TokenKind::Punctuator(Add) => lhs + rhs,
+            TokenKind::Punctuator(GreaterThan) => lhs.gt(&rhs),
+            TokenKind::Punctuator(GreaterThanOrEq) => lhs.ge(&rhs),
+            TokenKind::Punctuator(LessThan) => lhs.lt(&rhs),
+            TokenKind::Punctuator(LessThanOrEq) => lhs.le(&rhs),
+            TokenKind::Punctuator(Eq) => Ok(LoxValue::Boolean(lhs == rhs)),
+            TokenKind::Punctuator(NotEq) => Ok(LoxValue::Boolean(lhs != rhs)),
+            _ => Err(InnerError::new(
+                *pos,
+                "attempt to evaluate an invalid binary expression. this is probably a bug.",
+            )
+            .into()),
+        }
+        // Point at the operator, the operands may span several lines
+        .map_err(|e: LoxError| {
+            let err: LoxError = InnerError::new(*op.span(), &e.to_string()).into();
+            err
+        })?;
+        Ok(Rc::new(result))
+    }
+
+    /// Evaluates an array index, negative indices give `None`.
+    fn index(idx: &Expr, env: Rc<Environment>, interpreter: &Interpreter) -> LoxResult<isize> {
+        let i = idx
+            .evaluate(env, interpreter)?
+            .to_int()
+            .map_err(|e| InnerError::new(idx.position(), &e.to_string()))?;
+        Ok(i)
+    }
+
+    /// Whether `expr` is a chain worth evaluating as a [`Expr::Concat`]: at least three
+    /// literals or variables added together, one of them a string literal.
+    pub(crate) fn is_concat(expr: &Expr) -> bool {
+        let operands = match Self::concat_operands(expr) {
+            Some(operands) if operands.len() >= 3 => operands,
+            _ => return false,
+        };
+        operands.iter().any(|(_, operand)| {
+            matches!(operand, Expr::Literal(tk) if matches!(tk.kind(), TokenKind::StringLiteral(_)))
+        })
+    }
+
+    /// The operands of a left-nested chain of `+`, each with the operator before it, if they're
+    /// all literals or variables.
+    fn concat_operands(mut expr: &Expr) -> Option<Vec<(Option<&Token>, &Expr)>> {
+        let mut operands = Vec::new();
+        while let Expr::Binary(lhs, op, rhs) = expr {
+            if *op.kind() != Punctuator::Add.into() {
+                break;
+            }
+            operands.push((Some(op), &**rhs));
+            expr = lhs;
+        }
+        operands.push((None, expr));
+        operands.reverse();
+        operands
+            .iter()
+            .all(|(_, operand)| matches!(operand, Expr::Literal(_) | Expr::Variable(_)))
+            .then_some(operands)
+    }
+
+    /// Evaluates a chain of `+` left to right like nested [`Expr::Binary`] would, but once the
+    /// running value is a string, appends every remaining operand to a single buffer.
+    fn concat(
+        chain: &Expr,
+        env: Rc<Environment>,
+        interpreter: &Interpreter,
+    ) -> LoxResult<Rc<LoxValue>> {
+        // Passes in between may have rewritten the chain into something else
+        let operands = match Self::concat_operands(chain) {
+            Some(operands) => operands,
+            None => return chain.evaluate(env, interpreter),
+        };
+        let values = operands
+            .iter()
+            .map(|(_, operand)| operand.evaluate(Rc::clone(&env), interpreter))
+            .collect::<LoxResult<Vec<_>>>()?;
+
+        let start = chain.position().start();
+        let mut acc = Rc::clone(&values[0]);
+        for (i, value) in values.iter().enumerate().skip(1) {
+            if let LoxValue::String(s) = &*acc {
+                let rest = &values[i..];
+                let capacity = rest.iter().fold(s.len(), |len, value| match &**value {
+                    LoxValue::String(s) => len + s.len(),
+                    _ => len + 8,
+                });
+                let mut out = String::with_capacity(capacity);
+                out.push_str(s);
+                for value in rest {
+                    match &**value {
+                        LoxValue::String(s) => out.push_str(s),
+                        oth => out += &oth.to_string(),
                     }
-                    _ => Err(InnerError::new(*pos, "attempt to index unindexable type").into()),
                 }
+                return Ok(Rc::new(LoxValue::String(out.into())));
             }
+            let (op, operand) = operands[i];
+            let pos = Span::new(start, operand.position().end());
+            acc = Self::binary(
+                acc,
+                op.unwrap(),
+                Rc::clone(value),
+                Rc::clone(&env),
+                interpreter,
+                &pos,
+            )?;
         }
+        Ok(acc)
+    }
+
+    /// Calls the method overloading `op` if `lhs` is an instance that defines one, e.g.
+    /// `a + b` calls `a.__add__(b)`. `!=` is the negation of `__eq__`.
+    fn overloaded(
+        lhs: &LoxValue,
+        op: &Token,
+        rhs: &Rc<LoxValue>,
+        env: Rc<Environment>,
+        interpreter: &Interpreter,
+        pos: &Span,
+    ) -> Option<LoxResult<Rc<LoxValue>>> {
+        use Punctuator::*;
+        let instance = match lhs {
+            LoxValue::Instance(instance) => instance,
+            _ => return None,
+        };
+        let name = match *op.kind() {
+            TokenKind::Punctuator(Add) => "__add__",
+            TokenKind::Punctuator(Sub) => "__sub__",
+            TokenKind::Punctuator(Mul) => "__mul__",
+            TokenKind::Punctuator(Div) => "__div__",
+            TokenKind::Punctuator(GreaterThan) => "__gt__",
+            TokenKind::Punctuator(GreaterThanOrEq) => "__ge__",
+            TokenKind::Punctuator(LessThan) => "__lt__",
+            TokenKind::Punctuator(LessThanOrEq) => "__le__",
+            TokenKind::Punctuator(Eq) | TokenKind::Punctuator(NotEq) => "__eq__",
+            _ => return None,
+        };
+
+        let result = instance.method(name)?.and_then(|method| {
+            if method.arity() != 1 {
+                return Err(InnerError::new(
+                    *pos,
+                    &format!("`{}` must take exactly one argument", name),
+                )
+                .into());
+            }
+            method.call(env, interpreter, &[Rc::clone(rhs)])
+        });
+        if *op.kind() == NotEq.into() {
+            return Some(result.map(|eq| Rc::new(LoxValue::Boolean(!eq.is_truthy()))));
+        }
+        Some(result)
+    }
+
+    /// Runs the body of an [`Expr::Loop`] until a `break` without a label exits it, returning
+    /// the value it carries.
+    pub fn run_loop(
+        body: &Stmt,
+        env: Rc<Environment>,
+        interpreter: &Interpreter,
+    ) -> LoxResult<Rc<LoxValue>> {
+        loop {
+            match body.execute(Rc::clone(&env), interpreter) {
+                Ok(()) | Err(LoxError::Continue) => (),
+                Err(LoxError::Break(None, value)) => return Ok(value),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Evaluates arguments or array elements, splicing in the elements of spread arrays.
+    fn evaluate_elements(
+        elements: &[Expr],
+        env: &Rc<Environment>,
+        interpreter: &Interpreter,
+    ) -> LoxResult<Vec<Rc<LoxValue>>> {
+        let mut values = Vec::with_capacity(elements.len());
+        for element in elements {
+            match element {
+                Expr::Spread(_, array) => match &*array.evaluate(Rc::clone(env), interpreter)? {
+                    LoxValue::Array(spread) => values.extend(spread.borrow().iter().cloned()),
+                    oth => {
+                        return Err(InnerError::new(
+                            element.position(),
+                            &format!("cannot spread `{}`, only arrays can be spread", oth),
+                        )
+                        .into())
+                    }
+                },
+                _ => values.push(element.evaluate(Rc::clone(env), interpreter)?),
+            }
+        }
+        Ok(values)
     }
 
     pub fn position(&self) -> Span {
@@ -265,13 +625,15 @@ impl Expr {
             Expr::This(tk) => *tk.span(),
             Expr::Literal(tk) => *tk.span(),
             Expr::Variable(tk) => *tk.span(),
-            Expr::Grouping(expr) => expr.position(),
             Expr::Get(expr, tk) => Span::new(expr.position().start(), tk.span().end()),
             Expr::Unary(op, expr) => Span::new(op.span().start(), expr.position().end()),
             Expr::Super(ltk, rtk) => Span::new(ltk.span().start(), rtk.span().end()),
             Expr::Assign(tk, expr) => Span::new(tk.span().start(), expr.position().end()),
             Expr::Binary(lhs, _, rhs) => Span::new(lhs.position().start(), rhs.position().end()),
             Expr::Logical(lhs, _, rhs) => Span::new(lhs.position().start(), rhs.position().end()),
+            Expr::Conditional(condition, _, otherwise) => {
+                Span::new(condition.position().start(), otherwise.position().end())
+            }
             Expr::Set(lhs, _, rhs) => Span::new(lhs.position().start(), rhs.position().end()),
             Expr::Call(expr, tk, args) => {
                 if let Some(arg) = args.last() {
@@ -285,8 +647,17 @@ impl Expr {
                 }
                 *tk.span()
             }
-            Expr::Index(name, idx) => Span::new(name.span().start(), idx.position().end()),
-            Expr::IndexAssign(name, _, val) => Span::new(name.span().start(), val.position().end()),
+            Expr::Index(array, idx) => Span::new(array.position().start(), idx.position().end()),
+            Expr::Range(start, end) => Span::new(start.position().start(), end.position().end()),
+            Expr::IndexAssign(array, _, val) => {
+                Span::new(array.position().start(), val.position().end())
+            }
+            Expr::Compound(target, _, val) => {
+                Span::new(target.position().start(), val.position().end())
+            }
+            Expr::Spread(tk, expr) => Span::new(tk.span().start(), expr.position().end()),
+            Expr::Concat(chain) => chain.position(),
+            Expr::Loop(kw, _) => *kw.span(),
         }
     }
 
@@ -301,9 +672,9 @@ impl std::fmt::Display for Expr {
             Expr::Binary(lhs, tk, rhs) => write!(f, "({} {} {})", tk, *lhs, *rhs),
             Expr::Unary(tk, rhs) => write!(f, "({} {})", tk, *rhs),
             Expr::Assign(tk, expr) => write!(f, "({} {})", tk, *expr),
-            Expr::Grouping(expr) => write!(f, "(group {})", *expr),
             Expr::Literal(tk) => write!(f, "{}", tk),
             Expr::Variable(tk) => write!(f, "{}", tk),
+            Expr::Concat(chain) => write!(f, "{}", chain),
             _ => unimplemented!(),
         }
     }
@@ -2,16 +2,24 @@
 //!
 //! **Context free grammar**
 //! ```text
-//! program        → declaration* EOF ;
+//! program        → ( declaration | ";" )* EOF ;
 //!
 //! declaration    → classDecl
+//!                | enumDecl
 //!                | funDecl
 //!                | varDecl
+//!                | constDecl
+//!                | staticDecl
 //!                | statement ;
 //! classDecl      → "class" IDENTIFIER ( "<" IDENTIFIER )?
 //!                  "{" function* "}" ;
+//! enumDecl       → "enum" IDENTIFIER "{" ( IDENTIFIER ( "," IDENTIFIER )* ","? )? "}" ;
 //! funDecl        → "fun" function ;
-//! varDecl        → "var" IDENTIFIER ( "=" expression )? ";" ;
+//! varDecl        → "var" IDENTIFIER type? ( "=" expression )? ";"
+//!                | "var" IDENTIFIER type? "?=" expression ";"
+//!                | "var" pattern "=" expression ";" ;
+//! constDecl      → "const" IDENTIFIER "=" expression ";" ;
+//! staticDecl     → "static" "let" IDENTIFIER "=" expression ";" ;
 //!
 //! statement      → exprStmt
 //!                | forStmt
@@ -19,72 +27,112 @@
 //!                | printStmt
 //!                | returnStmt
 //!                | whileStmt
+//!                | repeatStmt
+//!                | breakStmt
+//!                | continueStmt
+//!                | loopStmt
+//!                | labeledStmt
+//!                | withStmt
+//!                | assertStmt
+//!                | deferStmt
+//!                | matchStmt
 //!                | block ;
 //!
 //! exprStmt       → expression ";" ;
 //! forStmt        → "for" "(" ( varDecl | exprStmt | ";" )
 //!                            expression? ";"
-//!                            expression? ")" statement ;
+//!                            expression? ")" statement
+//!                | "for" IDENTIFIER "in" expression block ;
 //! ifStmt         → "if" "(" expression ")" statement
 //!                  ( "else" statement )? ;
 //! printStmt      → "print" expression ";" ;
 //! returnStmt     → "return" expression? ";" ;
 //! whileStmt      → "while" "(" expression ")" statement ;
-//! block          → "{" declaration* "}" ;
+//! repeatStmt     → "repeat" expression block ;
+//! breakStmt      → "break" ( IDENTIFIER | expression )? ";" ; // IDENTIFIER if it's a label
+//! continueStmt   → "continue" ";" ;
+//! loopStmt       → loop ";"? ;
+//! labeledStmt    → IDENTIFIER ":" block ;
+//! withStmt       → "with" expression "as" IDENTIFIER block ;
+//! assertStmt     → "assert" expression ";" ;
+//! deferStmt      → "defer" ( block | exprStmt ) ;
+//! matchStmt      → "match" "type" "(" expression ")" "{" matchArm* "}" ;
+//! matchArm       → ( IDENTIFIER | "nil" | "fn" | "else" ) "=>" block ","? ;
+//! block          → "{" ( declaration | ";" )* "}" ;
 //!
 //! expression     → assignment ;
 //!
-//! assignment     → ( call "." )? IDENTIFIER "=" assignment
-//!                | logic_or ;
+//! assignment     → ( call "." )? IDENTIFIER ( "=" | "+=" | "-=" | "*=" | "/=" | "**=" ) assignment
+//!                | conditional ;
+//!
+//! conditional    → range ( "?" expression ":" conditional )? ;
+//!
+//! range          → pipe ( ".." pipe )? ;
+//!
+//! pipe           → logic_or ( "|>" call )* ;
 //!
 //! logic_or       → logic_and ( "or" logic_and )* ;
 //! logic_and      → equality ( "and" equality )* ;
 //! equality       → comparison ( ( "!=" | "==" ) comparison )* ;
 //! comparison     → term ( ( ">" | ">=" | "<" | "<=" ) term )* ;
 //! term           → factor ( ( "-" | "+" ) factor )* ;
-//! factor         → unary ( ( "/" | "*" ) unary )* ;
+//! factor         → power ( ( "/" | "*" | "%" ) power )* ;
+//! power          → unary ( "**" power )? ;
 //!
-//! unary          → ( "!" | "-" ) unary | pipe ;
-//! pipe           → call ( "|>" call)*;
-//! call           → primary ( "(" arguments? ")" | "." IDENTIFIER )* ;
+//! unary          → ( "!" | "-" | OPERATOR ) unary | call ;
+//! call           → primary ( "(" arguments? ")" | "." IDENTIFIER | "[" expression "]" )* ;
 //! primary        → "true" | "false" | "nil" | "this"
-//!                | NUMBER | STRING | IDENTIFIER | "(" expression ")"
-//!                | "super" "." IDENTIFIER ;
+//!                | "nan" | "inf" | NUMBER | STRING | IDENTIFIER | "(" expression ")"
+//!                | "super" "." IDENTIFIER | loop ;
 //!
 //! Utility rules:
 //!
-//! function       → IDENTIFIER "(" parameters? ")" block ;
-//! parameters     → IDENTIFIER ( "," IDENTIFIER )* ;
-//! arguments      → expression ( "," expression )* ;
+//! loop           → "loop" block ;
+//! function       → IDENTIFIER "(" parameters? ")" type? block ;
+//! parameters     → IDENTIFIER type? ( "," IDENTIFIER type? )* ;
+//! type           → ":" IDENTIFIER ;
+//! pattern        → "[" ( IDENTIFIER ( "," IDENTIFIER )* )? ( ","? "..." IDENTIFIER )? "]"
+//!                | "{" ( field ( "," field )* )? "}" ;
+//! field          → IDENTIFIER ( ":" IDENTIFIER )? ;
+//! arguments      → element ( "," element )* ;
+//! element        → "..."? expression ;
 //!
 //! ```
 //! **Lexical grammar**
 //! ```text
-//! NUMBER         → DIGIT+ ( "." DIGIT+ )? ;
-//! STRING         → "\"" <any char except "\"">* "\"" ;
+//! NUMBER         → DIGIT+ ( "." DIGIT+ )? ( "i" | "f" )? ;
+//! STRING         → "\"" ( <any char except "\"" and "\\"> | ESCAPE )* "\""
+//!                | "r" "#"* "\"" <any char>* "\"" "#"* ; // raw, as many "#" on both ends
+//! ESCAPE         → "\\" ( "n" | "t" | "r" | "\\" | "\"" | "0" ) ;
 //! IDENTIFIER     → ALPHA ( ALPHA | DIGIT )* ;
 //! ALPHA          → "a" ... "z" | "A" ... "Z" | "_" ;
 //! DIGIT          → "0" ... "9" ;
 //! ```
 //!
+mod debug;
 pub(crate) mod expression;
 pub(crate) mod statements;
+pub(crate) mod visitor;
 use super::{
     error::{InnerError, LoxError, LoxResult},
-    token::{Keyword, Punctuator, Token, TokenKind},
+    position::Span,
+    token::{Keyword, Numeric, Punctuator, Token, TokenKind},
 };
-pub(crate) use expression::Expr;
-pub(crate) use statements::Stmt;
+pub(crate) use expression::{Expr, LoopBody};
+pub(crate) use statements::{Pattern, Signature, Stmt};
 use Keyword::*;
 
 pub(crate) struct Parser<'a> {
     inner: InnerIter<'a, Token>,
+    /// Labels of the enclosing labeled blocks, within the innermost function
+    labels: RefCell<Vec<String>>,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(tokens: &'a [Token]) -> Self {
         Self {
             inner: InnerIter::new(tokens),
+            labels: Default::default(),
         }
     }
 
@@ -92,6 +140,9 @@ impl<'a> Parser<'a> {
         let mut statements = Vec::new();
         let mut errors: Vec<LoxError> = Vec::new();
         while self.inner.peek().is_some() {
+            if self.skip_empty_statements() {
+                continue;
+            }
             match self.declaration() {
                 Ok(stmt) => statements.push(stmt),
                 Err(e) => {
@@ -110,15 +161,80 @@ impl<'a> Parser<'a> {
         if self.matches(Keyword::Class) {
             return self.class_decl();
         }
+        if self.matches(Keyword::Enum) {
+            return self.enum_decl();
+        }
         if self.matches(Keyword::Fn) {
             return self.func_decl("function");
         }
         if self.matches(Keyword::Let) {
             return self.var_decl();
         }
+        if self.matches(Keyword::Const) {
+            return self.const_decl();
+        }
+        if self.check(Keyword::Static)
+            && matches!(
+                self.inner.peek_next().map(Token::kind),
+                Some(TokenKind::Keyword(Keyword::Let))
+            )
+        {
+            self.inner.advance();
+            self.inner.advance();
+            return self.static_decl();
+        }
         self.statement()
     }
 
+    fn static_decl(&self) -> LoxResult<Stmt> {
+        let name = self.consume_ident("expected identifier")?.to_owned();
+        self.consume(
+            Punctuator::Assign,
+            "expected `=` after static variable name",
+        )?;
+        let initializer = self.expression()?;
+        self.consume(
+            Punctuator::Semicolon,
+            "expected `;` after static variable declaration",
+        )?;
+        let target = Expr::Variable(name.to_owned());
+        Ok(Stmt::Static(name, initializer.into(), target.into()))
+    }
+
+    fn const_decl(&self) -> LoxResult<Stmt> {
+        let name = self.consume_ident("expected identifier")?.to_owned();
+        self.consume(Punctuator::Assign, "expected `=` after constant name")?;
+        let initializer = self.expression()?;
+        self.consume(
+            Punctuator::Semicolon,
+            "expected `;` after constant declaration",
+        )?;
+        Ok(Stmt::Const(name, initializer))
+    }
+
+    fn enum_decl(&self) -> LoxResult<Stmt> {
+        let name = self.consume_ident("expected name after `enum`")?.to_owned();
+        self.consume(Punctuator::OpenBlock, "expected `{` after enum name")?;
+
+        let mut members: Vec<Token> = Vec::new();
+        while !self.check(Punctuator::CloseBlock) {
+            let member = self.consume_ident("expected enum member")?;
+            if members.iter().any(|m| m.to_string() == member.to_string()) {
+                return Err(InnerError::new(
+                    *member.span(),
+                    &format!("duplicate enum member `{}`", member),
+                )
+                .into());
+            }
+            members.push(member.to_owned());
+            if !self.matches(Punctuator::Comma) {
+                break;
+            }
+        }
+        self.consume(Punctuator::CloseBlock, "expected `}` after enum members")?;
+        Ok(Stmt::Enum(name, members))
+    }
+
     /// Parses a class declaration.
     fn class_decl(&self) -> LoxResult<Stmt> {
         let name = self.consume_ident("expected name after `class`")?;
@@ -152,7 +268,7 @@ impl<'a> Parser<'a> {
 
         Ok(Stmt::Class(
             name.clone(),
-            superclass,
+            superclass.map(Box::new),
             methods,
             static_methods,
         ))
@@ -167,6 +283,7 @@ impl<'a> Parser<'a> {
         )?;
 
         let mut params = Vec::new();
+        let mut sig = Signature::default();
 
         if !self.check(Punctuator::CloseParen) {
             loop {
@@ -179,6 +296,7 @@ impl<'a> Parser<'a> {
                 }
 
                 params.push(self.consume_ident("expected parameter name")?.to_owned());
+                sig.params.push(self.type_annotation()?);
 
                 if !self.matches(Punctuator::Comma) {
                     break;
@@ -186,24 +304,58 @@ impl<'a> Parser<'a> {
             }
         }
         self.consume(Punctuator::CloseParen, "expected `)` after parameters")?;
+        sig.ret = self.type_annotation()?;
         self.consume(
             Punctuator::OpenBlock,
             &format!("expected `{{` before {} body", &kind),
         )?;
 
-        let body = self.block_stmt()?;
-        Ok(Stmt::Function(name.to_owned(), params, body.into()))
+        let body = self.without_labels(|| self.block_stmt())?;
+        Ok(Stmt::Function(
+            name.to_owned(),
+            params,
+            body.into(),
+            sig.into(),
+        ))
     }
 
     fn var_decl(&self) -> LoxResult<Stmt> {
+        if self.matches(Punctuator::OpenBracket) {
+            return self.destructure_decl();
+        }
+        if self.matches(Punctuator::OpenBlock) {
+            return self.object_destructure_decl();
+        }
+
         let mut names = Vec::new();
+        let mut types = Vec::new();
         loop {
             names.push(self.consume_ident("expected identifier")?.to_owned());
+            types.push(self.type_annotation()?);
             if !self.matches(Punctuator::Comma) {
                 break;
             }
         }
 
+        if self.matches(Punctuator::AssignDefault) {
+            let op = self.inner.previous().unwrap();
+            if names.len() > 1 {
+                return Err(
+                    InnerError::new(*op.span(), "`?=` can only declare one variable").into(),
+                );
+            }
+            let initializer = self.expression()?;
+            self.consume(
+                Punctuator::Semicolon,
+                "expected `;` after variable declaration",
+            )?;
+            return Ok(Stmt::VariableDefault(
+                names.remove(0),
+                initializer.into(),
+                types.remove(0),
+            ));
+        }
+
         let mut initializers = Vec::with_capacity(names.len());
         if self.matches(Punctuator::Assign) {
             loop {
@@ -227,7 +379,79 @@ impl<'a> Parser<'a> {
             "expected `;` after variable declaration",
         )?;
 
-        Ok(Stmt::Variable(names, initializers))
+        Ok(Stmt::Variable(names, initializers, types))
+    }
+
+    fn destructure_decl(&self) -> LoxResult<Stmt> {
+        let bracket = self.inner.previous().unwrap().to_owned();
+        let mut names = Vec::new();
+        let mut rest = None;
+        while !self.check(Punctuator::CloseBracket) {
+            if self.matches(Punctuator::Ellipsis) {
+                rest = Some(
+                    self.consume_ident("expected identifier after `...`")?
+                        .to_owned(),
+                );
+                break;
+            }
+            names.push(self.consume_ident("expected identifier")?.to_owned());
+            if !self.matches(Punctuator::Comma) {
+                break;
+            }
+        }
+        self.consume(
+            Punctuator::CloseBracket,
+            "expected `]` after destructuring pattern",
+        )?;
+        self.destructure_initializer(Pattern::Array(bracket, names, rest))
+    }
+
+    fn object_destructure_decl(&self) -> LoxResult<Stmt> {
+        let brace = self.inner.previous().unwrap().to_owned();
+        let mut fields = Vec::new();
+        while !self.check(Punctuator::CloseBlock) {
+            let field = self.consume_ident("expected field name")?.to_owned();
+            let name = if self.matches(Punctuator::Colon) {
+                self.consume_ident("expected identifier after `:`")?
+                    .to_owned()
+            } else {
+                field.clone()
+            };
+            fields.push((field, name));
+            if !self.matches(Punctuator::Comma) {
+                break;
+            }
+        }
+        self.consume(
+            Punctuator::CloseBlock,
+            "expected `}` after destructuring pattern",
+        )?;
+        self.destructure_initializer(Pattern::Object(brace, fields))
+    }
+
+    /// Parses the `= expression;` that follows a destructuring pattern.
+    fn destructure_initializer(&self, pattern: Pattern) -> LoxResult<Stmt> {
+        self.consume(
+            Punctuator::Assign,
+            "expected `=` after destructuring pattern",
+        )?;
+        let initializer = self.expression()?;
+        self.consume(
+            Punctuator::Semicolon,
+            "expected `;` after variable declaration",
+        )?;
+        Ok(Stmt::Destructure(pattern.into(), initializer))
+    }
+
+    /// Parses an optional `: TYPE` annotation.
+    fn type_annotation(&self) -> LoxResult<Option<Token>> {
+        if self.matches(Punctuator::Colon) {
+            return Ok(Some(
+                self.consume_ident("expected type name after `:`")?
+                    .to_owned(),
+            ));
+        }
+        Ok(None)
     }
 
     fn statement(&self) -> LoxResult<Stmt> {
@@ -250,13 +474,145 @@ impl<'a> Parser<'a> {
             return self.while_stmt();
         }
 
+        if self.matches(Keyword::Repeat) {
+            return self.repeat_stmt();
+        }
+
+        if self.matches(Keyword::Match) {
+            return self.match_stmt();
+        }
+
+        if self.matches(Keyword::Break) {
+            return self.break_stmt();
+        }
+
+        if self.matches(Keyword::Continue) {
+            let kw = self.inner.previous().unwrap().to_owned();
+            self.consume(
+                Punctuator::Semicolon,
+                "expected `;` after continue statement",
+            )?;
+            return Ok(Stmt::Continue(kw));
+        }
+
+        // As a statement, the loop needs no `;` after it
+        if self.matches(Keyword::Loop) {
+            let expr = self.loop_expr()?;
+            self.matches(Punctuator::Semicolon);
+            return Ok(Stmt::Expression(expr));
+        }
+
+        if self.matches(Keyword::With) {
+            return self.with_stmt();
+        }
+
+        if self.matches(Keyword::Assert) {
+            return self.assert_stmt();
+        }
+
+        if self.matches(Keyword::Defer) {
+            let kw = self.inner.previous().unwrap().to_owned();
+            // The labels are done by the time deferred statements run
+            let body = self.without_labels(|| {
+                if self.matches(Punctuator::OpenBlock) {
+                    self.block_stmt()
+                } else {
+                    self.expression_stmt()
+                }
+            })?;
+            return Ok(Stmt::Defer(kw, body.into()));
+        }
+
         if self.matches(Punctuator::OpenBlock) {
             return self.block_stmt();
         }
 
+        if self.check_label() {
+            return self.labeled_stmt();
+        }
+
         self.expression_stmt()
     }
 
+    /// A lone identifier after `break` naming an enclosing label is that label, any other
+    /// expression is the value to exit a `loop` with. `break (x);` always exits with the
+    /// value of `x`.
+    fn break_stmt(&self) -> LoxResult<Stmt> {
+        let kw = self.inner.previous().unwrap().to_owned();
+        let is_label = matches!(
+            self.inner.peek(),
+            Some(tk) if self.labels.borrow().contains(&tk.to_string())
+        ) && matches!(
+            self.inner.peek_next().map(Token::kind),
+            Some(TokenKind::Punctuator(Punctuator::Semicolon))
+        );
+        let (label, value) = if is_label {
+            (Some(self.inner.advance().unwrap().to_owned()), None)
+        } else if self.check(Punctuator::Semicolon) {
+            (None, None)
+        } else {
+            (None, Some(self.expression()?))
+        };
+        self.consume(Punctuator::Semicolon, "expected `;` after break statement")?;
+        Ok(Stmt::Break(kw, label, value.map(Box::new)))
+    }
+
+    /// Parses the block of a `loop`, its keyword already consumed.
+    fn loop_expr(&self) -> LoxResult<Expr> {
+        let kw = self.inner.previous().unwrap().to_owned();
+        self.consume(Punctuator::OpenBlock, "expected `{` after `loop`")?;
+        let body = self.block_stmt()?;
+        Ok(Expr::Loop(kw, LoopBody(body.into())))
+    }
+
+    /// Looks ahead for the `IDENTIFIER ":"` that starts a labeled statement.
+    fn check_label(&self) -> bool {
+        matches!(
+            self.inner.peek().map(Token::kind),
+            Some(TokenKind::Identifier(_))
+        ) && matches!(
+            self.inner.peek_next().map(Token::kind),
+            Some(TokenKind::Punctuator(Punctuator::Colon))
+        )
+    }
+
+    fn labeled_stmt(&self) -> LoxResult<Stmt> {
+        let label = self.consume_ident("expected label")?.to_owned();
+        self.consume(Punctuator::Colon, "expected `:` after label")?;
+        self.consume(Punctuator::OpenBlock, "expected `{` after label")?;
+        self.labels.borrow_mut().push(label.to_string());
+        let body = self.block_stmt();
+        self.labels.borrow_mut().pop();
+        Ok(Stmt::Labeled(label, body?.into()))
+    }
+
+    /// Parses with `parse` where the enclosing labels can't be broken out of.
+    fn without_labels<T>(&self, parse: impl FnOnce() -> LoxResult<T>) -> LoxResult<T> {
+        let enclosing = self.labels.take();
+        let res = parse();
+        *self.labels.borrow_mut() = enclosing;
+        res
+    }
+
+    fn with_stmt(&self) -> LoxResult<Stmt> {
+        let kw = self.inner.previous().unwrap().to_owned();
+        let resource = self.expression()?;
+        self.consume(Keyword::As, "expected `as` after resource")?;
+        let name = self.consume_ident("expected resource name")?.to_owned();
+        self.consume(Punctuator::OpenBlock, "expected `{` after resource name")?;
+        let body = self.block_stmt()?;
+        Ok(Stmt::With(kw, resource.into(), name, body.into()))
+    }
+
+    fn assert_stmt(&self) -> LoxResult<Stmt> {
+        let kw = self.inner.previous().unwrap().to_owned();
+        let start = self.inner.index();
+        let condition = self.expression()?;
+        let source = source_text(self.inner.since(start));
+        self.consume(Punctuator::Semicolon, "expected `;` after assertion")?;
+        Ok(Stmt::Assert(kw, condition.into(), source))
+    }
+
     fn return_stmt(&self) -> LoxResult<Stmt> {
         let kw = self.inner.previous().unwrap().to_owned();
 
@@ -275,6 +631,16 @@ impl<'a> Parser<'a> {
     }
 
     fn for_stmt(&self) -> LoxResult<Stmt> {
+        let is_for_each = matches!(
+            self.inner.peek().map(Token::kind),
+            Some(TokenKind::Identifier(_))
+        ) && matches!(
+            self.inner.peek_next().map(Token::kind),
+            Some(TokenKind::Keyword(Keyword::In))
+        );
+        if is_for_each {
+            return self.for_each_stmt();
+        }
         self.consume(Punctuator::OpenParen, "expected `(` after `for` keyword")?;
 
         let initializer = if self.matches(Punctuator::Semicolon) {
@@ -304,14 +670,8 @@ impl<'a> Parser<'a> {
 
         self.consume(Punctuator::CloseParen, "expected `)` after for clauses")?;
 
-        let mut body = self.statement()?;
-
-        // Append the increment expression, if there's one, at the end of the loop
-        if let Some(expr) = increment {
-            body = Stmt::Block(vec![body, Stmt::Expression(expr)]);
-        }
-
-        body = Stmt::While(condition, body.into());
+        let body = self.statement()?;
+        let mut body = Stmt::While(condition, body.into(), increment.map(Box::new));
 
         // Place the initialization before the loop body
         if let Some(stmt) = initializer {
@@ -321,13 +681,146 @@ impl<'a> Parser<'a> {
         Ok(body)
     }
 
+    fn for_each_stmt(&self) -> LoxResult<Stmt> {
+        let name = self.consume_ident("expected loop variable")?.to_owned();
+        self.consume(Keyword::In, "expected `in` after loop variable")?;
+        let iterable = self.expression()?;
+        self.consume(Punctuator::OpenBlock, "expected `{` after iterated value")?;
+        let body = self.block_stmt()?;
+        Ok(Stmt::ForEach(name, iterable, body.into()))
+    }
+
     fn while_stmt(&self) -> LoxResult<Stmt> {
         let condition = self.expression()?;
         self.consume(Punctuator::OpenBlock, "expected `{` after condition")?;
 
         let body = self.block_stmt()?;
 
-        Ok(Stmt::While(condition, body.into()))
+        Ok(Stmt::While(condition, body.into(), None))
+    }
+
+    /// Parses a count loop, desugared into a `while` loop over a hidden counter.
+    ///
+    /// ```text
+    /// repeat n { body }
+    /// // becomes
+    /// {
+    ///     let <repeat> = <repeat count>(n);
+    ///     while <repeat> > 0 { <repeat> = <repeat> - 1; body }
+    /// }
+    /// ```
+    /// where `<repeat count>` is the native checking the count is a non-negative integer.
+    fn repeat_stmt(&self) -> LoxResult<Stmt> {
+        let keyword = self.inner.previous().unwrap().to_owned();
+        let count = self.expression()?;
+        self.consume(Punctuator::OpenBlock, "expected `{` after repeat count")?;
+        let body = self.block_stmt()?;
+
+        // Uses of the counter from different scopes must have different spans, otherwise
+        // they'd be the same expression to the resolver
+        let token = |kind: TokenKind, span: Span| Token::new(kind, span);
+        let counter = |span| token(TokenKind::identifier("<repeat>"), span);
+        let (outer, inner) = (*keyword.span(), count.position());
+        let int = |i, span| Expr::Literal(token(Numeric::Integer(i).into(), span));
+
+        // Spanning the count, so that the call checking it reports errors there
+        let check = Expr::Variable(token(TokenKind::identifier("<repeat count>"), inner));
+        let count = Expr::Call(check.into(), keyword, Box::new([count]));
+        let decl = Stmt::Variable(vec![counter(outer)], vec![Some(count)], vec![None]);
+        let condition = Expr::Binary(
+            Expr::Variable(counter(outer)).into(),
+            token(Punctuator::GreaterThan.into(), outer),
+            int(0, outer).into(),
+        );
+        let decrement = Expr::Assign(
+            counter(inner),
+            Expr::Binary(
+                Expr::Variable(counter(inner)).into(),
+                token(Punctuator::Sub.into(), inner),
+                int(1, inner).into(),
+            )
+            .into(),
+        );
+        let body = Stmt::Block(vec![Stmt::Expression(decrement), body]);
+        Ok(Stmt::Block(vec![
+            decl,
+            Stmt::While(condition, body.into(), None),
+        ]))
+    }
+
+    /// match type(x) { number => { a } string => { b } else => { c } }
+    ///
+    /// Desugared into a chain of `if`s over the name of the type of `x`, as given by `type`.
+    fn match_stmt(&self) -> LoxResult<Stmt> {
+        let keyword = self.inner.previous().unwrap().to_owned();
+        let ty = self.consume_ident("expected `type` after `match`")?;
+        if ty.to_string() != "type" {
+            return Err(InnerError::new(*ty.span(), "expected `type` after `match`").into());
+        }
+        self.consume(Punctuator::OpenParen, "expected `(` after `type`")?;
+        let value = self.expression()?;
+        self.consume(Punctuator::CloseParen, "expected `)` after matched value")?;
+        self.consume(Punctuator::OpenBlock, "expected `{` before match arms")?;
+
+        let mut arms: Vec<(Token, Stmt)> = Vec::new();
+        let mut fallback = None;
+        while !self.check(Punctuator::CloseBlock) && self.inner.peek().is_some() {
+            let ty = match self.inner.peek().map(Token::kind) {
+                Some(TokenKind::Identifier(_))
+                | Some(TokenKind::Keyword(Keyword::Nil | Keyword::Fn | Keyword::Else)) => {
+                    self.inner.advance().unwrap().to_owned()
+                }
+                _ => {
+                    let span = *self.inner.previous().unwrap().span();
+                    return Err(InnerError::new(span, "expected a type name or `else`").into());
+                }
+            };
+            if fallback.is_some() {
+                return Err(
+                    InnerError::new(*ty.span(), "the `else` arm must be the last one").into(),
+                );
+            }
+            if arms
+                .iter()
+                .any(|(seen, _)| seen.to_string() == ty.to_string())
+            {
+                return Err(
+                    InnerError::new(*ty.span(), &format!("duplicate arm for `{}`", ty)).into(),
+                );
+            }
+            self.consume(Punctuator::FatArrow, "expected `=>` after the type name")?;
+            self.consume(Punctuator::OpenBlock, "expected `{` after `=>`")?;
+            let body = self.block_stmt()?;
+            self.matches(Punctuator::Comma);
+            if *ty.kind() == Keyword::Else.into() {
+                fallback = Some(body);
+            } else {
+                arms.push((ty, body));
+            }
+        }
+        self.consume(Punctuator::CloseBlock, "expected `}` after match arms")?;
+
+        let span = *keyword.span();
+        let name = Token::new(TokenKind::identifier("<match>"), span);
+        let type_of = Expr::Variable(Token::new(TokenKind::identifier("<type>"), span));
+        let value = Expr::Call(type_of.into(), keyword, Box::new([value]));
+        let decl = Stmt::Variable(vec![name.to_owned()], vec![Some(value)], vec![None]);
+        let chain = arms
+            .into_iter()
+            .rev()
+            .fold(fallback, |otherwise, (ty, body)| {
+                let condition = Expr::Binary(
+                    Expr::Variable(name.to_owned()).into(),
+                    Token::new(Punctuator::Eq, *ty.span()),
+                    Expr::Literal(Token::new(
+                        TokenKind::string_literal(ty.to_string()),
+                        *ty.span(),
+                    ))
+                    .into(),
+                );
+                Some(Stmt::If(condition, body.into(), otherwise.map(Box::new)))
+            });
+        Ok(Stmt::Block(std::iter::once(decl).chain(chain).collect()))
     }
 
     fn if_stmt(&self) -> LoxResult<Stmt> {
@@ -350,6 +843,9 @@ impl<'a> Parser<'a> {
     fn block_stmt(&self) -> LoxResult<Stmt> {
         let mut statements = Vec::new();
         while !self.check(Punctuator::CloseBlock) && self.inner.peek().is_some() {
+            if self.skip_empty_statements() {
+                continue;
+            }
             statements.push(self.declaration()?);
         }
         self.consume(Punctuator::CloseBlock, "expected `}` after block")?;
@@ -368,6 +864,16 @@ impl<'a> Parser<'a> {
         Ok(Stmt::Expression(expr))
     }
 
+    /// Consumes stray `;`s, which are harmless empty statements.
+    /// Returns true if any was found.
+    fn skip_empty_statements(&self) -> bool {
+        let mut skipped = false;
+        while self.matches(Punctuator::Semicolon) {
+            skipped = true;
+        }
+        skipped
+    }
+
     /// Helper function for recovering from errors.
     /// It walks the token buffer until it finds a statement boundary.
     fn synchronize(&self) {
@@ -396,20 +902,96 @@ impl<'a> Parser<'a> {
     }
 
     fn assignment(&self) -> LoxResult<Expr> {
-        let expr = self.or()?;
+        let expr = self.conditional()?;
+        // Parentheses are folded away, but no valid target ends with one, so `(a) = 1` is
+        // still rejected
+        let parenthesized = matches!(
+            self.inner.previous().map(Token::kind),
+            Some(TokenKind::Punctuator(Punctuator::CloseParen))
+        );
+        let assigns = [
+            Punctuator::Assign,
+            Punctuator::AssignAdd,
+            Punctuator::AssignSub,
+            Punctuator::AssignMul,
+            Punctuator::AssignDiv,
+            Punctuator::AssignPow,
+        ];
+        if parenthesized && assigns.iter().any(|&assign| self.check(assign)) {
+            return Err(InnerError::new(expr.position(), "invalid assigment target").into());
+        }
 
         if self.matches(Punctuator::Assign) {
+            let val = self.assignment()?;
+            return Self::assign_to(expr, val);
+        }
+        if self.multi_check(&[
+            Punctuator::AssignAdd,
+            Punctuator::AssignSub,
+            Punctuator::AssignMul,
+            Punctuator::AssignDiv,
+            Punctuator::AssignPow,
+        ]) {
+            let assign = self.inner.previous().unwrap().to_owned();
+            let op = match assign.kind() {
+                TokenKind::Punctuator(Punctuator::AssignAdd) => Punctuator::Add,
+                TokenKind::Punctuator(Punctuator::AssignSub) => Punctuator::Sub,
+                TokenKind::Punctuator(Punctuator::AssignMul) => Punctuator::Mul,
+                TokenKind::Punctuator(Punctuator::AssignPow) => Punctuator::Pow,
+                _ => Punctuator::Div,
+            };
+            let op = Token::new(op, *assign.span());
             let val = self.assignment()?;
             return match expr {
-                Expr::Variable(name) => Ok(Expr::Assign(name, Box::new(val))),
-                Expr::Get(object, name) => Ok(Expr::Set(object, name, Box::new(val))),
-                Expr::Index(name, idx) => Ok(Expr::IndexAssign(name, idx, Box::new(val))),
-                _ => Err(InnerError::new(expr.position(), "invalid assigment target").into()),
+                Expr::Get(..) | Expr::Index(..) => Ok(Expr::Compound(expr.into(), op, val.into())),
+                _ => {
+                    let val = Expr::Binary(expr.clone().into(), op, val.into());
+                    Self::assign_to(expr, val)
+                }
             };
         }
         Ok(expr)
     }
 
+    /// Builds the assignment of `val` to `target`. Compound assignments to variables are
+    /// desugared, `x += 1` being `x = x + 1`, while fields and elements get an
+    /// [`Expr::Compound`] so that their object and index are evaluated once.
+    fn assign_to(target: Expr, val: Expr) -> LoxResult<Expr> {
+        match target {
+            Expr::Variable(name) => Ok(Expr::Assign(name, Box::new(val))),
+            Expr::Get(object, name) => Ok(Expr::Set(object, name, Box::new(val))),
+            Expr::Index(name, idx) => Ok(Expr::IndexAssign(name, idx, Box::new(val))),
+            _ => Err(InnerError::new(target.position(), "invalid assigment target").into()),
+        }
+    }
+
+    /// Parses a conditional, the `else` branch being parsed again as one, so that
+    /// `a ? b : c ? d : e` is `a ? b : (c ? d : e)`.
+    fn conditional(&self) -> LoxResult<Expr> {
+        let condition = self.range()?;
+        if !self.matches(Punctuator::Question) {
+            return Ok(condition);
+        }
+        let then = self.expression()?;
+        self.consume(Punctuator::Colon, "expected `:` after the `then` branch")?;
+        let otherwise = self.conditional()?;
+        Ok(Expr::Conditional(
+            condition.into(),
+            then.into(),
+            otherwise.into(),
+        ))
+    }
+
+    /// Parses a range, `a..b` is the integers from `a` up to, but not including, `b`.
+    fn range(&self) -> LoxResult<Expr> {
+        let start = self.pipe()?;
+        if !self.matches(Punctuator::DotDot) {
+            return Ok(start);
+        }
+        let end = self.pipe()?;
+        Ok(Expr::Range(start.into(), end.into()))
+    }
+
     /// Parses a logical OR
     fn or(&self) -> LoxResult<Expr> {
         let mut expr = self.and()?;
@@ -454,13 +1036,31 @@ impl<'a> Parser<'a> {
     /// Parses addition/subtraction expressions
     #[inline]
     fn term(&self) -> LoxResult<Expr> {
-        self.parse_left(&[Punctuator::Add, Punctuator::Sub], Self::factor)
+        let expr = self.parse_left(&[Punctuator::Add, Punctuator::Sub], Self::factor)?;
+        if Expr::is_concat(&expr) {
+            return Ok(Expr::Concat(expr.into()));
+        }
+        Ok(expr)
     }
 
     /// Parses division/multiplication expressions
     #[inline]
     fn factor(&self) -> LoxResult<Expr> {
-        self.parse_left(&[Punctuator::Div, Punctuator::Mul], Self::unary)
+        self.parse_left(
+            &[Punctuator::Div, Punctuator::Mul, Punctuator::Mod],
+            Self::power,
+        )
+    }
+
+    /// Parses an exponentiation, which is right-associative: `2 ** 3 ** 2` is `2 ** (3 ** 2)`.
+    fn power(&self) -> LoxResult<Expr> {
+        let base = self.unary()?;
+        if self.matches(Punctuator::Pow) {
+            let op = self.inner.previous().unwrap().to_owned();
+            let exponent = self.power()?;
+            return Ok(Expr::Binary(base.into(), op, exponent.into()));
+        }
+        Ok(base)
     }
 
     /// Parses logic/arithmetic negation expressions
@@ -471,27 +1071,40 @@ impl<'a> Parser<'a> {
             let rhs = self.unary()?;
             return Ok(Expr::Unary(op, rhs.into()));
         }
-        self.pipe()
+        if let Some(TokenKind::Operator(symbol)) = self.inner.peek().map(Token::kind) {
+            let op = self.inner.advance().unwrap().to_owned();
+            let function = Token::new(TokenKind::identifier(symbol.to_owned()), *op.span());
+            let rhs = self.unary()?;
+            return Ok(Expr::Call(
+                Expr::Variable(function).into(),
+                op,
+                Box::new([rhs]),
+            ));
+        }
+        self.call()
     }
 
+    /// Parses a pipeline, `x |> f(a)` is rewritten into `f(x, a)` and `x |> f` into `f(x)`.
     fn pipe(&self) -> LoxResult<Expr> {
-        let mut expr = self.call()?;
+        let mut expr = self.or()?;
         while self.matches(Punctuator::Pipe) {
-            if let Expr::Call(callee, paren, args) = self.call()? {
-                let args = [expr]
-                    .iter()
-                    .chain(&args)
-                    .map(|x| x.to_owned())
-                    .collect::<Vec<_>>();
-
-                expr = Expr::Call(callee, paren, args);
-            } else {
-                return Err(InnerError::new(
-                    expr.position(),
-                    "cannot pipe into non-callable expression",
-                )
-                .into());
-            }
+            let pipe = self.inner.previous().unwrap().to_owned();
+            expr = match self.call()? {
+                Expr::Call(callee, paren, args) => {
+                    let args = std::iter::once(expr).chain(args.into_vec()).collect();
+                    Expr::Call(callee, paren, args)
+                }
+                callee @ Expr::Variable(_) | callee @ Expr::Get(..) => {
+                    Expr::Call(callee.into(), pipe, Box::new([expr]))
+                }
+                oth => {
+                    return Err(InnerError::new(
+                        oth.position(),
+                        "cannot pipe into non-callable expression",
+                    )
+                    .into())
+                }
+            };
         }
         Ok(expr)
     }
@@ -506,6 +1119,10 @@ impl<'a> Parser<'a> {
             } else if self.matches(Punctuator::Dot) {
                 let name = self.consume_ident("expected property name after `.`")?;
                 expr = Expr::Get(Box::new(expr), name.to_owned());
+            } else if self.matches(Punctuator::OpenBracket) {
+                let idx = self.expression()?;
+                self.consume(Punctuator::CloseBracket, "expected `]` after index")?;
+                expr = Expr::Index(Box::new(expr), Box::new(idx));
             } else {
                 break;
             }
@@ -513,6 +1130,15 @@ impl<'a> Parser<'a> {
         Ok(expr)
     }
 
+    /// Parses a call argument or array element, which may be spread.
+    fn element(&self) -> LoxResult<Expr> {
+        if self.matches(Punctuator::Ellipsis) {
+            let ellipsis = self.inner.previous().unwrap().to_owned();
+            return Ok(Expr::Spread(ellipsis, self.expression()?.into()));
+        }
+        self.expression()
+    }
+
     fn finish_call(&self, callee: Expr) -> LoxResult<Expr> {
         let mut args = Vec::new();
 
@@ -526,7 +1152,7 @@ impl<'a> Parser<'a> {
                     .into());
                 }
 
-                args.push(self.expression()?);
+                args.push(self.element()?);
 
                 if !self.matches(Punctuator::Comma) {
                     break;
@@ -538,7 +1164,7 @@ impl<'a> Parser<'a> {
             .consume(Punctuator::CloseParen, "Expected `)` after arguments")?
             .to_owned();
 
-        Ok(Expr::Call(callee.into(), paren, args))
+        Ok(Expr::Call(callee.into(), paren, args.into()))
     }
 
     /// Parses primary expressions (literals, groups)
@@ -550,24 +1176,19 @@ impl<'a> Parser<'a> {
                 TokenKind::NumericLiteral(_) => Expr::Literal(tk.to_owned()),
                 TokenKind::Keyword(Keyword::Nil) => Expr::Literal(tk.to_owned()),
                 TokenKind::Keyword(Keyword::This) => Expr::This(tk.to_owned()),
-                TokenKind::Identifier(_) => {
-                    if self.matches(Punctuator::OpenBracket) {
-                        let idx = self.expression()?;
-                        self.consume(Punctuator::CloseBracket, "expected `]` after index")?;
-                        return Ok(Expr::Index(tk.to_owned(), Box::new(idx)));
-                    }
-                    Expr::Variable(tk.to_owned())
-                }
+                TokenKind::Identifier(_) => Expr::Variable(tk.to_owned()),
                 TokenKind::Keyword(Keyword::Super) => {
                     self.consume(Punctuator::Dot, "expected `.` after `super`")?;
                     let method = self.consume_ident("expected superclass method name")?;
-                    return Ok(Expr::Super(tk.to_owned(), method.to_owned()));
+                    return Ok(Expr::Super(tk.to_owned(), method.to_owned().into()));
                 }
                 TokenKind::Punctuator(Punctuator::OpenParen) => {
                     let expr = self.expression()?;
                     self.consume(Punctuator::CloseParen, "expected `)` after expression")?;
-                    return Ok(Expr::Grouping(expr.into()));
+                    // Parentheses only matter for precedence, which the tree already encodes
+                    return Ok(expr);
                 }
+                TokenKind::Keyword(Keyword::Loop) => return self.loop_expr(),
                 TokenKind::Keyword(Keyword::Static) => {
                     return Err(InnerError::new(
                         *tk.span(),
@@ -578,17 +1199,17 @@ impl<'a> Parser<'a> {
                 TokenKind::Punctuator(Punctuator::OpenBracket) => {
                     let mut values = Vec::new();
                     while !self.check(Punctuator::CloseBracket) {
-                        values.push(self.expression()?);
+                        values.push(self.element()?);
                         if !self.matches(Punctuator::Comma) {
                             break;
                         }
                     }
                     self.consume(Punctuator::CloseBracket, "expected `]` after array")?;
-                    return Ok(Expr::Array(tk.to_owned(), values));
+                    return Ok(Expr::Array(tk.to_owned(), values.into()));
                 }
                 _ => {
                     return Err(
-                        InnerError::new(*tk.span(), &*format!("unexpected token `{}`", tk)).into(),
+                        InnerError::new(*tk.span(), &format!("unexpected token `{}`", tk)).into(),
                     )
                 }
             };
@@ -644,7 +1265,7 @@ impl<'a> Parser<'a> {
                 return Ok(self.inner.advance().unwrap());
             }
         };
-        return Err(InnerError::new(*self.inner.previous().unwrap().span(), msg).into());
+        Err(InnerError::new(*self.inner.previous().unwrap().span(), msg).into())
     }
 
     /// Parses left associative tokens.
@@ -668,6 +1289,31 @@ impl<'a> Parser<'a> {
     }
 }
 
+/// Rebuilds the source code of the tokens, as long as they are on the same line.
+fn source_text(tokens: &[Token]) -> String {
+    let mut text = String::new();
+    let mut prev: Option<&Token> = None;
+    for tk in tokens {
+        if let Some(prev) = prev {
+            let (end, start) = (prev.span().end(), tk.span().start());
+            let gap = if end.line_number() == start.line_number() {
+                start
+                    .column_number()
+                    .saturating_sub(end.column_number() + 1)
+            } else {
+                1
+            };
+            text += &" ".repeat(gap as usize);
+        }
+        match tk.kind() {
+            TokenKind::StringLiteral(s) => text += &format!("\"{}\"", s),
+            kind => text += &kind.to_string(),
+        }
+        prev = Some(tk);
+    }
+    text
+}
+
 use std::cell::RefCell;
 
 /// Inner iterator for the parser.
@@ -694,6 +1340,18 @@ impl<'a, T> InnerIter<'a, T> {
         None
     }
 
+    /// Index of the next item.
+    #[inline]
+    fn index(&self) -> usize {
+        *self.current.borrow()
+    }
+
+    /// Items consumed since `start`.
+    #[inline]
+    fn since(&self, start: usize) -> &'a [T] {
+        &self.collection[start..self.index()]
+    }
+
     #[inline]
     fn previous(&self) -> Option<&T> {
         self.collection
@@ -705,6 +1363,11 @@ impl<'a, T> InnerIter<'a, T> {
         self.collection.get(*self.current.borrow())
     }
 
+    #[inline]
+    fn peek_next(&self) -> Option<&T> {
+        self.collection.get(*self.current.borrow() + 1)
+    }
+
     #[inline]
     fn next_if(&self, test: bool) -> bool {
         if test {
@@ -717,7 +1380,10 @@ impl<'a, T> InnerIter<'a, T> {
 
 #[cfg(test)]
 mod test {
-    use crate::lib::lexer::Lexer;
+    use crate::{
+        interpreter::{Environment, Interpreter},
+        lexer::Lexer,
+    };
 
     use super::*;
 
@@ -726,12 +1392,71 @@ mod test {
         let src = "(1+(3-2)+4);";
         let tokens = Lexer::new(src).scan_tokens().unwrap();
         let expr = Parser::new(&tokens).parse().unwrap();
-        let expected_expression = "(group (+ (+ 1 (group (- 3 2))) 4))";
+        let expected_expression = "(+ (+ 1 (- 3 2)) 4)";
         assert!(
             matches!(&expr[0], Stmt::Expression(expr) if expr.to_string() == expected_expression)
         )
     }
 
+    #[test]
+    fn parentheses_are_folded_into_the_inner_expression() {
+        let src = "(((1)));";
+        let tokens = Lexer::new(src).scan_tokens().unwrap();
+        let stmts = Parser::new(&tokens).parse().unwrap();
+        match &stmts[0] {
+            Stmt::Expression(expr @ Expr::Literal(_)) => {
+                assert_eq!(expr.position().to_string(), "[1:4..1:4]");
+                let val = expr
+                    .evaluate(Environment::new().into(), &Interpreter::new(&[]))
+                    .unwrap();
+                assert_eq!(val.to_string(), "1");
+            }
+            stmt => panic!("expected a literal, got {:?}", stmt),
+        }
+    }
+
+    #[test]
+    fn parenthesized_assignment_targets_are_rejected() {
+        let parse = |src| {
+            let tokens = Lexer::new(src).scan_tokens().unwrap();
+            Parser::new(&tokens).parse()
+        };
+        for src in ["(a) = 1;", "((a)) = 1;", "(a.b) = 1;", "(a[0]) += 1;"] {
+            let errors = parse(src).err().unwrap_or_else(|| panic!("{} parsed", src));
+            assert_eq!(errors[0].to_string(), "invalid assigment target", "{}", src);
+        }
+        for src in ["(a).b = 1;", "(a)[0] = 1;", "a = (b);", "(a = 1);"] {
+            assert!(parse(src).is_ok(), "{}", src);
+        }
+    }
+
+    #[test]
+    fn folds_chains_of_string_concatenation() {
+        let parse = |src| {
+            let tokens = Lexer::new(src).scan_tokens().unwrap();
+            Parser::new(&tokens).parse().unwrap().remove(0)
+        };
+        match parse(r#"a + "-" + b + 1;"#) {
+            Stmt::Expression(Expr::Concat(chain)) => {
+                assert_eq!(chain.to_string(), "(+ (+ (+ a -) b) 1)")
+            }
+            stmt => panic!("expected a concatenation, got {:?}", stmt),
+        }
+        // Too short, no strings, or operands that aren't literals or variables
+        for src in [
+            r#"a + "b";"#,
+            "a + b + c;",
+            r#"a + f() + "c";"#,
+            r#"a - "b" + c;"#,
+        ] {
+            assert!(
+                !matches!(parse(src), Stmt::Expression(Expr::Concat(_))),
+                "{}",
+                src
+            );
+        }
+    }
+
     #[test]
     fn parses_var_declaration() {
         let src = "let foo, bar = true, false;";
@@ -739,7 +1464,48 @@ mod test {
         let expr = Parser::new(&tokens).parse().unwrap();
 
         assert!(
-            matches!(&expr[0], Stmt::Variable(tk, expr) if &tk[0].to_string() == "foo" && &expr[0].as_ref().unwrap().to_string() == "true")
+            matches!(&expr[0], Stmt::Variable(tk, expr, _) if &tk[0].to_string() == "foo" && &expr[0].as_ref().unwrap().to_string() == "true")
         );
     }
+
+    #[test]
+    fn parses_type_annotations() {
+        let src = "let x: number, y = 1, 2; fn f(a: string, b): number { return a; }";
+        let tokens = Lexer::new(src).scan_tokens().unwrap();
+        let stmts = Parser::new(&tokens).parse().unwrap();
+
+        let annotations = |types: &[Option<Token>]| -> Vec<_> {
+            types
+                .iter()
+                .map(|t| t.as_ref().map(Token::to_string))
+                .collect()
+        };
+
+        match &stmts[0] {
+            Stmt::Variable(_, _, types) => {
+                assert_eq!(annotations(types), vec![Some("number".into()), None]);
+            }
+            stmt => panic!("expected variable declaration, got {}", stmt),
+        }
+        match &stmts[1] {
+            Stmt::Function(_, params, _, sig) => {
+                assert_eq!(params.len(), 2);
+                assert_eq!(annotations(&sig.params), vec![Some("string".into()), None]);
+                assert_eq!(
+                    sig.ret.as_ref().map(Token::to_string),
+                    Some("number".into())
+                );
+            }
+            stmt => panic!("expected function declaration, got {}", stmt),
+        }
+    }
+
+    /// The bounds documented on [`Expr`] and [`Stmt`], which hold pointers and so only make
+    /// sense on 64-bit targets.
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn syntax_nodes_stay_small() {
+        assert!(std::mem::size_of::<Expr>() <= 64);
+        assert!(std::mem::size_of::<Stmt>() <= 112);
+    }
 }
@@ -1,16 +1,21 @@
-use std::{collections::HashMap, io::Write, rc::Rc};
-
-use crate::lib::{
+use crate::{
     error::*,
-    interpreter::{Environment, LoxClass, LoxFunction, LoxValue},
+    interpreter::{
+        Environment, Interpreter, LoxCallable, LoxClass, LoxEnum, LoxFunction, LoxValue,
+    },
     position::Span,
+    sync::{Rc, RefCell},
     token::Token,
 };
 
 use super::Expr;
 
+/// Language statements
+///
+/// Every statement takes the space of the largest variant, so the payloads of rare or large
+/// variants are boxed to keep them at most 112 bytes.
 #[allow(dead_code)]
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub(crate) enum Stmt {
     /// Expression statement(expression)
     Expression(Expr),
@@ -20,10 +25,18 @@ pub(crate) enum Stmt {
     Return(Token, Expr),
     /// If statement(condition, then, else)
     If(Expr, Box<Stmt>, Option<Box<Stmt>>),
-    /// Function statement(name, params, body)
-    Function(Token, Vec<Token>, Box<Stmt>),
+    /// Function statement(name, params, body, signature)
+    Function(Token, Vec<Token>, Box<Stmt>, Box<Signature>),
     /// Class statement(name, superclass: Expr::Variable, methods: Vec<Stmt::Function>)
-    Class(Token, Option<Expr>, Vec<Stmt>, Vec<Stmt>),
+    Class(Token, Option<Box<Expr>>, Vec<Stmt>, Vec<Stmt>),
+    /// Enum statement(name, members)
+    ///
+    /// Declares a namespace holding one distinct value per member.
+    /// ```text
+    /// enum Color { Red, Green }
+    /// print Color.Red; // Red
+    /// ```
+    Enum(Token, Vec<Token>),
     /// Variable declaration statement (names, initializers)
     ///
     /// Holds a vector because there can be more than one variable being declared at a time.
@@ -32,34 +45,108 @@ pub(crate) enum Stmt {
     /// let a, b, c;
     /// let a, b, c = 1;
     /// ```
-    Variable(Vec<Token>, Vec<Option<Expr>>),
-    /// While statement(condition, body)
-    While(Expr, Box<Stmt>),
+    ///
+    /// Each name may carry a type annotation (`let a: number;`), these are ignored at runtime.
+    Variable(Vec<Token>, Vec<Option<Expr>>, Vec<Option<Token>>),
+    /// Defaulted variable declaration statement (name, initializer, type)
+    ///
+    /// Defines the variable only if it isn't already defined in the current scope, or is `nil`.
+    /// ```text
+    /// let x ?= compute_default();
+    /// ```
+    VariableDefault(Token, Box<Expr>, Option<Token>),
+    /// Constant declaration statement(name, initializer), can't be assigned to afterwards
+    Const(Token, Expr),
+    /// Destructuring declaration statement(pattern, initializer)
+    /// ```text
+    /// let [a, b, ...rest] = arr;
+    /// ```
+    Destructure(Box<Pattern>, Expr),
+    /// While statement(condition, body, increment), the increment of a `for` loop runs after
+    /// every iteration, even one cut short by `continue`
+    While(Expr, Box<Stmt>, Option<Box<Expr>>),
+    /// For each statement(name, iterable, body)
+    ///
+    /// Runs the body once for every element of an array, or every integer of a range, bound
+    /// to a fresh variable each time.
+    /// ```text
+    /// for x in [1, 2, 3] { print x; }
+    /// for i in 0..10 { print i; }
+    /// ```
+    ForEach(Token, Expr, Box<Stmt>),
     /// Block statement(statements)
     Block(Vec<Stmt>),
+    /// Labeled statement(label, body), the body can be exited early with `break label;`
+    /// ```text
+    /// outer: {
+    ///     if done { break outer; }
+    /// }
+    /// ```
+    Labeled(Token, Box<Stmt>),
+    /// Break statement(keyword, label, value), exits the innermost loop unless it has a label,
+    /// only a `loop` can be exited with a value
+    Break(Token, Option<Token>, Option<Box<Expr>>),
+    /// Continue statement(keyword), skips the rest of the body of the innermost loop
+    Continue(Token),
+    /// Assert statement(keyword, condition, source of the condition)
+    ///
+    /// Fails if the condition is falsy, removed before running when asserts are disabled.
+    Assert(Token, Box<Expr>, String),
+    /// With statement(keyword, resource, name, body)
+    ///
+    /// The `close` method of the resource is called once the body is done, even if it
+    /// returns early or fails.
+    /// ```text
+    /// with open("log") as file {
+    ///     file.write("hi");
+    /// }
+    /// ```
+    With(Token, Box<Expr>, Token, Box<Stmt>),
+    /// Static variable declaration(name, initializer, target)
+    ///
+    /// Declared at the top of a function body, the variable keeps its value between calls.
+    /// The initializer only runs the first time. `target` is a variable expression that the
+    /// resolver resolves to the variable's place.
+    /// ```text
+    /// fn next() {
+    ///     static let count = 0;
+    ///     count = count + 1;
+    ///     return count;
+    /// }
+    /// ```
+    Static(Token, Box<Expr>, Box<Expr>),
+    /// Defer statement(keyword, body)
+    ///
+    /// The body runs once the enclosing function returns, however it does, after the
+    /// statements deferred later.
+    /// ```text
+    /// fn log() {
+    ///     defer print "done";
+    ///     print "working";
+    /// }
+    /// ```
+    Defer(Token, Box<Stmt>),
 }
 
 impl Stmt {
-    pub fn execute(
-        &self,
-        env: Rc<Environment>,
-        locals: &HashMap<Expr, usize>,
-        writer: &mut dyn Write,
-    ) -> LoxResult<()> {
+    pub fn execute(&self, env: Rc<Environment>, interpreter: &Interpreter) -> LoxResult<()> {
+        interpreter.record(self);
         match &self {
             Stmt::Expression(expr) => {
-                expr.evaluate(env, locals)?;
+                expr.evaluate(env, interpreter)?;
             }
             Stmt::Print(expr) => {
-                writer.write_all(format!("{}\n", expr.evaluate(env, locals)?).as_bytes())?;
+                interpreter.write(&format!("{}\n", expr.evaluate(env, interpreter)?))?;
             }
-            Stmt::Variable(names, initializers) => {
+            Stmt::Variable(names, initializers, _) => {
                 let variables: Vec<_> = names
                     .iter()
                     .zip(initializers)
                     .map(|(name, initializer)| {
                         let value = match initializer {
-                            Some(initializer) => initializer.evaluate(Rc::clone(&env), locals)?,
+                            Some(initializer) => {
+                                initializer.evaluate(Rc::clone(&env), interpreter)?
+                            }
                             None => Rc::new(LoxValue::Nil),
                         };
                         Ok((name.to_string(), value))
@@ -70,32 +157,155 @@ impl Stmt {
                     env.define(&name, value);
                 }
             }
+            Stmt::Const(name, initializer) => {
+                let value = initializer.evaluate(Rc::clone(&env), interpreter)?;
+                env.define(&name.to_string(), value);
+            }
+            Stmt::VariableDefault(name, initializer, _) => {
+                let name = name.to_string();
+                let is_set =
+                    matches!(env.get_here(&name), Some(val) if !matches!(*val, LoxValue::Nil));
+                if !is_set {
+                    let value = initializer.evaluate(Rc::clone(&env), interpreter)?;
+                    env.define(&name, value);
+                }
+            }
+            Stmt::Destructure(pattern, initializer) => {
+                let value = initializer.evaluate(Rc::clone(&env), interpreter)?;
+                for (name, value) in pattern.bind(&value)? {
+                    env.define(&name.to_string(), value);
+                }
+            }
             Stmt::Block(stmts) => {
                 let scope = Rc::new(Environment::from(env));
                 for stmt in stmts {
-                    stmt.execute(Rc::clone(&scope), locals, writer)?;
+                    stmt.execute(Rc::clone(&scope), interpreter)?;
                 }
             }
             Stmt::If(condition, then_branch, else_branch) => {
-                let condition = condition.evaluate(Rc::clone(&env), locals)?;
+                let condition = condition.evaluate(Rc::clone(&env), interpreter)?;
                 if condition.is_truthy() {
-                    then_branch.execute(env, locals, writer)?;
+                    then_branch.execute(env, interpreter)?;
                 } else if let Some(stmt) = else_branch {
-                    stmt.execute(env, locals, writer)?;
+                    stmt.execute(env, interpreter)?;
+                }
+            }
+            Stmt::While(condition, body, increment) => {
+                while condition
+                    .evaluate(Rc::clone(&env), interpreter)?
+                    .is_truthy()
+                {
+                    match body.execute(Rc::clone(&env), interpreter) {
+                        Err(LoxError::Break(None, _)) => break,
+                        Err(LoxError::Continue) => (),
+                        res => res?,
+                    }
+                    if let Some(increment) = increment {
+                        increment.evaluate(Rc::clone(&env), interpreter)?;
+                    }
+                }
+            }
+            Stmt::ForEach(name, iterable, body) => {
+                let values: Box<dyn Iterator<Item = Rc<LoxValue>>> =
+                    match &*iterable.evaluate(Rc::clone(&env), interpreter)? {
+                        // Elements pushed by the body aren't iterated
+                        LoxValue::Array(values) => Box::new(values.borrow().clone().into_iter()),
+                        LoxValue::Range(start, end) => {
+                            Box::new((*start..*end).map(|i| Rc::new(LoxValue::Integer(i))))
+                        }
+                        oth => {
+                            let msg = format!(
+                                "cannot iterate over `{}`, only arrays and ranges can be iterated",
+                                oth.type_name()
+                            );
+                            return Err(InnerError::new(iterable.position(), &msg).into());
+                        }
+                    };
+                for value in values {
+                    let scope = Rc::new(Environment::from(Rc::clone(&env)));
+                    scope.define(&name.to_string(), value);
+                    match body.execute(scope, interpreter) {
+                        Err(LoxError::Break(None, _)) => break,
+                        Err(LoxError::Continue) => (),
+                        res => res?,
+                    }
+                }
+            }
+            Stmt::Labeled(label, body) => match body.execute(env, interpreter) {
+                Err(LoxError::Break(Some(target), _)) if target == label.to_string() => (),
+                res => res?,
+            },
+            Stmt::Break(_, label, value) => {
+                let value = match value {
+                    Some(value) => value.evaluate(env, interpreter)?,
+                    None => Rc::new(LoxValue::Nil),
+                };
+                return Err(LoxError::Break(label.as_ref().map(Token::to_string), value));
+            }
+            Stmt::Continue(_) => return Err(LoxError::Continue),
+            Stmt::Assert(_, condition, source) => {
+                if !condition.evaluate(env, interpreter)?.is_truthy() {
+                    return Err(InnerError::new(
+                        condition.position(),
+                        &format!("assertion failed: `{}`", source),
+                    )
+                    .into());
                 }
             }
-            Stmt::While(condition, body) => {
-                while condition.evaluate(Rc::clone(&env), locals)?.is_truthy() {
-                    body.execute(Rc::clone(&env), locals, writer)?;
+            Stmt::With(kw, resource, name, body) => {
+                let resource = resource.evaluate(Rc::clone(&env), interpreter)?;
+                let close = match &*resource {
+                    LoxValue::Instance(instance) => instance.method("close").transpose()?,
+                    _ => None,
                 }
+                .filter(|close| close.arity() == 0)
+                .ok_or_else(|| {
+                    InnerError::new(
+                        *kw.span(),
+                        &format!(
+                            "`{}` cannot be used in `with`, it has no `close()` method",
+                            resource
+                        ),
+                    )
+                })?;
+
+                let scope = Rc::new(Environment::from(env));
+                scope.define(&name.to_string(), resource);
+                let res = body.execute(Rc::clone(&scope), interpreter);
+                close.call(scope, interpreter, &[])?;
+                res?;
             }
-            Stmt::Function(name, _, _) => {
+            Stmt::Static(name, initializer, target) => {
+                let local = interpreter
+                    .local(target)
+                    .ok_or_else(|| InnerError::new(*name.span(), "unresolved static variable"))?;
+                if !env.is_defined_at(local) {
+                    let value = initializer.evaluate(Rc::clone(&env), interpreter)?;
+                    env.define_at(local, &name.to_string(), value);
+                }
+            }
+            Stmt::Defer(kw, body) => {
+                if !env.defer(body, &env) {
+                    return Err(
+                        InnerError::new(*kw.span(), "cannot defer outside of a function").into(),
+                    );
+                }
+            }
+            Stmt::Enum(name, members) => {
+                let members: Vec<_> = members.iter().map(Token::to_string).collect();
+                let namespace = LoxEnum::new(name.to_string(), &members);
+                env.define(
+                    &name.to_string(),
+                    Rc::new(LoxValue::Enum(Rc::new(namespace))),
+                );
+            }
+            Stmt::Function(name, ..) => {
                 let function = Rc::new(LoxFunction::new(self.to_owned(), Rc::clone(&env), false)?);
                 env.define(&name.to_string(), Rc::new(LoxValue::Callable(function)));
             }
             Stmt::Return(kw, val) => {
                 return Err(ReturnVal::new(
-                    (*val.evaluate(env, locals)?).to_owned(),
+                    (*val.evaluate(env, interpreter)?).to_owned(),
                     Span::new(kw.span().start(), val.position().end()),
                 )
                 .into());
@@ -105,13 +315,15 @@ impl Stmt {
                 let name = name.to_string();
 
                 let superclass = if let Some(superclass) = &superclass {
-                    let superclass = superclass.evaluate(Rc::clone(&env), locals)?;
-                    if let LoxValue::Callable(f) = &*superclass {
-                        if f.as_any().downcast_ref::<LoxClass>().is_none() {
-                            return Err(InnerError::new(*pos, "superclass must be a class").into());
-                        }
+                    let value = superclass.evaluate(Rc::clone(&env), interpreter)?;
+                    if value.as_class().is_err() {
+                        return Err(InnerError::new(
+                            superclass.position(),
+                            &format!("superclass must be a class, got `{}`", value),
+                        )
+                        .into());
                     }
-                    Some(superclass)
+                    Some(value)
                 } else {
                     None
                 };
@@ -159,17 +371,136 @@ impl Stmt {
         Ok(())
     }
 
+    /// Source span of the statement's head, blocks don't have one.
+    pub fn position(&self) -> Option<Span> {
+        match self {
+            Stmt::Expression(expr) | Stmt::Print(expr) => Some(expr.position()),
+            Stmt::Return(kw, val) => Some(Span::new(kw.span().start(), val.position().end())),
+            Stmt::If(condition, ..) | Stmt::While(condition, ..) => Some(condition.position()),
+            Stmt::Function(name, ..) | Stmt::Class(name, ..) | Stmt::Enum(name, _) => {
+                Some(*name.span())
+            }
+            Stmt::Variable(names, ..) => match (names.first(), names.last()) {
+                (Some(first), Some(last)) => {
+                    Some(Span::new(first.span().start(), last.span().end()))
+                }
+                _ => None,
+            },
+            Stmt::VariableDefault(name, ..) | Stmt::Const(name, _) => Some(*name.span()),
+            Stmt::Destructure(pattern, _) => match &**pattern {
+                Pattern::Array(tk, ..) | Pattern::Object(tk, _) => Some(*tk.span()),
+            },
+            Stmt::Block(_) => None,
+            Stmt::Labeled(tk, _)
+            | Stmt::Break(tk, ..)
+            | Stmt::Continue(tk)
+            | Stmt::ForEach(tk, ..)
+            | Stmt::With(tk, ..)
+            | Stmt::Defer(tk, _)
+            | Stmt::Static(tk, ..)
+            | Stmt::Assert(tk, ..) => Some(*tk.span()),
+        }
+    }
+
     pub fn name(&self) -> String {
         match self {
             Stmt::Function(name, ..) => name.to_string(),
-            Stmt::Class(name, ..) => name.to_string(),
+            Stmt::Class(name, ..) | Stmt::Enum(name, _) => name.to_string(),
             _ => self.to_string(),
         }
     }
 }
 
+/// Optional type annotations of a function declaration, ignored at runtime.
+/// ```text
+/// fn add(a: number, b: number): number { ... }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Signature {
+    /// Annotation of each parameter, in declaration order
+    pub params: Vec<Option<Token>>,
+    /// Annotation of the return value
+    pub ret: Option<Token>,
+}
+
+/// Left hand side of a destructuring declaration.
+#[derive(Debug, Clone)]
+pub(crate) enum Pattern {
+    /// Binds array elements by position (bracket, names, rest).
+    ///
+    /// The array must have at least as many elements as there are names, extra elements are
+    /// collected into the rest name if there's one, and ignored otherwise.
+    Array(Token, Vec<Token>, Option<Token>),
+    /// Binds instance fields by name (brace, (field, name) pairs).
+    ///
+    /// Missing fields are an error, same as accessing them with `.`.
+    Object(Token, Vec<(Token, Token)>),
+}
+
+impl Pattern {
+    /// Every name bound by the pattern, in declaration order.
+    pub fn names(&self) -> Vec<&Token> {
+        match self {
+            Pattern::Array(_, names, rest) => names.iter().chain(rest).collect(),
+            Pattern::Object(_, fields) => fields.iter().map(|(_, name)| name).collect(),
+        }
+    }
+
+    /// Matches `value` against the pattern, pairing each name with its value.
+    fn bind<'a>(&'a self, value: &LoxValue) -> LoxResult<Vec<(&'a Token, Rc<LoxValue>)>> {
+        match self {
+            Pattern::Array(bracket, names, rest) => {
+                let values = match value {
+                    LoxValue::Array(values) => values.borrow(),
+                    oth => {
+                        return Err(InnerError::new(
+                            *bracket.span(),
+                            &format!("cannot destructure `{}` as an array", oth),
+                        )
+                        .into())
+                    }
+                };
+                if values.len() < names.len() {
+                    return Err(InnerError::new(
+                        *bracket.span(),
+                        &format!(
+                            "expected an array of at least {} elements, found {}",
+                            names.len(),
+                            values.len()
+                        ),
+                    )
+                    .into());
+                }
+
+                let mut bindings: Vec<_> = names.iter().zip(values.iter().cloned()).collect();
+                if let Some(rest) = rest {
+                    let tail = values[names.len()..].to_vec();
+                    bindings.push((rest, Rc::new(LoxValue::Array(RefCell::new(tail)))));
+                }
+                Ok(bindings)
+            }
+            Pattern::Object(brace, fields) => {
+                let instance = match value {
+                    LoxValue::Instance(instance) => instance,
+                    oth => {
+                        return Err(InnerError::new(
+                            *brace.span(),
+                            &format!("cannot destructure `{}` as an object", oth),
+                        )
+                        .into())
+                    }
+                };
+                fields
+                    .iter()
+                    .map(|(field, name)| Ok((name, instance.get(field)?)))
+                    .collect()
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
-pub(crate) struct ReturnVal {
+pub struct ReturnVal {
     pub val: LoxValue,
     pub pos: Span,
 }
@@ -192,9 +523,20 @@ impl std::fmt::Display for Stmt {
                 Stmt::If(..) => "if",
                 Stmt::Function(..) => "function",
                 Stmt::Class(..) => "class",
-                Stmt::Variable(..) => "variable",
+                Stmt::Variable(..) | Stmt::VariableDefault(..) => "variable",
+                Stmt::Const(..) => "constant",
+                Stmt::Enum(..) => "enum",
+                Stmt::Destructure(..) => "destructure",
                 Stmt::While(..) => "while",
+                Stmt::ForEach(..) => "for",
                 Stmt::Block(..) => "block",
+                Stmt::Labeled(..) => "labeled",
+                Stmt::Break(..) => "break",
+                Stmt::Continue(..) => "continue",
+                Stmt::With(..) => "with",
+                Stmt::Defer(..) => "defer",
+                Stmt::Static(..) => "static variable",
+                Stmt::Assert(..) => "assert",
             }
         )
     }
@@ -0,0 +1,383 @@
+//! Read-only traversal over the syntax tree.
+//!
+//! Implementors override only the hooks they care about; every default hook walks into the
+//! node's children, so an empty `impl Visitor for T {}` visits the whole program.
+//!
+//! ```ignore
+//! struct FnCounter(usize);
+//!
+//! impl Visitor for FnCounter {
+//!     fn visit_function(&mut self, name: &Token, params: &[Token], body: &Stmt, sig: &Signature) {
+//!         self.0 += 1;
+//!         walk_function(self, name, params, body, sig);
+//!     }
+//! }
+//! ```
+use super::{statements::Signature, Expr, Pattern, Stmt};
+use crate::token::Token;
+
+pub(crate) trait Visitor: Sized {
+    /// Entry point for statements, dispatches to the hook of the variant, if it has one.
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        walk_stmt(self, stmt)
+    }
+
+    /// Entry point for expressions, dispatches to the hook of the variant, if it has one.
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr)
+    }
+
+    fn visit_program(&mut self, statements: &[Stmt]) {
+        for stmt in statements {
+            self.visit_stmt(stmt);
+        }
+    }
+
+    // Statements
+
+    fn visit_return(&mut self, _keyword: &Token, value: &Expr) {
+        self.visit_expr(value)
+    }
+
+    fn visit_function(&mut self, name: &Token, params: &[Token], body: &Stmt, sig: &Signature) {
+        walk_function(self, name, params, body, sig)
+    }
+
+    fn visit_class(
+        &mut self,
+        _name: &Token,
+        superclass: Option<&Expr>,
+        methods: &[Stmt],
+        static_methods: &[Stmt],
+    ) {
+        if let Some(superclass) = superclass {
+            self.visit_expr(superclass);
+        }
+        self.visit_program(methods);
+        self.visit_program(static_methods);
+    }
+
+    fn visit_enum(&mut self, _name: &Token, _members: &[Token]) {}
+
+    fn visit_var_decl(
+        &mut self,
+        _names: &[Token],
+        initializers: &[Option<Expr>],
+        _types: &[Option<Token>],
+    ) {
+        for initializer in initializers.iter().flatten() {
+            self.visit_expr(initializer);
+        }
+    }
+
+    fn visit_var_default(&mut self, _name: &Token, initializer: &Expr, _ty: Option<&Token>) {
+        self.visit_expr(initializer)
+    }
+
+    fn visit_const(&mut self, _name: &Token, initializer: &Expr) {
+        self.visit_expr(initializer)
+    }
+
+    fn visit_destructure(&mut self, _pattern: &Pattern, initializer: &Expr) {
+        self.visit_expr(initializer)
+    }
+
+    fn visit_for_each(&mut self, _name: &Token, iterable: &Expr, body: &Stmt) {
+        self.visit_expr(iterable);
+        self.visit_stmt(body);
+    }
+
+    fn visit_block(&mut self, statements: &[Stmt]) {
+        self.visit_program(statements)
+    }
+
+    fn visit_with(&mut self, _keyword: &Token, resource: &Expr, _name: &Token, body: &Stmt) {
+        self.visit_expr(resource);
+        self.visit_stmt(body);
+    }
+
+    fn visit_static(&mut self, _name: &Token, initializer: &Expr) {
+        self.visit_expr(initializer)
+    }
+
+    // Expressions
+
+    fn visit_assign(&mut self, _name: &Token, value: &Expr) {
+        self.visit_expr(value)
+    }
+
+    fn visit_call(&mut self, callee: &Expr, _paren: &Token, args: &[Expr]) {
+        self.visit_expr(callee);
+        for arg in args {
+            self.visit_expr(arg);
+        }
+    }
+
+    fn visit_index_assign(&mut self, array: &Expr, idx: &Expr, value: &Expr) {
+        self.visit_expr(array);
+        self.visit_expr(idx);
+        self.visit_expr(value);
+    }
+
+    fn visit_compound(&mut self, target: &Expr, _op: &Token, value: &Expr) {
+        self.visit_expr(target);
+        self.visit_expr(value);
+    }
+}
+
+/// Dispatches `stmt` to the matching hook of `visitor`, visiting the children of the
+/// statements without one.
+pub(crate) fn walk_stmt<V: Visitor>(visitor: &mut V, stmt: &Stmt) {
+    match stmt {
+        Stmt::Expression(expr) | Stmt::Print(expr) => visitor.visit_expr(expr),
+        Stmt::Assert(_, condition, _) => visitor.visit_expr(condition),
+        Stmt::Return(kw, val) => visitor.visit_return(kw, val),
+        Stmt::If(condition, then_branch, else_branch) => {
+            visitor.visit_expr(condition);
+            visitor.visit_stmt(then_branch);
+            if let Some(stmt) = else_branch {
+                visitor.visit_stmt(stmt);
+            }
+        }
+        Stmt::Function(name, params, body, sig) => visitor.visit_function(name, params, body, sig),
+        Stmt::Class(name, superclass, methods, static_methods) => {
+            visitor.visit_class(name, superclass.as_deref(), methods, static_methods)
+        }
+        Stmt::Enum(name, members) => visitor.visit_enum(name, members),
+        Stmt::Variable(names, initializers, types) => {
+            visitor.visit_var_decl(names, initializers, types)
+        }
+        Stmt::VariableDefault(name, initializer, ty) => {
+            visitor.visit_var_default(name, initializer, ty.as_ref())
+        }
+        Stmt::Const(name, initializer) => visitor.visit_const(name, initializer),
+        Stmt::Destructure(pattern, initializer) => visitor.visit_destructure(pattern, initializer),
+        Stmt::While(condition, body, increment) => {
+            visitor.visit_expr(condition);
+            visitor.visit_stmt(body);
+            if let Some(increment) = increment {
+                visitor.visit_expr(increment);
+            }
+        }
+        Stmt::ForEach(name, iterable, body) => visitor.visit_for_each(name, iterable, body),
+        Stmt::Block(statements) => visitor.visit_block(statements),
+        Stmt::Labeled(_, body) | Stmt::Defer(_, body) => visitor.visit_stmt(body),
+        Stmt::Break(_, _, value) => {
+            if let Some(value) = value {
+                visitor.visit_expr(value);
+            }
+        }
+        Stmt::Continue(_) => (),
+        Stmt::With(kw, resource, name, body) => visitor.visit_with(kw, resource, name, body),
+        Stmt::Static(name, initializer, _) => visitor.visit_static(name, initializer),
+    }
+}
+
+/// Dispatches `expr` to the matching hook of `visitor`, visiting the children of the
+/// expressions without one.
+pub(crate) fn walk_expr<V: Visitor>(visitor: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Binary(lhs, _, rhs) | Expr::Logical(lhs, _, rhs) => {
+            visitor.visit_expr(lhs);
+            visitor.visit_expr(rhs);
+        }
+        Expr::Index(lhs, rhs) | Expr::Range(lhs, rhs) => {
+            visitor.visit_expr(lhs);
+            visitor.visit_expr(rhs);
+        }
+        Expr::Conditional(condition, then, otherwise) => {
+            visitor.visit_expr(condition);
+            visitor.visit_expr(then);
+            visitor.visit_expr(otherwise);
+        }
+        Expr::Unary(_, rhs) | Expr::Get(rhs, _) => visitor.visit_expr(rhs),
+        Expr::Spread(_, array) | Expr::Concat(array) => visitor.visit_expr(array),
+        Expr::Assign(name, value) => visitor.visit_assign(name, value),
+        Expr::Call(callee, paren, args) => visitor.visit_call(callee, paren, args),
+        Expr::Set(object, _, value) => {
+            visitor.visit_expr(object);
+            visitor.visit_expr(value);
+        }
+        Expr::Array(_, values) => {
+            for value in values {
+                visitor.visit_expr(value);
+            }
+        }
+        Expr::IndexAssign(array, idx, value) => visitor.visit_index_assign(array, idx, value),
+        Expr::Compound(target, op, value) => visitor.visit_compound(target, op, value),
+        Expr::Loop(_, body) => visitor.visit_stmt(&body.0),
+        Expr::Literal(_) | Expr::Super(..) | Expr::This(_) | Expr::Variable(_) => (),
+    }
+}
+
+/// In-place traversal over the syntax tree, used by rewriting passes.
+///
+/// The default hooks walk every child of the node, overriding them and calling
+/// [`walk_stmt_mut`] or [`walk_expr_mut`] keeps the traversal going.
+pub(crate) trait VisitorMut: Sized {
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt)
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr)
+    }
+
+    fn visit_program_mut(&mut self, statements: &mut [Stmt]) {
+        for stmt in statements {
+            self.visit_stmt_mut(stmt);
+        }
+    }
+}
+
+/// Visits the children of `stmt`.
+pub(crate) fn walk_stmt_mut<V: VisitorMut>(visitor: &mut V, stmt: &mut Stmt) {
+    match stmt {
+        Stmt::Expression(expr) | Stmt::Print(expr) | Stmt::Return(_, expr) => {
+            visitor.visit_expr_mut(expr)
+        }
+        Stmt::Assert(_, expr, _) => visitor.visit_expr_mut(expr),
+        Stmt::If(condition, then_branch, else_branch) => {
+            visitor.visit_expr_mut(condition);
+            visitor.visit_stmt_mut(then_branch);
+            if let Some(stmt) = else_branch {
+                visitor.visit_stmt_mut(stmt);
+            }
+        }
+        Stmt::Function(_, _, body, _) => visitor.visit_stmt_mut(body),
+        Stmt::Class(_, superclass, methods, static_methods) => {
+            if let Some(superclass) = superclass {
+                visitor.visit_expr_mut(superclass);
+            }
+            visitor.visit_program_mut(methods);
+            visitor.visit_program_mut(static_methods);
+        }
+        Stmt::Variable(_, initializers, _) => {
+            for initializer in initializers.iter_mut().flatten() {
+                visitor.visit_expr_mut(initializer);
+            }
+        }
+        Stmt::VariableDefault(_, initializer, _) | Stmt::Static(_, initializer, _) => {
+            visitor.visit_expr_mut(initializer)
+        }
+        Stmt::Const(_, initializer) | Stmt::Destructure(_, initializer) => {
+            visitor.visit_expr_mut(initializer)
+        }
+        Stmt::While(condition, body, increment) => {
+            visitor.visit_expr_mut(condition);
+            visitor.visit_stmt_mut(body);
+            if let Some(increment) = increment {
+                visitor.visit_expr_mut(increment);
+            }
+        }
+        Stmt::Block(statements) => visitor.visit_program_mut(statements),
+        Stmt::Labeled(_, body) | Stmt::Defer(_, body) => visitor.visit_stmt_mut(body),
+        Stmt::Break(_, _, value) => {
+            if let Some(value) = value {
+                visitor.visit_expr_mut(value);
+            }
+        }
+        Stmt::Enum(..) | Stmt::Continue(_) => (),
+        Stmt::With(_, resource, _, body) => {
+            visitor.visit_expr_mut(resource);
+            visitor.visit_stmt_mut(body);
+        }
+        Stmt::ForEach(_, iterable, body) => {
+            visitor.visit_expr_mut(iterable);
+            visitor.visit_stmt_mut(body);
+        }
+    }
+}
+
+/// Visits the children of `expr`.
+pub(crate) fn walk_expr_mut<V: VisitorMut>(visitor: &mut V, expr: &mut Expr) {
+    match expr {
+        Expr::Binary(lhs, _, rhs) | Expr::Logical(lhs, _, rhs) => {
+            visitor.visit_expr_mut(lhs);
+            visitor.visit_expr_mut(rhs);
+        }
+        Expr::Conditional(condition, then, otherwise) => {
+            visitor.visit_expr_mut(condition);
+            visitor.visit_expr_mut(then);
+            visitor.visit_expr_mut(otherwise);
+        }
+        Expr::Unary(_, rhs) => visitor.visit_expr_mut(rhs),
+        Expr::Assign(_, value) => visitor.visit_expr_mut(value),
+        Expr::Call(callee, _, args) => {
+            visitor.visit_expr_mut(callee);
+            for arg in args {
+                visitor.visit_expr_mut(arg);
+            }
+        }
+        Expr::Get(object, _) => visitor.visit_expr_mut(object),
+        Expr::Set(object, _, value) => {
+            visitor.visit_expr_mut(object);
+            visitor.visit_expr_mut(value);
+        }
+        Expr::Array(_, values) => {
+            for value in values {
+                visitor.visit_expr_mut(value);
+            }
+        }
+        Expr::Index(lhs, rhs) | Expr::Range(lhs, rhs) | Expr::Compound(lhs, _, rhs) => {
+            visitor.visit_expr_mut(lhs);
+            visitor.visit_expr_mut(rhs);
+        }
+        Expr::IndexAssign(array, idx, value) => {
+            visitor.visit_expr_mut(array);
+            visitor.visit_expr_mut(idx);
+            visitor.visit_expr_mut(value);
+        }
+        Expr::Spread(_, array) | Expr::Concat(array) => visitor.visit_expr_mut(array),
+        Expr::Loop(_, body) => visitor.visit_stmt_mut(&mut body.0),
+        Expr::Literal(_) | Expr::Super(..) | Expr::This(_) | Expr::Variable(_) => (),
+    }
+}
+
+/// Default behaviour of [`Visitor::visit_function`], visits the function body.
+pub(crate) fn walk_function<V: Visitor>(
+    visitor: &mut V,
+    _name: &Token,
+    _params: &[Token],
+    body: &Stmt,
+    _sig: &Signature,
+) {
+    visitor.visit_stmt(body)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{lexer::Lexer, parser::Parser};
+
+    #[derive(Default)]
+    struct FnCounter(usize);
+
+    impl Visitor for FnCounter {
+        fn visit_function(&mut self, name: &Token, params: &[Token], body: &Stmt, sig: &Signature) {
+            self.0 += 1;
+            walk_function(self, name, params, body, sig);
+        }
+    }
+
+    #[test]
+    fn counts_function_declarations() {
+        let src = r#"
+            fn outer() {
+                fn inner() {}
+                return inner;
+            }
+            class Foo {
+                bar() {}
+                static baz() {}
+            }
+            let x = 1;
+        "#;
+        let tokens = Lexer::new(src).scan_tokens().unwrap();
+        let stmts = Parser::new(&tokens).parse().unwrap();
+
+        let mut counter = FnCounter::default();
+        counter.visit_program(&stmts);
+        assert_eq!(counter.0, 4);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::lib::LoxResult;
+use crate::LoxResult;
 use std::{iter::Peekable, num::NonZeroU32, str::Chars};
 
 #[derive(Debug, Hash, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -44,7 +44,7 @@ impl Span {
     /// Creates a new Span from two positions.
     #[inline]
     pub fn new(start: Position, end: Position) -> Self {
-        if start < end {
+        if start > end {
             Self {
                 start: end,
                 end: start,
@@ -129,10 +129,32 @@ impl<'a> Cursor<'a> {
         }
     }
 
+    /// Like [`consume_until`](Self::consume_until), but returns what was consumed before `ch`.
+    pub fn take_until(&mut self, ch: char) -> String {
+        let mut buf = String::new();
+        for t in &mut self.iter {
+            if t == ch {
+                break;
+            }
+            buf.push(t);
+        }
+        buf
+    }
+
     pub fn peek_next(&mut self) -> Option<char> {
         self.iter.peek().copied()
     }
 
+    /// Peeks the char after [`peek_next`](Self::peek_next)'s.
+    pub fn peek_second(&self) -> Option<char> {
+        self.iter.clone().nth(1)
+    }
+
+    /// Peeks the `n`th char ahead, [`peek_next`](Self::peek_next)'s being the 0th.
+    pub fn peek_nth(&self, n: usize) -> Option<char> {
+        self.iter.clone().nth(n)
+    }
+
     pub fn take_char_while(
         &mut self,
         start: char,
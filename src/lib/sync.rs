@@ -0,0 +1,76 @@
+//! Shared ownership and interior mutability of the values of a program.
+//!
+//! By default these are the [`std::rc::Rc`] and [`std::cell::RefCell`] of the standard
+//! library, so environments and values are confined to the thread that created them. With the
+//! `sync` feature they're replaced by [`Arc`] and a [`RwLock`] with the interface of a
+//! `RefCell`, making them `Send` and `Sync`, so a single environment can be shared between
+//! threads.
+
+#[cfg(not(feature = "sync"))]
+pub(crate) use std::{cell::RefCell, rc::Rc};
+
+#[cfg(feature = "sync")]
+pub(crate) use std::sync::Arc as Rc;
+#[cfg(feature = "sync")]
+use std::{
+    fmt,
+    sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+/// Bounds of everything held by a [`LoxValue`](super::interpreter::LoxValue), so values
+/// can be shared between threads with the `sync` feature. Without it there are none.
+#[cfg(feature = "sync")]
+pub trait Shared: Send + Sync {}
+
+#[cfg(feature = "sync")]
+impl<T: Send + Sync + ?Sized> Shared for T {}
+
+/// Bounds of everything held by a [`LoxValue`](super::interpreter::LoxValue), so values
+/// can be shared between threads with the `sync` feature. Without it there are none.
+#[cfg(not(feature = "sync"))]
+pub trait Shared {}
+
+#[cfg(not(feature = "sync"))]
+impl<T: ?Sized> Shared for T {}
+
+/// A [`RwLock`] borrowed like a [`std::cell::RefCell`].
+///
+/// As with a `RefCell`, a panic while the value is borrowed doesn't make it unusable
+/// afterwards.
+#[cfg(feature = "sync")]
+#[derive(Default)]
+pub struct RefCell<T: ?Sized>(RwLock<T>);
+
+#[cfg(feature = "sync")]
+impl<T> RefCell<T> {
+    pub fn new(value: T) -> Self {
+        Self(RwLock::new(value))
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<T: ?Sized> RefCell<T> {
+    pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+        self.0.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn borrow_mut(&self) -> RwLockWriteGuard<'_, T> {
+        self.0.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<T: Clone> Clone for RefCell<T> {
+    fn clone(&self) -> Self {
+        Self::new(self.borrow().clone())
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<T: fmt::Debug + ?Sized> fmt::Debug for RefCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RefCell")
+            .field("value", &&*self.borrow())
+            .finish()
+    }
+}
@@ -0,0 +1,574 @@
+//! Best-effort static type checker over the optional type annotations.
+//!
+//! Only the builtin type names are checked (`number`, `string`, `bool`, `nil`, `array`, `fn`),
+//! anything else (class names, `any`...) is accepted as is. Expressions whose type can't be
+//! known ahead of time are never reported, so the checker only flags obvious mismatches.
+//! Calls to functions declared in scope are also checked to pass as many arguments as they
+//! have parameters, unless an argument is spread. Since globals are late-bound, functions that
+//! are assigned to anywhere, or declared again in their scope, are left to the runtime.
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    error::{InnerError, LoxError},
+    interpreter::LoxValue,
+    parser::{visitor::Visitor, Expr, Pattern, Signature, Stmt},
+    position::Span,
+    token::{Keyword, Punctuator, Token, TokenKind},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Type {
+    Number,
+    String,
+    Bool,
+    Nil,
+    Array,
+    Function,
+    Unknown,
+}
+
+impl Type {
+    pub fn from_annotation(annotation: &Option<Token>) -> Self {
+        match annotation.as_ref().map(Token::to_string).as_deref() {
+            Some("number") => Type::Number,
+            Some("string") => Type::String,
+            Some("bool") => Type::Bool,
+            Some("nil") => Type::Nil,
+            Some("array") => Type::Array,
+            Some("fn") => Type::Function,
+            _ => Type::Unknown,
+        }
+    }
+
+    /// Type of a runtime value, instances are `Unknown` as classes aren't checked.
+    pub fn of(val: &LoxValue) -> Self {
+        match val {
+            LoxValue::Integer(_) | LoxValue::Decimal(_) => Type::Number,
+            LoxValue::String(_) => Type::String,
+            LoxValue::Boolean(_) => Type::Bool,
+            LoxValue::Nil => Type::Nil,
+            LoxValue::Array(_) => Type::Array,
+            LoxValue::Callable(_) => Type::Function,
+            LoxValue::Instance(_)
+            | LoxValue::Variant(_)
+            | LoxValue::Enum(_)
+            | LoxValue::Range(..) => Type::Unknown,
+        }
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Type::Number => "number",
+                Type::String => "string",
+                Type::Bool => "bool",
+                Type::Nil => "nil",
+                Type::Array => "array",
+                Type::Function => "fn",
+                Type::Unknown => "unknown",
+            }
+        )
+    }
+}
+
+#[derive(Clone)]
+enum Binding {
+    Variable(Type),
+    Function(Vec<Type>, Type),
+}
+
+/// Finds the functions whose signature can't be relied on where they are called.
+struct Rebound {
+    /// Names assigned to anywhere, which could be any of their declarations
+    assigned: HashSet<String>,
+    /// Times each name is declared in each scope, with the spans of the functions among them
+    scopes: Vec<HashMap<String, (usize, Vec<Span>)>>,
+    /// Spans of the names of functions declared more than once in their scope
+    redeclared: HashSet<Span>,
+}
+
+impl Rebound {
+    fn find(statements: &[Stmt]) -> Self {
+        let mut rebound = Self {
+            assigned: HashSet::new(),
+            scopes: vec![HashMap::new()],
+            redeclared: HashSet::new(),
+        };
+        rebound.visit_program(statements);
+        rebound.end_scope();
+        rebound
+    }
+
+    /// Whether the function declared as `name` may not be the one called by that name.
+    fn contains(&self, name: &Token) -> bool {
+        self.assigned.contains(&name.to_string()) || self.redeclared.contains(name.span())
+    }
+
+    fn declare(&mut self, name: &Token, function: bool) {
+        let (count, functions) = self
+            .scopes
+            .last_mut()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default();
+        *count += 1;
+        if function {
+            functions.push(*name.span());
+        }
+    }
+
+    fn end_scope(&mut self) {
+        for (count, functions) in self.scopes.pop().unwrap().into_values() {
+            if count > 1 {
+                self.redeclared.extend(functions);
+            }
+        }
+    }
+
+    /// Visits `body` in a new scope holding `names`.
+    fn scoped(&mut self, names: &[Token], body: &Stmt) {
+        self.scopes.push(HashMap::new());
+        names.iter().for_each(|name| self.declare(name, false));
+        self.visit_stmt(body);
+        self.end_scope();
+    }
+}
+
+impl Visitor for Rebound {
+    fn visit_block(&mut self, statements: &[Stmt]) {
+        self.scopes.push(HashMap::new());
+        self.visit_program(statements);
+        self.end_scope();
+    }
+
+    fn visit_function(&mut self, name: &Token, params: &[Token], body: &Stmt, _sig: &Signature) {
+        self.declare(name, true);
+        self.scoped(params, body);
+    }
+
+    fn visit_class(
+        &mut self,
+        name: &Token,
+        superclass: Option<&Expr>,
+        methods: &[Stmt],
+        static_methods: &[Stmt],
+    ) {
+        if let Some(superclass) = superclass {
+            self.visit_expr(superclass);
+        }
+        self.declare(name, false);
+        // Methods aren't bound in the enclosing scope
+        for method in methods.iter().chain(static_methods) {
+            if let Stmt::Function(_, params, body, _) = method {
+                self.scoped(params, body);
+            }
+        }
+    }
+
+    fn visit_enum(&mut self, name: &Token, _members: &[Token]) {
+        self.declare(name, false);
+    }
+
+    fn visit_var_decl(
+        &mut self,
+        names: &[Token],
+        initializers: &[Option<Expr>],
+        _types: &[Option<Token>],
+    ) {
+        initializers
+            .iter()
+            .flatten()
+            .for_each(|initializer| self.visit_expr(initializer));
+        names.iter().for_each(|name| self.declare(name, false));
+    }
+
+    fn visit_var_default(&mut self, name: &Token, initializer: &Expr, _ty: Option<&Token>) {
+        self.visit_expr(initializer);
+        self.declare(name, false);
+    }
+
+    fn visit_const(&mut self, name: &Token, initializer: &Expr) {
+        self.visit_expr(initializer);
+        self.declare(name, false);
+    }
+
+    fn visit_static(&mut self, name: &Token, initializer: &Expr) {
+        self.visit_expr(initializer);
+        self.declare(name, false);
+    }
+
+    fn visit_destructure(&mut self, pattern: &Pattern, initializer: &Expr) {
+        self.visit_expr(initializer);
+        pattern
+            .names()
+            .into_iter()
+            .for_each(|name| self.declare(name, false));
+    }
+
+    fn visit_with(&mut self, _keyword: &Token, resource: &Expr, name: &Token, body: &Stmt) {
+        self.visit_expr(resource);
+        self.scoped(std::slice::from_ref(name), body);
+    }
+
+    fn visit_for_each(&mut self, name: &Token, iterable: &Expr, body: &Stmt) {
+        self.visit_expr(iterable);
+        self.scoped(std::slice::from_ref(name), body);
+    }
+
+    fn visit_assign(&mut self, name: &Token, value: &Expr) {
+        self.assigned.insert(name.to_string());
+        self.visit_expr(value);
+    }
+}
+
+pub(crate) struct TypeChecker {
+    scopes: Vec<HashMap<String, Binding>>,
+    /// Functions that are only checked at runtime
+    rebound: Rebound,
+    /// Declared return types of the functions being checked, innermost last
+    returns: Vec<Type>,
+    errors: Vec<LoxError>,
+}
+
+impl TypeChecker {
+    /// Checks a program, returning every mismatch found.
+    pub fn check(statements: &[Stmt]) -> Result<(), Vec<LoxError>> {
+        let mut checker = Self {
+            scopes: vec![HashMap::new()],
+            rebound: Rebound::find(statements),
+            returns: Vec::new(),
+            errors: Vec::new(),
+        };
+        checker.visit_program(statements);
+
+        if !checker.errors.is_empty() {
+            return Err(checker.errors);
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, name: &Token, binding: Binding) {
+        // There's always at least the global scope
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(name.to_string(), binding);
+    }
+
+    fn lookup(&self, name: &Token) -> Option<&Binding> {
+        let name = name.to_string();
+        self.scopes.iter().rev().find_map(|scope| scope.get(&name))
+    }
+
+    /// Reports an error if `expr` is known to have a type other than `expected`.
+    fn expect(&mut self, expected: Type, expr: &Expr) {
+        let found = self.infer(expr);
+        if expected != Type::Unknown && found != Type::Unknown && expected != found {
+            self.errors.push(
+                InnerError::new(
+                    expr.position(),
+                    &format!(
+                        "mismatched types: expected `{}`, found `{}`",
+                        expected, found
+                    ),
+                )
+                .into(),
+            );
+        }
+    }
+
+    /// Statically infers the type of an expression.
+    fn infer(&self, expr: &Expr) -> Type {
+        match expr {
+            Expr::Literal(tk) => match tk.kind() {
+                TokenKind::NumericLiteral(_) => Type::Number,
+                TokenKind::StringLiteral(_) => Type::String,
+                TokenKind::BooleanLiteral(_) => Type::Bool,
+                TokenKind::Keyword(Keyword::Nil) => Type::Nil,
+                _ => Type::Unknown,
+            },
+            Expr::Assign(_, value) | Expr::Concat(value) => self.infer(value),
+            Expr::Array(..) => Type::Array,
+            Expr::Variable(name) => match self.lookup(name) {
+                Some(Binding::Variable(ty)) => *ty,
+                Some(Binding::Function(..)) => Type::Function,
+                None => Type::Unknown,
+            },
+            Expr::Call(callee, ..) => match &**callee {
+                Expr::Variable(name) => match self.lookup(name) {
+                    Some(Binding::Function(_, ret)) => *ret,
+                    _ => Type::Unknown,
+                },
+                _ => Type::Unknown,
+            },
+            Expr::Unary(op, rhs) => match op.kind() {
+                TokenKind::Punctuator(Punctuator::Not) => Type::Bool,
+                _ if self.infer(rhs) == Type::Number => Type::Number,
+                _ => Type::Unknown,
+            },
+            Expr::Binary(lhs, op, rhs) => {
+                use Punctuator::*;
+                let (lhs, rhs) = (self.infer(lhs), self.infer(rhs));
+                match op.kind() {
+                    TokenKind::Punctuator(
+                        Eq | NotEq | GreaterThan | GreaterThanOrEq | LessThan | LessThanOrEq,
+                    ) => Type::Bool,
+                    TokenKind::Punctuator(Add) if lhs == Type::String || rhs == Type::String => {
+                        Type::String
+                    }
+                    _ if lhs == Type::Number && rhs == Type::Number => Type::Number,
+                    _ => Type::Unknown,
+                }
+            }
+            Expr::Logical(lhs, _, rhs) | Expr::Conditional(_, lhs, rhs) => {
+                match (self.infer(lhs), self.infer(rhs)) {
+                    (lhs, rhs) if lhs == rhs => lhs,
+                    _ => Type::Unknown,
+                }
+            }
+            _ => Type::Unknown,
+        }
+    }
+
+    /// Checks a function body against its signature.
+    fn function_body(&mut self, params: &[Token], body: &Stmt, sig: &Signature) {
+        self.scopes.push(HashMap::new());
+        for (param, annotation) in params.iter().zip(&sig.params) {
+            self.define(param, Binding::Variable(Type::from_annotation(annotation)));
+        }
+        self.returns.push(Type::from_annotation(&sig.ret));
+        self.visit_stmt(body);
+        self.returns.pop();
+        self.scopes.pop();
+    }
+}
+
+impl Visitor for TypeChecker {
+    fn visit_block(&mut self, statements: &[Stmt]) {
+        self.scopes.push(HashMap::new());
+        self.visit_program(statements);
+        self.scopes.pop();
+    }
+
+    fn visit_var_decl(
+        &mut self,
+        names: &[Token],
+        initializers: &[Option<Expr>],
+        types: &[Option<Token>],
+    ) {
+        for ((name, initializer), annotation) in names.iter().zip(initializers).zip(types) {
+            let ty = Type::from_annotation(annotation);
+            if let Some(initializer) = initializer {
+                self.visit_expr(initializer);
+                self.expect(ty, initializer);
+            }
+            self.define(name, Binding::Variable(ty));
+        }
+    }
+
+    fn visit_var_default(&mut self, name: &Token, initializer: &Expr, ty: Option<&Token>) {
+        let ty = Type::from_annotation(&ty.cloned());
+        self.visit_expr(initializer);
+        self.expect(ty, initializer);
+        self.define(name, Binding::Variable(ty));
+    }
+
+    fn visit_enum(&mut self, name: &Token, _members: &[Token]) {
+        self.define(name, Binding::Variable(Type::Unknown));
+    }
+
+    fn visit_const(&mut self, name: &Token, initializer: &Expr) {
+        self.visit_expr(initializer);
+        let ty = self.infer(initializer);
+        self.define(name, Binding::Variable(ty));
+    }
+
+    fn visit_static(&mut self, name: &Token, initializer: &Expr) {
+        self.visit_expr(initializer);
+        self.define(name, Binding::Variable(Type::Unknown));
+    }
+
+    fn visit_with(&mut self, _keyword: &Token, resource: &Expr, name: &Token, body: &Stmt) {
+        self.visit_expr(resource);
+        self.scopes.push(HashMap::new());
+        self.define(name, Binding::Variable(Type::Unknown));
+        self.visit_stmt(body);
+        self.scopes.pop();
+    }
+
+    fn visit_destructure(&mut self, pattern: &Pattern, initializer: &Expr) {
+        self.visit_expr(initializer);
+        for name in pattern.names() {
+            self.define(name, Binding::Variable(Type::Unknown));
+        }
+    }
+
+    fn visit_assign(&mut self, name: &Token, value: &Expr) {
+        self.visit_expr(value);
+        if let Some(Binding::Variable(ty)) = self.lookup(name) {
+            self.expect(*ty, value);
+        }
+    }
+
+    fn visit_return(&mut self, _keyword: &Token, value: &Expr) {
+        self.visit_expr(value);
+        if let Some(ty) = self.returns.last() {
+            self.expect(*ty, value);
+        }
+    }
+
+    fn visit_call(&mut self, callee: &Expr, paren: &Token, args: &[Expr]) {
+        self.visit_expr(callee);
+        for arg in args {
+            self.visit_expr(arg);
+        }
+
+        if let Expr::Variable(name) = callee {
+            if let Some(Binding::Function(params, _)) = self.lookup(name).cloned() {
+                // The length of a spread is only known at runtime, which checks the arity then
+                let spreads = args.iter().any(|arg| matches!(arg, Expr::Spread(..)));
+                if !spreads && params.len() != args.len() {
+                    self.errors.push(
+                        InnerError::new(
+                            Span::new(callee.position().start(), paren.span().end()),
+                            &format!("expected {} arguments but got {}", params.len(), args.len()),
+                        )
+                        .into(),
+                    );
+                }
+                // Arguments after a spread can't be matched to their parameter
+                let args = args
+                    .iter()
+                    .take_while(|arg| !matches!(arg, Expr::Spread(..)));
+                for (ty, arg) in params.into_iter().zip(args) {
+                    self.expect(ty, arg);
+                }
+            }
+        }
+    }
+
+    fn visit_function(&mut self, name: &Token, params: &[Token], body: &Stmt, sig: &Signature) {
+        // The function may be replaced by any other, so its signature doesn't hold
+        let binding = if self.rebound.contains(name) {
+            Binding::Variable(Type::Function)
+        } else {
+            Binding::Function(
+                sig.params.iter().map(Type::from_annotation).collect(),
+                Type::from_annotation(&sig.ret),
+            )
+        };
+        self.define(name, binding);
+        self.function_body(params, body, sig);
+    }
+
+    fn visit_class(
+        &mut self,
+        name: &Token,
+        superclass: Option<&Expr>,
+        methods: &[Stmt],
+        static_methods: &[Stmt],
+    ) {
+        if let Some(superclass) = superclass {
+            self.visit_expr(superclass);
+        }
+        self.define(name, Binding::Variable(Type::Unknown));
+        // Methods aren't bound in the enclosing scope, so only their bodies are checked
+        for method in methods.iter().chain(static_methods) {
+            if let Stmt::Function(_, params, body, sig) = method {
+                self.function_body(params, body, sig);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{lexer::Lexer, parser::Parser};
+
+    fn check(src: &str) -> Result<(), Vec<String>> {
+        let tokens = Lexer::new(src).scan_tokens().unwrap();
+        let statements = Parser::new(&tokens).parse().unwrap();
+        TypeChecker::check(&statements)
+            .map_err(|errors| errors.iter().map(|e| e.to_string()).collect())
+    }
+
+    #[test]
+    fn accepts_well_typed_programs() {
+        let src = r#"
+            let greeting: string = "hello";
+            let n: number = 1 + 2 * 3;
+            fn shout(s: string, times: number): string {
+                return s * times;
+            }
+            let loud: string = shout(greeting, n);
+            let unknown = shout;
+            n = unknown(1, 2);
+            class Foo {
+                bar(x: number): bool { return x > 1; }
+            }
+        "#;
+        assert_eq!(check(src), Ok(()));
+    }
+
+    #[test]
+    fn reports_mismatches() {
+        let src = r#"
+            let n: number = "one";
+            n = true;
+            fn name(): string { return 1; }
+            fn double(x: number): number { return x * 2; }
+            double("two");
+            let s: string = double(2);
+        "#;
+        let expected = "mismatched types: expected `number`, found `string`";
+        assert_eq!(
+            check(src),
+            Err(vec![
+                expected.to_string(),
+                "mismatched types: expected `number`, found `bool`".to_string(),
+                "mismatched types: expected `string`, found `number`".to_string(),
+                expected.to_string(),
+                "mismatched types: expected `string`, found `number`".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn checks_arity_unless_a_spread_is_involved() {
+        let src = "
+            fn pair(a, b) {}
+            pair(1);
+            pair(1, 2, 3);
+            pair(...[1], 2);
+            pair(...[1, 2, 3]);
+        ";
+        assert_eq!(
+            check(src),
+            Err(vec![
+                "expected 2 arguments but got 1".to_string(),
+                "expected 2 arguments but got 3".to_string(),
+            ])
+        );
+        // Once reassigned, the function could be any other
+        assert_eq!(check("fn f(a) {} f = clock; f();"), Ok(()));
+        // Even after the call, as globals are late-bound
+        assert_eq!(
+            check("fn g(a, b) {} fn k() { return g(); } g = clock; print k();"),
+            Ok(())
+        );
+        assert_eq!(
+            check("fn g(a, b) {} fn k() { return g(1); } fn g(a) { return a; } print k();"),
+            Ok(())
+        );
+        // Shadowing in another scope is no redeclaration
+        assert_eq!(
+            check("fn g(a, b) {} { fn g() {} g(); } g();"),
+            Err(vec!["expected 2 arguments but got 0".to_string()])
+        );
+    }
+}
@@ -1,6 +1,4 @@
-#![feature(result_cloned)]
-mod lib;
-use lib::Lox;
+use jlox::{Config, Lox};
 use std::path::PathBuf;
 use structopt::StructOpt;
 
@@ -8,15 +6,67 @@ use structopt::StructOpt;
 struct Options {
     #[structopt(parse(from_os_str), help = "Script file to be interpreted (*.lox)")]
     file: Option<PathBuf>,
+    #[structopt(long, help = "Check type annotations before running")]
+    typecheck: bool,
+    #[structopt(long, help = "Warn about code that is likely a mistake")]
+    strict: bool,
+    #[structopt(
+        long,
+        help = "Read integer literals that overflow as decimals, possibly losing precision"
+    )]
+    lossy_integers: bool,
+    #[structopt(long, help = "Report the lines that were never executed")]
+    lines: bool,
+    #[structopt(long, help = "Skip `assert` statements, as in a release build")]
+    no_asserts: bool,
+    #[structopt(long, help = "List the scopes searched when a variable is not found")]
+    debug_scopes: bool,
+    #[structopt(
+        long,
+        help = "Check returned values against the return type annotations, including the implicit `nil`"
+    )]
+    check_returns: bool,
+    #[structopt(long, help = "Dump the syntax tree of the parsed code to stderr")]
+    debug_parse: bool,
+    #[structopt(
+        long,
+        requires = "file",
+        help = "Print the syntax tree of the script instead of running it"
+    )]
+    dump_ast: bool,
+    #[structopt(
+        long,
+        requires = "file",
+        help = "Print the script formatted, comments included, instead of running it"
+    )]
+    fmt: bool,
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let opt = Options::from_args();
-    if let Err(e) = match opt.file {
-        Some(path) => Lox::do_file(path),
-        None => Lox::do_repl(),
-    } {
-        eprintln!("{}", e);
+    let lox = Lox::new(Config {
+        typecheck: opt.typecheck,
+        strict: opt.strict,
+        lossy_integers: opt.lossy_integers,
+        lines: opt.lines,
+        strip_asserts: opt.no_asserts,
+        debug_scopes: opt.debug_scopes,
+        check_returns: opt.check_returns,
+        debug_parse: opt.debug_parse,
+        dump_ast: opt.dump_ast,
+        fmt: opt.fmt,
+    });
+    let succeeded = match opt.file {
+        Some(path) => lox.do_file(path),
+        None => lox.do_repl().map(|_| true),
+    };
+    match succeeded {
+        Ok(true) => (),
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
     }
 
     Ok(())
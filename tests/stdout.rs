@@ -0,0 +1,88 @@
+//! Runs the interpreter binary on scripts, checking everything it writes to stdout.
+use std::{
+    fs,
+    process::{Command, Output},
+};
+
+/// Stdout of running `src` as a script, which must succeed without writing to stderr.
+fn run(name: &str, src: &str) -> String {
+    run_with(name, &[], src)
+}
+
+/// Like [`run`], passing `args` to the interpreter before the script.
+fn run_with(name: &str, args: &[&str], src: &str) -> String {
+    let out = output(name, args, src);
+    assert!(
+        out.stderr.is_empty(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert!(out.status.success());
+    String::from_utf8(out.stdout).unwrap()
+}
+
+/// Everything the interpreter gives back for running `src` with `args`.
+fn output(name: &str, args: &[&str], src: &str) -> Output {
+    let path = std::env::temp_dir().join(format!("jlox-{}-{}.lox", name, std::process::id()));
+    fs::write(&path, src).unwrap();
+    let out = Command::new(env!("CARGO_BIN_EXE_jlox"))
+        .args(args)
+        .arg(&path)
+        .output()
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+    out
+}
+
+#[test]
+fn parsing_a_group_prints_nothing() {
+    assert_eq!(run("group", "print (1 + 2);"), "3\n");
+}
+
+#[test]
+fn overloaded_operators_in_loop_conditions_run_every_iteration() {
+    let src = "
+        class B { init() { this.v = 0; } __add__(n) { return this.v + n; } }
+        let i = 0;
+        let b = B();
+        while i < b + 3 { i = i + 1; b.v = b.v + 1; if i > 10 { break; } }
+        print i;
+    ";
+    assert_eq!(run("overloaded", src), "11\n");
+}
+
+#[test]
+fn dump_ast_prints_the_tree_without_running() {
+    let src = "print 1 + 2;\nassert false;\n";
+    let expected = "Print [1:7..1:11]\n  Binary `+` [1:7..1:11]\n    Literal `1` [1:7..1:7]\n    Literal `2` [1:11..1:11]\nAssert [2:1..2:6]\n  Literal `false` [2:8..2:12]\n";
+    assert_eq!(run_with("dump", &["--dump-ast"], src), expected);
+
+    let src = "print \"1\" + 1;";
+    let expected = "Print [1:7..1:13]\n  Binary `+` [1:7..1:13]\n    Literal `\"1\"` [1:7..1:9]\n    Literal `1` [1:13..1:13]\n";
+    assert_eq!(run_with("dump-string", &["--dump-ast"], src), expected);
+}
+
+#[test]
+fn fmt_prints_the_script_formatted_without_running_it() {
+    let src = "let x=1; // note\nif (x>0) {\nprint x;}\n";
+    let expected = "let x = 1; // note\nif (x > 0) {\n    print x; }\n";
+    assert_eq!(run_with("fmt", &["--fmt"], src), expected);
+}
+
+#[test]
+fn failing_scripts_exit_with_an_error() {
+    let cases = [
+        ("dump-fail", &["--dump-ast"][..]),
+        ("fmt-fail", &["--fmt"]),
+        ("parse-fail", &[]),
+    ];
+    for (name, args) in cases {
+        let out = output(name, args, "print (1;");
+        assert!(!out.status.success(), "{}", name);
+        assert!(out.stdout.is_empty(), "{}", name);
+        assert!(!out.stderr.is_empty(), "{}", name);
+    }
+    assert!(!output("runtime-fail", &[], "print nil + 1;")
+        .status
+        .success());
+}